@@ -0,0 +1,73 @@
+// Push/pop throughput of lists at large sizes, measured end to end against a
+// server started from the built binary. Run with `cargo bench --bench list`.
+//
+// For every list size the list is first filled with RPUSH, then each command
+// is timed over a fixed number of pipelined calls. Every push is followed by as
+// many pops, so the list stays around the measured size. With head and tail
+// operations in O(1) the rate should barely change as the list grows.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use std::io::Write;
+use std::time::Instant;
+
+use common::{encode, start_server, Client};
+
+const LIST_SIZES: &[usize] = &[10_000, 100_000, 1_000_000];
+const OPERATIONS: usize = 100_000;
+const PIPELINE: usize = 1_000;
+const ELEMENT: &[u8] = b"element-0123456789";
+const COMMANDS: &[&[u8]] = &[b"LPUSH", b"LPOP", b"RPUSH", b"RPOP"];
+
+/// Sends `count` copies of the command in pipelined batches and waits for
+/// every reply.
+fn run(client: &mut Client, args: &[&[u8]], count: usize) {
+    let mut command = Vec::new();
+    encode(&mut command, args);
+    let batch = command.repeat(PIPELINE.min(count));
+    let mut sent = 0;
+    while sent < count {
+        let n = PIPELINE.min(count - sent);
+        client
+            .stream
+            .write_all(&batch[..command.len() * n])
+            .unwrap();
+        for _ in 0..n {
+            let reply = client.read_reply();
+            assert!(!reply.starts_with('-'), "server replied {}", reply);
+        }
+        sent += n;
+    }
+}
+
+fn main() {
+    let server = start_server();
+    let mut client = Client::connect(&server);
+
+    println!("{:>10} {:>8} {:>14}", "list size", "command", "ops/sec");
+    for &size in LIST_SIZES {
+        let key = format!("bench:{}", size).into_bytes();
+        let mut fill: Vec<&[u8]> = vec![b"RPUSH", &key];
+        fill.extend([ELEMENT; PIPELINE]);
+        run(&mut client, &fill, size / PIPELINE);
+
+        for &command in COMMANDS {
+            let args: Vec<&[u8]> = if command.ends_with(b"PUSH") {
+                vec![command, &key, ELEMENT]
+            } else {
+                vec![command, &key]
+            };
+            let started = Instant::now();
+            run(&mut client, &args, OPERATIONS);
+            let rate = OPERATIONS as f64 / started.elapsed().as_secs_f64();
+            println!(
+                "{:>10} {:>8} {:>14.0}",
+                size,
+                String::from_utf8_lossy(command),
+                rate
+            );
+        }
+        run(&mut client, &[b"DEL", &key], 1);
+    }
+}
@@ -0,0 +1,134 @@
+// Memory taken by streams, measured end to end against a server started from
+// the built binary. Run with `cargo bench --bench stream`.
+//
+// For every workload a stream is filled with XADD, and the growth of
+// `total.allocated` in MEMORY STATS is divided by the number of entries. The
+// same entries are then stored here the way the server used to keep them, a
+// map from ID to a vector of field-value pairs, with the allocations counted
+// by a wrapper around the system allocator. Entries sharing their field names
+// should take a fraction of the per-entry layout.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use common::{encode, start_server, Client};
+
+const ENTRIES: usize = 100_000;
+const PIPELINE: usize = 1_000;
+
+type Fields = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// A workload: a name and the field-value pairs of the n-th entry.
+type Workload = (&'static str, fn(usize) -> Fields);
+
+const WORKLOADS: &[Workload] = &[
+    ("same fields, small values", |n| {
+        vec![
+            (b"sensor".to_vec(), format!("{}", n % 16).into_bytes()),
+            (
+                b"temperature".to_vec(),
+                format!("{}", 20 + n % 10).into_bytes(),
+            ),
+        ]
+    }),
+    ("same fields, 100-byte values", |n| {
+        vec![
+            (b"user".to_vec(), format!("user:{}", n % 1000).into_bytes()),
+            (b"payload".to_vec(), format!("{:0>100}", n).into_bytes()),
+        ]
+    }),
+    ("different fields each entry", |n| {
+        vec![(format!("field:{}", n).into_bytes(), b"value".to_vec())]
+    }),
+];
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// `total.allocated` from MEMORY STATS.
+fn allocated(client: &mut Client) -> usize {
+    client.send(&[b"MEMORY", b"STATS"]);
+    let mut line = String::new();
+    client.reader.read_line(&mut line).unwrap();
+    let fields: usize = line[1..].trim_end().parse().unwrap();
+    let mut total = None;
+    for _ in 0..fields / 2 {
+        let name = client.read_reply();
+        let value = client.read_reply();
+        if name == "total.allocated" {
+            total = value[1..].parse().ok();
+        }
+    }
+    total.expect("no total.allocated in MEMORY STATS")
+}
+
+fn main() {
+    let server = start_server();
+    let mut client = Client::connect(&server);
+
+    println!(
+        "{:<30} {:>14} {:>14} {:>8}",
+        "workload", "server B/entry", "per-entry B", "ratio"
+    );
+    for (index, (name, fields_of)) in WORKLOADS.iter().enumerate() {
+        let key = format!("bench:{}", index).into_bytes();
+        let before = allocated(&mut client);
+        let mut sent = 0;
+        while sent < ENTRIES {
+            let mut batch = Vec::new();
+            for n in sent..(sent + PIPELINE).min(ENTRIES) {
+                let fields = fields_of(n);
+                let mut args: Vec<&[u8]> = vec![b"XADD", &key, b"*"];
+                for (field, value) in &fields {
+                    args.extend([field.as_slice(), value.as_slice()]);
+                }
+                encode(&mut batch, &args);
+            }
+            client.stream.write_all(&batch).unwrap();
+            for _ in sent..(sent + PIPELINE).min(ENTRIES) {
+                let reply = client.read_reply();
+                assert!(!reply.starts_with('-'), "server replied {}", reply);
+            }
+            sent += PIPELINE;
+        }
+        let server = allocated(&mut client) - before;
+
+        let before = ALLOCATED.load(Ordering::Relaxed);
+        let per_entry: BTreeMap<(u64, u64), Fields> = (0..ENTRIES)
+            .map(|n| ((n as u64, 0), fields_of(n)))
+            .collect();
+        let baseline = ALLOCATED.load(Ordering::Relaxed) - before;
+        drop(per_entry);
+
+        println!(
+            "{:<30} {:>14} {:>14} {:>7.2}x",
+            name,
+            server / ENTRIES,
+            baseline / ENTRIES,
+            baseline as f64 / server as f64
+        );
+        client.send(&[b"DEL", &key]);
+        client.read_reply();
+    }
+}
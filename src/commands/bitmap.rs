@@ -0,0 +1,564 @@
+use crate::commands::string::{lookup_string, lookup_string_mut, new_string_entry, update_string};
+use crate::protocol;
+use crate::storage::{AppState, DataStoreValue, ValueEntry};
+use std::fmt::Write;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+
+const INT_ERR: &str = "-ERR value is not an integer or out of range\r\n";
+const OFFSET_ERR: &str = "-ERR bit offset is not an integer or out of range\r\n";
+const SYNTAX_ERR: &str = "-ERR syntax error\r\n";
+
+// Strings are capped at 512MB, like Redis.
+const MAX_BIT_OFFSET: u64 = 512 * 1024 * 1024 * 8;
+
+fn parse_bit_offset(arg: &[u8]) -> Option<u64> {
+    protocol::parse_arg::<u64>(arg).filter(|&o| o < MAX_BIT_OFFSET)
+}
+
+fn get_bit(bytes: &[u8], offset: u64) -> u8 {
+    let byte = (offset >> 3) as usize;
+    let bit = 7 - (offset & 7) as u8;
+    bytes.get(byte).map_or(0, |b| (b >> bit) & 1)
+}
+
+fn set_bit(bytes: &mut Vec<u8>, offset: u64, value: u8) {
+    let byte = (offset >> 3) as usize;
+    let bit = 7 - (offset & 7) as u8;
+    if bytes.len() <= byte {
+        bytes.resize(byte + 1, 0);
+    }
+    bytes[byte] = (bytes[byte] & !(1 << bit)) | (value << bit);
+}
+
+/// Resolves a possibly negative `start`/`end` pair against a length, returning
+/// `None` when the range is empty.
+fn normalize_range(mut start: i64, mut end: i64, len: i64) -> Option<(i64, i64)> {
+    if start < 0 {
+        start = (len + start).max(0);
+    }
+    if end < 0 {
+        end = (len + end).max(0);
+    }
+    end = end.min(len - 1);
+    if start > end || len == 0 {
+        None
+    } else {
+        Some((start, end))
+    }
+}
+
+/// Counts the set bits between two bit positions, inclusive.
+fn count_bits(bytes: &[u8], start: u64, end: u64) -> u64 {
+    let first_byte = (start >> 3) as usize;
+    let last_byte = (end >> 3) as usize;
+    let mut count: u64 = bytes[first_byte..=last_byte]
+        .iter()
+        .map(|b| b.count_ones() as u64)
+        .sum();
+
+    // Remove the bits of the edge bytes that fall outside the range.
+    let head_mask = !(0xffu8 >> (start & 7));
+    let tail_mask = 0xffu8.checked_shr((end & 7) as u32 + 1).unwrap_or(0);
+    count -= (bytes[first_byte] & head_mask).count_ones() as u64;
+    count -= (bytes[last_byte] & tail_mask).count_ones() as u64;
+    count
+}
+
+/// Parses the optional `start end [BYTE|BIT]` arguments shared by BITCOUNT
+/// and BITPOS, returning the range as bit positions.
+fn parse_range(args: &[Vec<u8>], bytes: &[u8]) -> Result<Option<(u64, u64)>, &'static str> {
+    let start = match args.first() {
+        Some(s) => protocol::parse_arg::<i64>(s).ok_or(INT_ERR)?,
+        None => 0,
+    };
+    let end = match args.get(1) {
+        Some(e) => protocol::parse_arg::<i64>(e).ok_or(INT_ERR)?,
+        None => -1,
+    };
+    let is_bit = match args.get(2).map(|unit| protocol::to_upper(unit)) {
+        None => false,
+        Some(unit) if unit == "BYTE" => false,
+        Some(unit) if unit == "BIT" => true,
+        Some(_) => return Err(SYNTAX_ERR),
+    };
+    if args.len() > 3 {
+        return Err(SYNTAX_ERR);
+    }
+
+    let len = bytes.len() as i64;
+    if is_bit {
+        Ok(normalize_range(start, end, len * 8).map(|(s, e)| (s as u64, e as u64)))
+    } else {
+        Ok(normalize_range(start, end, len).map(|(s, e)| (s as u64 * 8, e as u64 * 8 + 7)))
+    }
+}
+
+pub async fn handle_setbit<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 3 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'setbit' command\r\n")
+            .await;
+    }
+    let Some(offset) = parse_bit_offset(&args[1]) else {
+        return stream.write_all(OFFSET_ERR.as_bytes()).await;
+    };
+    let value = match args[2].as_slice() {
+        b"0" => 0,
+        b"1" => 1,
+        _ => {
+            return stream
+                .write_all(b"-ERR bit is not an integer or out of range\r\n")
+                .await;
+        }
+    };
+
+    let mut map = state.db().lock().await;
+    let result = update_string(&mut map, &args[0], |bytes| {
+        let previous = get_bit(bytes, offset);
+        set_bit(bytes, offset, value);
+        Ok(previous)
+    });
+    drop(map);
+    let previous = match result {
+        Ok(previous) => previous,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+
+    stream
+        .write_all(format!(":{}\r\n", previous).as_bytes())
+        .await?;
+    let mut command_with_args = vec![b"SETBIT".to_vec()];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await
+}
+
+pub async fn handle_getbit<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 2 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'getbit' command\r\n")
+            .await;
+    }
+    let Some(offset) = parse_bit_offset(&args[1]) else {
+        return stream.write_all(OFFSET_ERR.as_bytes()).await;
+    };
+
+    let mut map = state.db().lock().await;
+    let bit = match lookup_string(&mut map, &args[0]) {
+        Ok(bytes) => bytes.map_or(0, |b| get_bit(b, offset)),
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    stream.write_all(format!(":{}\r\n", bit).as_bytes()).await
+}
+
+pub async fn handle_bitcount<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.is_empty() {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'bitcount' command\r\n")
+            .await;
+    }
+    if args.len() == 2 {
+        return stream.write_all(SYNTAX_ERR.as_bytes()).await;
+    }
+
+    let mut map = state.db().lock().await;
+    let bytes = match lookup_string(&mut map, &args[0]) {
+        Ok(bytes) => bytes.map_or(&[][..], |b| b.as_slice()),
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let range = match parse_range(&args[1..], bytes) {
+        Ok(range) => range,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+
+    let count = range.map_or(0, |(start, end)| count_bits(bytes, start, end));
+    stream.write_all(format!(":{}\r\n", count).as_bytes()).await
+}
+
+pub async fn handle_bitpos<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() < 2 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'bitpos' command\r\n")
+            .await;
+    }
+    let target = match args[1].as_slice() {
+        b"0" => 0,
+        b"1" => 1,
+        _ => {
+            return stream
+                .write_all(b"-ERR The bit argument must be 1 or 0.\r\n")
+                .await;
+        }
+    };
+
+    let mut map = state.db().lock().await;
+    let bytes = match lookup_string(&mut map, &args[0]) {
+        Ok(Some(bytes)) => bytes,
+        Ok(None) => {
+            let pos = if target == 1 { -1 } else { 0 };
+            return stream.write_all(format!(":{}\r\n", pos).as_bytes()).await;
+        }
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let range = match parse_range(&args[2..], bytes) {
+        Ok(range) => range,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+
+    let Some((start, end)) = range else {
+        return stream.write_all(b":-1\r\n").await;
+    };
+
+    // Whole bytes that cannot contain the target bit are skipped.
+    let skip = if target == 1 { 0x00 } else { 0xff };
+    let mut bit = start;
+    let mut found = None;
+    while bit <= end {
+        if bit & 7 == 0 && bit + 7 <= end && bytes[(bit >> 3) as usize] == skip {
+            bit += 8;
+            continue;
+        }
+        if get_bit(bytes, bit) == target {
+            found = Some(bit as i64);
+            break;
+        }
+        bit += 1;
+    }
+
+    // Looking for a clear bit without an explicit end treats the string as
+    // padded with zeros on the right.
+    let pos = match found {
+        Some(pos) => pos,
+        None if target == 0 && args.len() < 4 => bytes.len() as i64 * 8,
+        None => -1,
+    };
+    stream.write_all(format!(":{}\r\n", pos).as_bytes()).await
+}
+
+pub async fn handle_bitop<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() < 3 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'bitop' command\r\n")
+            .await;
+    }
+    let op = protocol::to_upper(&args[0]);
+    if !matches!(op.as_str(), "AND" | "OR" | "XOR" | "NOT") {
+        return stream.write_all(SYNTAX_ERR.as_bytes()).await;
+    }
+    if op == "NOT" && args.len() != 3 {
+        return stream
+            .write_all(b"-ERR BITOP NOT must be called with a single source key.\r\n")
+            .await;
+    }
+
+    let mut map = state.db().lock().await;
+    let mut sources = Vec::with_capacity(args.len() - 2);
+    for key in &args[2..] {
+        match lookup_string(&mut map, key) {
+            Ok(bytes) => sources.push(bytes.cloned().unwrap_or_default()),
+            Err(err) => return stream.write_all(err.as_bytes()).await,
+        }
+    }
+
+    // Shorter inputs behave as if padded with zero bytes.
+    let len = sources.iter().map(|s| s.len()).max().unwrap_or(0);
+    let result: Vec<u8> = (0..len)
+        .map(|i| {
+            let mut bytes = sources.iter().map(|s| s.get(i).copied().unwrap_or(0));
+            let first = bytes.next().unwrap_or(0);
+            match op.as_str() {
+                "AND" => bytes.fold(first, |acc, b| acc & b),
+                "OR" => bytes.fold(first, |acc, b| acc | b),
+                "XOR" => bytes.fold(first, |acc, b| acc ^ b),
+                _ => !first,
+            }
+        })
+        .collect();
+
+    let dest = args[1].to_vec();
+    if result.is_empty() {
+        map.remove(&dest);
+    } else {
+        map.insert(
+            dest,
+            ValueEntry::new(DataStoreValue::String(result), None),
+        );
+    }
+    drop(map);
+
+    stream.write_all(format!(":{}\r\n", len).as_bytes()).await?;
+    let mut command_with_args = vec![b"BITOP".to_vec()];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Overflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+#[derive(Clone, Copy)]
+struct FieldType {
+    signed: bool,
+    bits: u32,
+}
+
+enum FieldOp {
+    Get(FieldType, u64),
+    Set(FieldType, u64, i64, Overflow),
+    IncrBy(FieldType, u64, i64, Overflow),
+}
+
+fn parse_field_type(arg: &[u8]) -> Option<FieldType> {
+    let signed = match arg.first() {
+        Some(b'i') | Some(b'I') => true,
+        Some(b'u') | Some(b'U') => false,
+        _ => return None,
+    };
+    let bits = protocol::parse_arg::<u32>(&arg[1..])?;
+    let max_bits = if signed { 64 } else { 63 };
+    if bits == 0 || bits > max_bits {
+        return None;
+    }
+    Some(FieldType { signed, bits })
+}
+
+/// Parses a BITFIELD offset, where `#N` means the N-th field of this type.
+fn parse_field_offset(arg: &[u8], field: FieldType) -> Option<u64> {
+    let offset = match arg.strip_prefix(b"#") {
+        Some(index) => protocol::parse_arg::<u64>(index)?.checked_mul(field.bits as u64)?,
+        None => protocol::parse_arg::<u64>(arg)?,
+    };
+    if offset + field.bits as u64 > MAX_BIT_OFFSET {
+        return None;
+    }
+    Some(offset)
+}
+
+fn parse_field_ops(args: &[Vec<u8>], read_only: bool) -> Result<Vec<FieldOp>, &'static str> {
+    let mut ops = Vec::new();
+    let mut overflow = Overflow::Wrap;
+    let mut i = 0;
+    while i < args.len() {
+        let sub = protocol::to_upper(&args[i]);
+        if sub == "OVERFLOW" && !read_only {
+            overflow = match args.get(i + 1).map(|o| protocol::to_upper(o)).as_deref() {
+                Some("WRAP") => Overflow::Wrap,
+                Some("SAT") => Overflow::Sat,
+                Some("FAIL") => Overflow::Fail,
+                Some(_) => return Err("-ERR Invalid OVERFLOW type specified\r\n"),
+                None => return Err(SYNTAX_ERR),
+            };
+            i += 2;
+            continue;
+        }
+
+        let arity = match sub.as_str() {
+            "GET" => 3,
+            "SET" | "INCRBY" if !read_only => 4,
+            _ if read_only => return Err("-ERR BITFIELD_RO only supports the GET subcommand\r\n"),
+            _ => return Err(SYNTAX_ERR),
+        };
+        if i + arity > args.len() {
+            return Err(SYNTAX_ERR);
+        }
+
+        let field = parse_field_type(&args[i + 1]).ok_or(
+            "-ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.\r\n",
+        )?;
+        let offset = parse_field_offset(&args[i + 2], field).ok_or(OFFSET_ERR)?;
+        ops.push(match sub.as_str() {
+            "GET" => FieldOp::Get(field, offset),
+            _ => {
+                let value = protocol::parse_arg::<i64>(&args[i + 3]).ok_or(INT_ERR)?;
+                if sub == "SET" {
+                    FieldOp::Set(field, offset, value, overflow)
+                } else {
+                    FieldOp::IncrBy(field, offset, value, overflow)
+                }
+            }
+        });
+        i += arity;
+    }
+    Ok(ops)
+}
+
+fn get_field(bytes: &[u8], offset: u64, field: FieldType) -> i64 {
+    let mut value: u64 = 0;
+    for i in 0..field.bits as u64 {
+        value = (value << 1) | get_bit(bytes, offset + i) as u64;
+    }
+    if field.signed && field.bits < 64 && value & (1 << (field.bits - 1)) != 0 {
+        value |= u64::MAX << field.bits;
+    }
+    value as i64
+}
+
+fn set_field(bytes: &mut Vec<u8>, offset: u64, field: FieldType, value: i64) {
+    let value = value as u64;
+    for i in 0..field.bits as u64 {
+        let bit = (value >> (field.bits as u64 - 1 - i)) & 1;
+        set_bit(bytes, offset + i, bit as u8);
+    }
+}
+
+/// Applies the overflow policy to `value`, returning `None` when the
+/// operation must fail.
+fn handle_overflow(value: i128, field: FieldType, overflow: Overflow) -> Option<i64> {
+    let (min, max) = if field.signed {
+        (
+            -(1i128 << (field.bits - 1)),
+            (1i128 << (field.bits - 1)) - 1,
+        )
+    } else {
+        (0, (1i128 << field.bits) - 1)
+    };
+    if value >= min && value <= max {
+        return Some(value as i64);
+    }
+
+    match overflow {
+        Overflow::Fail => None,
+        Overflow::Sat => Some(if value > max { max as i64 } else { min as i64 }),
+        Overflow::Wrap => {
+            let wrapped = value.rem_euclid(1i128 << field.bits);
+            if field.signed && wrapped > max {
+                Some((wrapped - (1i128 << field.bits)) as i64)
+            } else {
+                Some(wrapped as i64)
+            }
+        }
+    }
+}
+
+async fn bitfield<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+    read_only: bool,
+) -> std::io::Result<()> {
+    if args.is_empty() {
+        let name = if read_only { "bitfield_ro" } else { "bitfield" };
+        let err = format!("-ERR wrong number of arguments for '{}' command\r\n", name);
+        return stream.write_all(err.as_bytes()).await;
+    }
+    let ops = match parse_field_ops(&args[1..], read_only) {
+        Ok(ops) => ops,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let has_writes = ops.iter().any(|op| !matches!(op, FieldOp::Get(..)));
+
+    let mut map = state.db().lock().await;
+    let mut response = format!("*{}\r\n", ops.len());
+    // Whether any write went through, as `OVERFLOW FAIL` writes may not.
+    let mut changed = false;
+    if has_writes {
+        // A missing key is only created if something gets written to it.
+        let mut created = Vec::new();
+        let (bytes, missing) = match lookup_string_mut(&mut map, &args[0]) {
+            Ok(Some(bytes)) => (bytes, false),
+            Ok(None) => (&mut created, true),
+            Err(err) => return stream.write_all(err.as_bytes()).await,
+        };
+        for op in &ops {
+            match *op {
+                FieldOp::Get(field, offset) => {
+                    write!(&mut response, ":{}\r\n", get_field(bytes, offset, field)).unwrap();
+                }
+                FieldOp::Set(field, offset, value, overflow) => {
+                    // Unsigned fields treat the input as its two's complement bits.
+                    let value = if field.signed {
+                        value as i128
+                    } else {
+                        value as u64 as i128
+                    };
+                    match handle_overflow(value, field, overflow) {
+                        Some(new) => {
+                            let old = get_field(bytes, offset, field);
+                            set_field(bytes, offset, field, new);
+                            changed = true;
+                            write!(&mut response, ":{}\r\n", old).unwrap();
+                        }
+                        None => response.push_str("$-1\r\n"),
+                    }
+                }
+                FieldOp::IncrBy(field, offset, incr, overflow) => {
+                    let old = get_field(bytes, offset, field);
+                    let old = if field.signed {
+                        old as i128
+                    } else {
+                        old as u64 as i128
+                    };
+                    match handle_overflow(old + incr as i128, field, overflow) {
+                        Some(new) => {
+                            set_field(bytes, offset, field, new);
+                            changed = true;
+                            write!(&mut response, ":{}\r\n", new).unwrap();
+                        }
+                        None => response.push_str("$-1\r\n"),
+                    }
+                }
+            }
+        }
+        if missing && changed {
+            map.insert(args[0].to_vec(), new_string_entry(created));
+        }
+    } else {
+        let bytes = match lookup_string(&mut map, &args[0]) {
+            Ok(bytes) => bytes.map_or(&[][..], |b| b.as_slice()),
+            Err(err) => return stream.write_all(err.as_bytes()).await,
+        };
+        for op in &ops {
+            if let FieldOp::Get(field, offset) = *op {
+                write!(&mut response, ":{}\r\n", get_field(bytes, offset, field)).unwrap();
+            }
+        }
+    }
+    drop(map);
+
+    stream.write_all(response.as_bytes()).await?;
+    if changed {
+        let mut command_with_args = vec![b"BITFIELD".to_vec()];
+        command_with_args.extend_from_slice(args);
+        protocol::replicate_command(state, command_with_args).await?;
+    }
+    Ok(())
+}
+
+pub async fn handle_bitfield<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    bitfield(stream, state, args, false).await
+}
+
+pub async fn handle_bitfield_ro<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    bitfield(stream, state, args, true).await
+}
@@ -0,0 +1,950 @@
+use crate::commands::object::{help_reply, unknown_subcommand};
+use crate::commands::stream::{lookup_stream_mut, parse_range_bound, push_entry, range_is_empty};
+use crate::protocol;
+use crate::storage::{
+    unix_time_ms, AppState, Consumer, ConsumerGroup, DataStoreValue, Keyspace, PendingEntry,
+    Stream, StreamFields, StreamId, TransactionState, ValueEntry,
+};
+use std::collections::BTreeMap;
+use std::ops::Bound::{Excluded, Unbounded};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{timeout_at, Instant};
+
+const INT_ERR: &str = "-ERR value is not an integer or out of range\r\n";
+const SYNTAX_ERR: &str = "-ERR syntax error\r\n";
+const INVALID_ID_ERR: &str = "-ERR Invalid stream ID specified as stream command argument\r\n";
+const TIMEOUT_ERR: &str = "-ERR timeout is not an integer or out of range\r\n";
+const NEGATIVE_TIMEOUT_ERR: &str = "-ERR timeout is negative\r\n";
+const BUSYGROUP_ERR: &str = "-BUSYGROUP Consumer Group name already exists\r\n";
+const KEY_REQUIRED_ERR: &str = "-ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.\r\n";
+const MISSING_GROUP_ERR: &str = "-ERR Missing GROUP option for XREADGROUP\r\n";
+const UNBALANCED_ERR: &str = "-ERR Unbalanced 'xreadgroup' list of streams: for each stream key an ID or '>' must be specified.\r\n";
+const DOLLAR_ID_ERR: &str = "-ERR The $ ID is meaningless in the context of XREADGROUP: you want to read the history of this consumer by specifying a proper ID, or use the > ID to get new messages. The $ ID would just return an empty result set.\r\n";
+
+// How many entries XAUTOCLAIM claims by default, and how many pending
+// entries it looks at for each of them at most.
+const AUTOCLAIM_COUNT: usize = 100;
+const AUTOCLAIM_ATTEMPTS: i64 = 10;
+
+const XGROUP_HELP: &[&str] = &[
+    "XGROUP <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+    "CREATE <key> <groupname> <id|$> [option]",
+    "    Create a new consumer group. Options are:",
+    "    * MKSTREAM",
+    "      Create the empty stream if it does not exist.",
+    "CREATECONSUMER <key> <groupname> <consumer>",
+    "    Create a new consumer in the specified group.",
+    "DELCONSUMER <key> <groupname> <consumer>",
+    "    Remove the specified consumer.",
+    "DESTROY <key> <groupname>",
+    "    Remove the specified group.",
+    "SETID <key> <groupname> <id|$>",
+    "    Set the current group ID.",
+    "HELP",
+    "    Print this help.",
+];
+
+pub async fn handle_xgroup<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    let Some(subcommand) = args.first() else {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'xgroup' command\r\n")
+            .await;
+    };
+    let subcommand = protocol::to_upper(subcommand);
+    let arity = match subcommand.as_str() {
+        "HELP" => 1..=1,
+        "CREATE" => 4..=5,
+        "SETID" | "CREATECONSUMER" | "DELCONSUMER" => 4..=4,
+        "DESTROY" => 3..=3,
+        _ => {
+            return stream
+                .write_all(unknown_subcommand(&args[0], "XGROUP").as_bytes())
+                .await
+        }
+    };
+    if !arity.contains(&args.len()) {
+        let err = format!(
+            "-ERR wrong number of arguments for 'xgroup|{}' command\r\n",
+            subcommand.to_lowercase()
+        );
+        return stream.write_all(err.as_bytes()).await;
+    }
+    if subcommand == "HELP" {
+        return stream.write_all(&help_reply(XGROUP_HELP)).await;
+    }
+
+    let (key, group_name) = (&args[1], &args[2]);
+    let mkstream = match args.get(4) {
+        Some(option) if option.eq_ignore_ascii_case(b"MKSTREAM") => true,
+        Some(_) => return stream.write_all(SYNTAX_ERR.as_bytes()).await,
+        None => false,
+    };
+    // `$` stands for the last ID of the stream, resolved once it's looked up.
+    let id = match subcommand.as_str() {
+        "CREATE" | "SETID" if args[3] == b"$" => None,
+        "CREATE" | "SETID" => match StreamId::parse(&args[3], 0) {
+            Some(id) => Some(id),
+            None => return stream.write_all(INVALID_ID_ERR.as_bytes()).await,
+        },
+        _ => None,
+    };
+
+    let mut map = state.db().lock().await;
+    let stream_value = match lookup_stream_mut(&mut map, key) {
+        Ok(Some(stream_value)) => stream_value,
+        Ok(None) if mkstream => {
+            let entry = ValueEntry::new(DataStoreValue::Stream(Stream::default()), None);
+            map.insert(key.clone(), entry);
+            match map.get_mut(key).map(|entry| &mut entry.value) {
+                Some(DataStoreValue::Stream(stream_value)) => stream_value,
+                _ => unreachable!("the stream was just inserted"),
+            }
+        }
+        Ok(None) => return stream.write_all(KEY_REQUIRED_ERR.as_bytes()).await,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let id = id.unwrap_or(stream_value.last_id);
+    let groups = &mut stream_value.groups;
+
+    let (reply, changed) = match subcommand.as_str() {
+        "CREATE" if groups.contains_key(group_name) => (BUSYGROUP_ERR.to_string(), false),
+        "CREATE" => {
+            groups.insert(group_name.clone(), ConsumerGroup::new(id));
+            ("+OK\r\n".to_string(), true)
+        }
+        "DESTROY" => match groups.remove(group_name) {
+            Some(_) => (":1\r\n".to_string(), true),
+            None => (":0\r\n".to_string(), false),
+        },
+        _ => match groups.get_mut(group_name) {
+            None => (no_such_group(key, group_name), false),
+            Some(group) if subcommand == "SETID" => {
+                group.last_delivered_id = id;
+                group.entries_read = None;
+                ("+OK\r\n".to_string(), true)
+            }
+            Some(group) if subcommand == "CREATECONSUMER" => {
+                if group.consumers.contains_key(&args[3]) {
+                    (":0\r\n".to_string(), false)
+                } else {
+                    let consumer = Consumer::new(unix_time_ms());
+                    group.consumers.insert(args[3].clone(), consumer);
+                    (":1\r\n".to_string(), true)
+                }
+            }
+            Some(group) => {
+                // Deleting a consumer drops its pending entries with it.
+                let existed = group.consumers.remove(&args[3]).is_some();
+                let before = group.pending.len();
+                group
+                    .pending
+                    .retain(|_, pending| pending.consumer != args[3]);
+                let dropped = before - group.pending.len();
+                (format!(":{}\r\n", dropped), existed)
+            }
+        },
+    };
+    drop(map);
+
+    stream.write_all(reply.as_bytes()).await?;
+    if !changed {
+        return Ok(());
+    }
+    let mut command_with_args = vec![b"XGROUP".to_vec()];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await
+}
+
+/// A parsed XREADGROUP call.
+struct ReadGroup {
+    group: Vec<u8>,
+    consumer: Vec<u8>,
+    count: Option<usize>,
+    /// How long to block for; zero blocks forever.
+    block: Option<Duration>,
+    noack: bool,
+    /// Each stream with the ID to read its consumer's history after, or
+    /// `None` for `>`, which reads entries never delivered to the group.
+    streams: Vec<(Vec<u8>, Option<StreamId>)>,
+}
+
+impl ReadGroup {
+    fn parse(args: &[Vec<u8>]) -> Result<ReadGroup, &'static str> {
+        let (mut group, mut count, mut block, mut noack) = (None, None, None, false);
+        let mut i = 0;
+        let streams = loop {
+            let Some(option) = args.get(i) else {
+                return Err(SYNTAX_ERR);
+            };
+            match protocol::to_upper(option).as_str() {
+                "GROUP" if i + 2 < args.len() => {
+                    group = Some((args[i + 1].clone(), args[i + 2].clone()));
+                    i += 3;
+                }
+                "COUNT" if i + 1 < args.len() => {
+                    let value = protocol::parse_arg::<i64>(&args[i + 1]).ok_or(INT_ERR)?;
+                    // A count of zero or less reads everything.
+                    count = usize::try_from(value).ok().filter(|&count| count > 0);
+                    i += 2;
+                }
+                "BLOCK" if i + 1 < args.len() => {
+                    let ms = protocol::parse_arg::<i64>(&args[i + 1]).ok_or(TIMEOUT_ERR)?;
+                    let ms = u64::try_from(ms).map_err(|_| NEGATIVE_TIMEOUT_ERR)?;
+                    block = Some(Duration::from_millis(ms));
+                    i += 2;
+                }
+                "NOACK" => {
+                    noack = true;
+                    i += 1;
+                }
+                "STREAMS" => break &args[i + 1..],
+                _ => return Err(SYNTAX_ERR),
+            }
+        };
+        let Some((group, consumer)) = group else {
+            return Err(MISSING_GROUP_ERR);
+        };
+        if streams.is_empty() || streams.len() % 2 != 0 {
+            return Err(UNBALANCED_ERR);
+        }
+
+        let (keys, ids) = streams.split_at(streams.len() / 2);
+        let mut parsed = Vec::with_capacity(keys.len());
+        for (key, id) in keys.iter().zip(ids) {
+            let id = match id.as_slice() {
+                b">" => None,
+                b"$" => return Err(DOLLAR_ID_ERR),
+                id => Some(StreamId::parse(id, 0).ok_or(INVALID_ID_ERR)?),
+            };
+            parsed.push((key.clone(), id));
+        }
+        Ok(ReadGroup {
+            group,
+            consumer,
+            count,
+            block,
+            noack,
+            streams: parsed,
+        })
+    }
+
+    /// The XREADGROUP a replica runs to deliver the same `count` entries of
+    /// `key`, whatever has been added to it since.
+    fn command_for(&self, key: &[u8], id: &Option<StreamId>, count: usize) -> Vec<Vec<u8>> {
+        let mut command = vec![
+            b"XREADGROUP".to_vec(),
+            b"GROUP".to_vec(),
+            self.group.clone(),
+            self.consumer.clone(),
+            b"COUNT".to_vec(),
+            count.to_string().into_bytes(),
+        ];
+        if self.noack {
+            command.push(b"NOACK".to_vec());
+        }
+        command.push(b"STREAMS".to_vec());
+        command.push(key.to_vec());
+        command.push(match id {
+            Some(id) => id.to_string().into_bytes(),
+            None => b">".to_vec(),
+        });
+        command
+    }
+}
+
+/// What one pass over the streams of an XREADGROUP produced.
+struct ReadOutcome {
+    /// `None` when nothing new was delivered and there is no history to
+    /// reply with, so a blocking call keeps waiting.
+    reply: Option<Vec<u8>>,
+    /// Commands that bring a replica's groups to the same state.
+    propagate: Vec<Vec<Vec<u8>>>,
+}
+
+pub async fn handle_xreadgroup<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    transation_state: &TransactionState,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    let request = match ReadGroup::parse(args) {
+        Ok(request) => request,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let deadline = request
+        .block
+        .filter(|wait| !wait.is_zero())
+        .map(|wait| Instant::now() + wait);
+
+    // Subscribed before the first look, so an XADD in between still wakes us.
+    let mut notifications = state.stream_notifier.subscribe();
+    loop {
+        let mut map = state.db().lock().await;
+        let outcome = read_groups(&mut map, &request);
+        drop(map);
+
+        let outcome = match outcome {
+            Ok(outcome) => outcome,
+            Err(err) => return stream.write_all(err.as_bytes()).await,
+        };
+        if let Some(reply) = &outcome.reply {
+            stream.write_all(reply).await?;
+        }
+        for command in outcome.propagate {
+            protocol::replicate_command(state, command).await?;
+        }
+        if outcome.reply.is_some() {
+            return Ok(());
+        }
+        if request.block.is_none() || transation_state.in_exec {
+            break;
+        }
+
+        let notified = match deadline {
+            Some(deadline) => match timeout_at(deadline, notifications.recv()).await {
+                Ok(result) => result,
+                Err(_) => break,
+            },
+            None => notifications.recv().await,
+        };
+        // Missed notifications still mean something was added.
+        if let Err(RecvError::Closed) = notified {
+            break;
+        }
+    }
+    stream.write_all(b"*-1\r\n").await
+}
+
+fn read_groups(map: &mut Keyspace, request: &ReadGroup) -> Result<ReadOutcome, String> {
+    // Every stream and group must exist before anything is delivered.
+    for (key, _) in &request.streams {
+        let stream_value = lookup_stream_mut(map, key).map_err(str::to_string)?;
+        if !stream_value
+            .is_some_and(|stream_value| stream_value.groups.contains_key(&request.group))
+        {
+            return Err(format!(
+                "-NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option\r\n",
+                String::from_utf8_lossy(key),
+                String::from_utf8_lossy(&request.group)
+            ));
+        }
+    }
+
+    let now = unix_time_ms();
+    let limit = request.count.unwrap_or(usize::MAX);
+    let mut replies = Vec::new();
+    let mut propagate = Vec::new();
+    for (key, id) in &request.streams {
+        let Ok(Some(stream_value)) = lookup_stream_mut(map, key) else {
+            continue;
+        };
+        let Some(group) = stream_value.groups.get(&request.group) else {
+            continue;
+        };
+
+        // History can name entries deleted from the stream since.
+        let entries = &stream_value.entries;
+        let delivered: Vec<(StreamId, Option<StreamFields>)> = match id {
+            None => entries
+                .range((Excluded(group.last_delivered_id), Unbounded))
+                .take(limit)
+                .map(|(id, fields)| (id, Some(fields)))
+                .collect(),
+            Some(id) => group
+                .pending
+                .range((Excluded(*id), Unbounded))
+                .filter(|(_, pending)| pending.consumer == request.consumer)
+                .take(limit)
+                .map(|(id, _)| (*id, entries.get(id)))
+                .collect(),
+        };
+        // New entries add to the count of entries the group has read, which
+        // is estimated again whenever counting one by one can't be trusted.
+        let mut entries_read = group.entries_read;
+        if id.is_none() {
+            for (entry_id, _) in &delivered {
+                entries_read = match entries_read {
+                    Some(read) if !stream_value.has_tombstones_from(*entry_id) => Some(read + 1),
+                    _ => stream_value.estimate_entries_read(*entry_id),
+                };
+            }
+        }
+
+        let Some(group) = stream_value.groups.get_mut(&request.group) else {
+            continue;
+        };
+        group.entries_read = entries_read;
+        let created = !group.consumers.contains_key(&request.consumer);
+        let consumer = group
+            .consumers
+            .entry(request.consumer.clone())
+            .or_insert(Consumer::new(now));
+        consumer.seen_time = now;
+        // Only new entries count as activity, not history read again.
+        if id.is_none() && !delivered.is_empty() {
+            consumer.active_time = Some(now);
+        }
+        for (entry_id, _) in &delivered {
+            if id.is_some() {
+                if let Some(pending) = group.pending.get_mut(entry_id) {
+                    pending.delivery_time = now;
+                    pending.delivery_count += 1;
+                }
+                continue;
+            }
+            group.last_delivered_id = *entry_id;
+            if !request.noack {
+                let pending = PendingEntry {
+                    consumer: request.consumer.clone(),
+                    delivery_time: now,
+                    delivery_count: 1,
+                };
+                group.pending.insert(*entry_id, pending);
+            }
+        }
+
+        if !delivered.is_empty() {
+            propagate.push(request.command_for(key, id, delivered.len()));
+        } else if created {
+            propagate.push(vec![
+                b"XGROUP".to_vec(),
+                b"CREATECONSUMER".to_vec(),
+                key.clone(),
+                request.group.clone(),
+                request.consumer.clone(),
+            ]);
+        }
+        // Only new entries leave a stream out when there are none; history
+        // is always answered, even if empty.
+        if id.is_none() && delivered.is_empty() {
+            continue;
+        }
+        let mut reply = b"*2\r\n".to_vec();
+        protocol::push_bulk_string(&mut reply, key);
+        reply.extend_from_slice(format!("*{}\r\n", delivered.len()).as_bytes());
+        for (entry_id, fields) in &delivered {
+            push_entry(&mut reply, entry_id, fields.as_ref());
+        }
+        replies.push(reply);
+    }
+
+    let reply = (!replies.is_empty()).then(|| {
+        let mut reply = format!("*{}\r\n", replies.len()).into_bytes();
+        replies
+            .iter()
+            .for_each(|stream_reply| reply.extend_from_slice(stream_reply));
+        reply
+    });
+    Ok(ReadOutcome { reply, propagate })
+}
+
+pub async fn handle_xack<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() < 3 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'xack' command\r\n")
+            .await;
+    }
+    let Some(ids) = args[2..]
+        .iter()
+        .map(|id| StreamId::parse(id, 0))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return stream.write_all(INVALID_ID_ERR.as_bytes()).await;
+    };
+
+    let mut map = state.db().lock().await;
+    let acknowledged = match lookup_stream_mut(&mut map, &args[0]) {
+        Ok(stream_value) => stream_value
+            .and_then(|stream_value| stream_value.groups.get_mut(&args[1]))
+            .map_or(0, |group| {
+                ids.iter()
+                    .filter(|id| group.pending.remove(*id).is_some())
+                    .count()
+            }),
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    drop(map);
+
+    stream
+        .write_all(format!(":{}\r\n", acknowledged).as_bytes())
+        .await?;
+    if acknowledged == 0 {
+        return Ok(());
+    }
+    let mut command_with_args = vec![b"XACK".to_vec()];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await
+}
+
+pub async fn handle_xpending<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() < 2 || args.len() == 3 || args.len() == 4 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'xpending' command\r\n")
+            .await;
+    }
+
+    // The extended form: [IDLE min-idle-time] start end count [consumer].
+    let mut extended = None;
+    if args.len() > 2 {
+        let mut rest = &args[2..];
+        let mut min_idle = 0;
+        if rest[0].eq_ignore_ascii_case(b"IDLE") && rest.len() > 1 {
+            min_idle = match protocol::parse_arg::<i64>(&rest[1]) {
+                Some(min_idle) => min_idle.max(0) as u64,
+                None => return stream.write_all(INT_ERR.as_bytes()).await,
+            };
+            rest = &rest[2..];
+        }
+        if !(3..=4).contains(&rest.len()) {
+            return stream.write_all(SYNTAX_ERR.as_bytes()).await;
+        }
+        let (Some(start), Some(end)) = (
+            parse_range_bound(&rest[0], true),
+            parse_range_bound(&rest[1], false),
+        ) else {
+            return stream.write_all(INVALID_ID_ERR.as_bytes()).await;
+        };
+        let Some(count) = protocol::parse_arg::<i64>(&rest[2]) else {
+            return stream.write_all(INT_ERR.as_bytes()).await;
+        };
+        extended = Some((min_idle, start, end, count.max(0) as usize, rest.get(3)));
+    }
+
+    let mut map = state.db().lock().await;
+    let group = match lookup_stream_mut(&mut map, &args[0]) {
+        Ok(stream_value) => stream_value.and_then(|stream_value| stream_value.groups.get(&args[1])),
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let Some(group) = group else {
+        return stream
+            .write_all(no_such_key_or_group(&args[0], &args[1]).as_bytes())
+            .await;
+    };
+
+    let Some((min_idle, start, end, count, consumer)) = extended else {
+        let reply = pending_summary(group);
+        drop(map);
+        return stream.write_all(&reply).await;
+    };
+    let now = unix_time_ms();
+    let mut entries = Vec::new();
+    if !range_is_empty(&start, &end) {
+        let matching = group.pending.range((start, end)).filter(|(_, pending)| {
+            consumer.is_none_or(|consumer| pending.consumer == *consumer)
+                && now.saturating_sub(pending.delivery_time) >= min_idle
+        });
+        for (id, pending) in matching.take(count) {
+            let mut entry = b"*4\r\n".to_vec();
+            protocol::push_bulk_string(&mut entry, id.to_string().as_bytes());
+            protocol::push_bulk_string(&mut entry, &pending.consumer);
+            entry.extend_from_slice(
+                format!(
+                    ":{}\r\n:{}\r\n",
+                    now.saturating_sub(pending.delivery_time),
+                    pending.delivery_count
+                )
+                .as_bytes(),
+            );
+            entries.push(entry);
+        }
+    }
+    drop(map);
+
+    let mut reply = format!("*{}\r\n", entries.len()).into_bytes();
+    entries
+        .iter()
+        .for_each(|entry| reply.extend_from_slice(entry));
+    stream.write_all(&reply).await
+}
+
+/// The summary form of XPENDING: how many entries are pending, the smallest
+/// and greatest of their IDs, and how many each consumer holds.
+fn pending_summary(group: &ConsumerGroup) -> Vec<u8> {
+    let (Some((first, _)), Some((last, _))) = (
+        group.pending.first_key_value(),
+        group.pending.last_key_value(),
+    ) else {
+        return b"*4\r\n:0\r\n$-1\r\n$-1\r\n*-1\r\n".to_vec();
+    };
+    let mut per_consumer: BTreeMap<&[u8], usize> = BTreeMap::new();
+    for pending in group.pending.values() {
+        *per_consumer.entry(&pending.consumer).or_default() += 1;
+    }
+
+    let mut reply = format!("*4\r\n:{}\r\n", group.pending.len()).into_bytes();
+    protocol::push_bulk_string(&mut reply, first.to_string().as_bytes());
+    protocol::push_bulk_string(&mut reply, last.to_string().as_bytes());
+    reply.extend_from_slice(format!("*{}\r\n", per_consumer.len()).as_bytes());
+    for (consumer, count) in per_consumer {
+        reply.extend_from_slice(b"*2\r\n");
+        protocol::push_bulk_string(&mut reply, consumer);
+        protocol::push_bulk_string(&mut reply, count.to_string().as_bytes());
+    }
+    reply
+}
+
+pub async fn handle_xclaim<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() < 5 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'xclaim' command\r\n")
+            .await;
+    }
+    let (key, group_name, consumer) = (&args[0], &args[1], &args[2]);
+    let Some(min_idle) = protocol::parse_arg::<i64>(&args[3]) else {
+        return stream
+            .write_all(b"-ERR Invalid min-idle-time argument for XCLAIM\r\n")
+            .await;
+    };
+    let min_idle = min_idle.max(0) as u64;
+
+    // IDs run up to the first argument that isn't one; options follow.
+    let mut ids = Vec::new();
+    let mut rest = &args[4..];
+    while let Some(id) = rest.first().and_then(|arg| StreamId::parse(arg, 0)) {
+        ids.push(id);
+        rest = &rest[1..];
+    }
+    let now = unix_time_ms();
+    let (mut delivery_time, mut retry_count, mut last_id) = (now, None, None);
+    let (mut force, mut justid) = (false, false);
+    while let Some(option) = rest.first() {
+        let option = protocol::to_upper(option);
+        let value = match option.as_str() {
+            "FORCE" | "JUSTID" => None,
+            "IDLE" | "TIME" | "RETRYCOUNT" | "LASTID" if rest.len() > 1 => Some(&rest[1]),
+            _ => {
+                let err = format!(
+                    "-ERR Unrecognized XCLAIM option '{}'\r\n",
+                    String::from_utf8_lossy(&rest[0])
+                );
+                return stream.write_all(err.as_bytes()).await;
+            }
+        };
+        rest = &rest[if value.is_some() { 2 } else { 1 }..];
+        let Some(value) = value else {
+            force |= option == "FORCE";
+            justid |= option == "JUSTID";
+            continue;
+        };
+        if option == "LASTID" {
+            match StreamId::parse(value, 0) {
+                Some(id) => last_id = Some(id),
+                None => return stream.write_all(INVALID_ID_ERR.as_bytes()).await,
+            }
+            continue;
+        }
+        let Some(value) = protocol::parse_arg::<i64>(value) else {
+            let err = format!("-ERR Invalid {} option argument for XCLAIM\r\n", option);
+            return stream.write_all(err.as_bytes()).await;
+        };
+        let value = value.max(0) as u64;
+        match option.as_str() {
+            "IDLE" => delivery_time = now.saturating_sub(value),
+            "TIME" => delivery_time = value,
+            _ => retry_count = Some(value),
+        }
+    }
+    // Entries can't have been delivered in the future.
+    let delivery_time = delivery_time.min(now);
+
+    let mut map = state.db().lock().await;
+    let stream_value = match lookup_stream_mut(&mut map, key) {
+        Ok(stream_value) => stream_value,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let Some(Stream {
+        entries, groups, ..
+    }) = stream_value
+    else {
+        return stream
+            .write_all(no_such_key_or_group(key, group_name).as_bytes())
+            .await;
+    };
+    let Some(group) = groups.get_mut(group_name) else {
+        return stream
+            .write_all(no_such_key_or_group(key, group_name).as_bytes())
+            .await;
+    };
+
+    let mut propagate = Vec::new();
+    if let Some(last_id) = last_id.filter(|last_id| *last_id > group.last_delivered_id) {
+        group.last_delivered_id = last_id;
+        propagate.push(vec![
+            b"XGROUP".to_vec(),
+            b"SETID".to_vec(),
+            key.clone(),
+            group_name.clone(),
+            last_id.to_string().into_bytes(),
+        ]);
+    }
+    let mut claimed = Vec::new();
+    for id in ids {
+        let pending = match group.pending.get(&id) {
+            Some(pending) => {
+                if now.saturating_sub(pending.delivery_time) < min_idle {
+                    continue;
+                }
+                pending
+            }
+            // FORCE creates the pending entry of an entry nobody got yet.
+            None if force && entries.contains_key(&id) => &PendingEntry {
+                consumer: consumer.clone(),
+                delivery_time,
+                delivery_count: 0,
+            },
+            None => continue,
+        };
+        if !entries.contains_key(&id) {
+            group.pending.remove(&id);
+            propagate.push(claim_command(key, group_name, consumer, &id, None));
+            continue;
+        }
+        let delivery_count = match retry_count {
+            Some(retry_count) => retry_count,
+            None if justid => pending.delivery_count,
+            None => pending.delivery_count + 1,
+        };
+        let pending = PendingEntry {
+            consumer: consumer.clone(),
+            delivery_time,
+            delivery_count,
+        };
+        propagate.push(claim_command(
+            key,
+            group_name,
+            consumer,
+            &id,
+            Some(&pending),
+        ));
+        group.pending.insert(id, pending);
+        claimed.push(id);
+    }
+    if !claimed.is_empty() || group.consumers.contains_key(consumer) {
+        let consumer = group
+            .consumers
+            .entry(consumer.clone())
+            .or_insert(Consumer::new(now));
+        consumer.seen_time = now;
+        if !claimed.is_empty() {
+            consumer.active_time = Some(now);
+        }
+    }
+
+    let mut reply = format!("*{}\r\n", claimed.len()).into_bytes();
+    for id in &claimed {
+        if justid {
+            protocol::push_bulk_string(&mut reply, id.to_string().as_bytes());
+        } else {
+            push_entry(&mut reply, id, entries.get(id).as_ref());
+        }
+    }
+    drop(map);
+
+    stream.write_all(&reply).await?;
+    for command in propagate {
+        protocol::replicate_command(state, command).await?;
+    }
+    Ok(())
+}
+
+pub async fn handle_xautoclaim<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() < 5 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'xautoclaim' command\r\n")
+            .await;
+    }
+    let (key, group_name, consumer) = (&args[0], &args[1], &args[2]);
+    let Some(min_idle) = protocol::parse_arg::<i64>(&args[3]) else {
+        return stream
+            .write_all(b"-ERR Invalid min-idle-time argument for XAUTOCLAIM\r\n")
+            .await;
+    };
+    let min_idle = min_idle.max(0) as u64;
+    let Some(start) = parse_range_bound(&args[4], true) else {
+        return stream.write_all(INVALID_ID_ERR.as_bytes()).await;
+    };
+    let (mut count, mut justid) = (AUTOCLAIM_COUNT, false);
+    let mut rest = &args[5..];
+    while let Some(option) = rest.first() {
+        match protocol::to_upper(option).as_str() {
+            "COUNT" if rest.len() > 1 => {
+                count = match protocol::parse_arg::<i64>(&rest[1]) {
+                    Some(value) if value > 0 && value <= i64::MAX / AUTOCLAIM_ATTEMPTS => {
+                        value as usize
+                    }
+                    Some(_) => return stream.write_all(b"-ERR COUNT must be > 0\r\n").await,
+                    None => return stream.write_all(INT_ERR.as_bytes()).await,
+                };
+                rest = &rest[2..];
+            }
+            "JUSTID" => {
+                justid = true;
+                rest = &rest[1..];
+            }
+            _ => return stream.write_all(SYNTAX_ERR.as_bytes()).await,
+        }
+    }
+
+    let mut map = state.db().lock().await;
+    let stream_value = match lookup_stream_mut(&mut map, key) {
+        Ok(stream_value) => stream_value,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let Some(Stream {
+        entries, groups, ..
+    }) = stream_value
+    else {
+        return stream
+            .write_all(no_such_key_or_group(key, group_name).as_bytes())
+            .await;
+    };
+    let Some(group) = groups.get_mut(group_name) else {
+        return stream
+            .write_all(no_such_key_or_group(key, group_name).as_bytes())
+            .await;
+    };
+
+    // At most ten pending entries are looked at for each one asked for, so a
+    // list of entries that are all too recent can't stall the server.
+    let now = unix_time_ms();
+    let scanned: Vec<StreamId> = group
+        .pending
+        .range((start, Unbounded))
+        .take(count * AUTOCLAIM_ATTEMPTS as usize)
+        .map(|(id, _)| *id)
+        .collect();
+    let (mut claimed, mut deleted, mut propagate) = (Vec::new(), Vec::new(), Vec::new());
+    let mut last_scanned = None;
+    for id in &scanned {
+        if claimed.len() == count {
+            break;
+        }
+        last_scanned = Some(id);
+        let Some(pending) = group.pending.get_mut(id) else {
+            continue;
+        };
+        if now.saturating_sub(pending.delivery_time) < min_idle {
+            continue;
+        }
+        if !entries.contains_key(id) {
+            group.pending.remove(id);
+            propagate.push(claim_command(key, group_name, consumer, id, None));
+            deleted.push(id);
+            continue;
+        }
+        pending.consumer = consumer.clone();
+        pending.delivery_time = now;
+        if !justid {
+            pending.delivery_count += 1;
+        }
+        propagate.push(claim_command(key, group_name, consumer, id, Some(pending)));
+        claimed.push(id);
+    }
+    if !claimed.is_empty() || group.consumers.contains_key(consumer) {
+        let consumer = group
+            .consumers
+            .entry(consumer.clone())
+            .or_insert(Consumer::new(now));
+        consumer.seen_time = now;
+        if !claimed.is_empty() {
+            consumer.active_time = Some(now);
+        }
+    }
+    // The next call picks up where this one stopped; 0-0 once the whole
+    // list has been scanned, or when nothing was left from `start` on.
+    let cursor = match last_scanned {
+        Some(last) => group.pending.range((Excluded(*last), Unbounded)).next(),
+        None => group.pending.range((start, Unbounded)).next(),
+    }
+    .map_or(StreamId::MIN, |(id, _)| *id);
+
+    let mut reply = b"*3\r\n".to_vec();
+    protocol::push_bulk_string(&mut reply, cursor.to_string().as_bytes());
+    reply.extend_from_slice(format!("*{}\r\n", claimed.len()).as_bytes());
+    for id in &claimed {
+        if justid {
+            protocol::push_bulk_string(&mut reply, id.to_string().as_bytes());
+        } else {
+            push_entry(&mut reply, id, entries.get(id).as_ref());
+        }
+    }
+    reply.extend_from_slice(format!("*{}\r\n", deleted.len()).as_bytes());
+    for id in &deleted {
+        protocol::push_bulk_string(&mut reply, id.to_string().as_bytes());
+    }
+    drop(map);
+
+    stream.write_all(&reply).await?;
+    for command in propagate {
+        protocol::replicate_command(state, command).await?;
+    }
+    Ok(())
+}
+
+/// The XCLAIM a replica runs to give the pending entry `id` the same owner,
+/// delivery time and count, or to drop it when `pending` is `None` because
+/// the entry was deleted from the stream.
+fn claim_command(
+    key: &[u8],
+    group: &[u8],
+    consumer: &[u8],
+    id: &StreamId,
+    pending: Option<&PendingEntry>,
+) -> Vec<Vec<u8>> {
+    let mut command = vec![
+        b"XCLAIM".to_vec(),
+        key.to_vec(),
+        group.to_vec(),
+        consumer.to_vec(),
+        b"0".to_vec(),
+        id.to_string().into_bytes(),
+    ];
+    if let Some(pending) = pending {
+        command.push(b"TIME".to_vec());
+        command.push(pending.delivery_time.to_string().into_bytes());
+        command.push(b"RETRYCOUNT".to_vec());
+        command.push(pending.delivery_count.to_string().into_bytes());
+    }
+    command.push(b"FORCE".to_vec());
+    command.push(b"JUSTID".to_vec());
+    command
+}
+
+fn no_such_key_or_group(key: &[u8], group: &[u8]) -> String {
+    format!(
+        "-NOGROUP No such key '{}' or consumer group '{}'\r\n",
+        String::from_utf8_lossy(key),
+        String::from_utf8_lossy(group)
+    )
+}
+
+pub fn no_such_group(key: &[u8], group: &[u8]) -> String {
+    format!(
+        "-NOGROUP No such consumer group '{}' for key name '{}'\r\n",
+        String::from_utf8_lossy(group),
+        String::from_utf8_lossy(key)
+    )
+}
@@ -0,0 +1,395 @@
+use crate::protocol;
+use crate::rdb;
+use crate::storage::{lookup_key, unix_time_ms, AppState, MigrateSocket, ValueEntry};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+
+const SYNTAX_ERR: &str = "-ERR syntax error\r\n";
+const INT_ERR: &str = "-ERR value is not an integer or out of range\r\n";
+const CONNECT_ERR: &str = "-IOERR error or timeout connecting to the client\r\n";
+const WRITE_ERR: &str = "-IOERR error or timeout writing to target instance\r\n";
+const READ_ERR: &str = "-IOERR error or timeout reading to target instance\r\n";
+
+// Cached MIGRATE connections are closed after this long without use.
+const MIGRATE_SOCKET_IDLE: Duration = Duration::from_secs(10);
+
+pub async fn handle_dump<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 1 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'dump' command\r\n")
+            .await;
+    }
+
+    let mut map = state.db().lock().await;
+    lookup_key(&mut map, &args[0]);
+    let payload = map.get(&args[0]).map(|entry| rdb::dump(&entry.value));
+    drop(map);
+
+    match payload {
+        Some(payload) => stream.write_all(&protocol::bulk_string(&payload)).await,
+        None => stream.write_all(b"$-1\r\n").await,
+    }
+}
+
+/// RESTORE key ttl payload [REPLACE] [ABSTTL] [IDLETIME seconds] [FREQ frequency]
+///
+/// The key is replicated with an absolute deadline, so replicas expire it at
+/// the same moment as the master.
+pub async fn handle_restore<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() < 3 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'restore' command\r\n")
+            .await;
+    }
+    let (key, payload) = (&args[0], &args[2]);
+
+    let mut replace = false;
+    let mut absttl = false;
+    let mut idle_secs = None;
+    let mut freq = None;
+    let mut i = 3;
+    while i < args.len() {
+        match protocol::to_upper(&args[i]).as_str() {
+            "REPLACE" => replace = true,
+            "ABSTTL" => absttl = true,
+            "IDLETIME" if i + 1 < args.len() && freq.is_none() => {
+                match protocol::parse_arg::<i64>(&args[i + 1]) {
+                    Some(secs) if secs >= 0 => idle_secs = Some(secs as u64),
+                    Some(_) => {
+                        return stream
+                            .write_all(b"-ERR Invalid IDLETIME value, must be >= 0\r\n")
+                            .await
+                    }
+                    None => return stream.write_all(INT_ERR.as_bytes()).await,
+                }
+                i += 1;
+            }
+            "FREQ" if i + 1 < args.len() && idle_secs.is_none() => {
+                match protocol::parse_arg::<i64>(&args[i + 1]) {
+                    Some(count) if (0..=255).contains(&count) => freq = Some(count as u8),
+                    Some(_) => {
+                        return stream
+                            .write_all(b"-ERR Invalid FREQ value, must be >= 0 and <= 255\r\n")
+                            .await
+                    }
+                    None => return stream.write_all(INT_ERR.as_bytes()).await,
+                }
+                i += 1;
+            }
+            _ => return stream.write_all(SYNTAX_ERR.as_bytes()).await,
+        }
+        i += 1;
+    }
+
+    let ttl = match protocol::parse_arg::<i64>(&args[1]) {
+        Some(ttl) if ttl >= 0 => ttl as u64,
+        Some(_) => {
+            return stream
+                .write_all(b"-ERR Invalid TTL value, must be >= 0\r\n")
+                .await
+        }
+        None => return stream.write_all(INT_ERR.as_bytes()).await,
+    };
+    let now = unix_time_ms();
+    let expires_at = match ttl {
+        0 => None,
+        ttl if absttl => Some(ttl),
+        ttl => Some(now.saturating_add(ttl)),
+    };
+
+    let value = match rdb::undump(payload) {
+        Ok(value) => value,
+        Err(err) => {
+            return stream
+                .write_all(format!("-ERR {}\r\n", err).as_bytes())
+                .await
+        }
+    };
+
+    let mut map = state.db().lock().await;
+    lookup_key(&mut map, key);
+    if map.contains_key(key) && !replace {
+        return stream
+            .write_all(b"-BUSYKEY Target key name already exists.\r\n")
+            .await;
+    }
+    // A deadline in the past only removes the key being replaced.
+    if expires_at.is_some_and(|deadline| deadline <= now) {
+        map.remove(key);
+    } else {
+        let mut entry = ValueEntry::new(value, expires_at);
+        if let Some(secs) = idle_secs {
+            entry.last_access = now.saturating_sub(secs * 1000);
+        }
+        if let Some(count) = freq {
+            entry.set_lfu_count(count);
+        }
+        map.insert(key.to_vec(), entry);
+    }
+    drop(map);
+
+    stream.write_all(b"+OK\r\n").await?;
+    let deadline = expires_at.unwrap_or(0).to_string().into_bytes();
+    let mut command_with_args = vec![
+        b"RESTORE".to_vec(),
+        key.to_vec(),
+        deadline,
+        payload.to_vec(),
+        b"REPLACE".to_vec(),
+        b"ABSTTL".to_vec(),
+    ];
+    if let Some(secs) = idle_secs {
+        command_with_args.push(b"IDLETIME".to_vec());
+        command_with_args.push(secs.to_string().into_bytes());
+    }
+    if let Some(count) = freq {
+        command_with_args.push(b"FREQ".to_vec());
+        command_with_args.push(count.to_string().into_bytes());
+    }
+    protocol::replicate_command(state, command_with_args).await
+}
+
+/// MIGRATE host port key|"" destination-db timeout [COPY] [REPLACE]
+/// [AUTH password] [AUTH2 username password] [KEYS key...]
+///
+/// The keys are sent to the target as RESTORE commands over a connection that
+/// is kept open for later calls. The source database stays locked until the
+/// target has answered, so no client sees a key half-moved; keys the target
+/// accepted are then deleted here unless COPY is given.
+pub async fn handle_migrate<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() < 5 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'migrate' command\r\n")
+            .await;
+    }
+    let address = format!(
+        "{}:{}",
+        String::from_utf8_lossy(&args[0]),
+        String::from_utf8_lossy(&args[1])
+    );
+    let Some(db) = protocol::parse_arg::<i64>(&args[3]) else {
+        return stream.write_all(INT_ERR.as_bytes()).await;
+    };
+    let Some(timeout_ms) = protocol::parse_arg::<i64>(&args[4]) else {
+        return stream.write_all(INT_ERR.as_bytes()).await;
+    };
+    let timeout = Duration::from_millis(if timeout_ms <= 0 {
+        1000
+    } else {
+        timeout_ms as u64
+    });
+
+    let mut copy = false;
+    let mut replace = false;
+    let mut auth = None;
+    let mut keys = vec![&args[2]];
+    let mut i = 5;
+    while i < args.len() {
+        match protocol::to_upper(&args[i]).as_str() {
+            "COPY" => copy = true,
+            "REPLACE" => replace = true,
+            "AUTH" if i + 1 < args.len() => {
+                auth = Some(vec![b"AUTH".to_vec(), args[i + 1].to_vec()]);
+                i += 1;
+            }
+            "AUTH2" if i + 2 < args.len() => {
+                auth = Some(vec![
+                    b"AUTH".to_vec(),
+                    args[i + 1].to_vec(),
+                    args[i + 2].to_vec(),
+                ]);
+                i += 2;
+            }
+            "KEYS" => {
+                if !args[2].is_empty() {
+                    return stream
+                        .write_all(b"-ERR When using MIGRATE KEYS option, the key argument must be set to the empty string\r\n")
+                        .await;
+                }
+                keys = args[i + 1..].iter().collect();
+                break;
+            }
+            _ => return stream.write_all(SYNTAX_ERR.as_bytes()).await,
+        }
+        i += 1;
+    }
+
+    let mut sockets = state.migrate_sockets.lock().await;
+    let mut map = state.db().lock().await;
+    let now = unix_time_ms();
+    let mut found = Vec::new();
+    for key in keys {
+        lookup_key(&mut map, key);
+        if let Some(entry) = map.get(key) {
+            let ttl = entry
+                .expires_at
+                .map_or(0, |deadline| deadline.saturating_sub(now).max(1));
+            found.push((key, ttl, rdb::dump(&entry.value)));
+        }
+    }
+    if found.is_empty() {
+        return stream.write_all(b"+NOKEY\r\n").await;
+    }
+
+    sockets.retain(|_, socket| socket.last_use.elapsed() < MIGRATE_SOCKET_IDLE);
+    let cached = sockets.remove(&address);
+    let retry = cached.is_some();
+    let mut attempt = transfer(
+        cached,
+        &address,
+        db,
+        auth.as_ref(),
+        &found,
+        replace,
+        timeout,
+    )
+    .await;
+    // A cached connection may have been closed by the target in the meantime,
+    // so writing to one is retried once on a fresh connection. Once the
+    // commands went out, or on a timeout, the target may have run them, so
+    // sending them again isn't safe.
+    if retry && matches!(attempt, Err((_, true))) {
+        attempt = transfer(None, &address, db, auth.as_ref(), &found, replace, timeout).await;
+    }
+    let (mut socket, replies) = match attempt {
+        Ok(result) => result,
+        Err((err, _)) => return stream.write_all(err.as_bytes()).await,
+    };
+
+    // The replies to AUTH and SELECT come first, followed by one per key. If
+    // either of the first failed, the RESTOREs can't be trusted to have gone
+    // to the right place, so nothing is deleted.
+    let (setup, restores) = replies.split_at(replies.len() - found.len());
+    let error_text = |reply: &Vec<u8>| String::from_utf8_lossy(&reply[1..]).into_owned();
+    let mut error = None;
+    let mut moved = Vec::new();
+    if let Some(reply) = setup.iter().find(|reply| reply.starts_with(b"-")) {
+        error = Some(error_text(reply));
+        socket.db = None;
+    } else {
+        for (reply, (key, _, _)) in restores.iter().zip(&found) {
+            if reply.starts_with(b"-") {
+                error.get_or_insert_with(|| error_text(reply));
+            } else {
+                moved.push(key.to_vec());
+            }
+        }
+    }
+    sockets.insert(address, socket);
+    drop(sockets);
+    if !copy {
+        for key in &moved {
+            map.remove(key);
+        }
+    }
+    drop(map);
+
+    match error {
+        Some(error) => {
+            let err = format!("-ERR Target instance replied with error: {}\r\n", error);
+            stream.write_all(err.as_bytes()).await?;
+        }
+        None => stream.write_all(b"+OK\r\n").await?,
+    }
+    if !copy && !moved.is_empty() {
+        let mut command_with_args = vec![b"DEL".to_vec()];
+        command_with_args.extend(moved);
+        protocol::replicate_command(state, command_with_args).await?;
+    }
+    Ok(())
+}
+
+/// Sends AUTH, SELECT and the RESTOREs of a MIGRATE as one pipeline and
+/// collects the target's reply lines. On success the socket is handed back to
+/// be cached; on failure it is dropped and the IOERR to reply with returned,
+/// along with whether the pipeline failed to be written, rather than timing
+/// out or failing while reading the replies.
+async fn transfer(
+    cached: Option<MigrateSocket>,
+    address: &str,
+    db: i64,
+    auth: Option<&Vec<Vec<u8>>>,
+    keys: &[(&Vec<u8>, u64, Vec<u8>)],
+    replace: bool,
+    timeout: Duration,
+) -> Result<(MigrateSocket, Vec<Vec<u8>>), (&'static str, bool)> {
+    let mut socket = match cached {
+        Some(socket) => socket,
+        None => {
+            let connect = tokio::time::timeout(timeout, TcpStream::connect(address)).await;
+            let Ok(Ok(tcp)) = connect else {
+                return Err((CONNECT_ERR, false));
+            };
+            MigrateSocket {
+                stream: BufStream::new(tcp),
+                db: None,
+                last_use: Instant::now(),
+            }
+        }
+    };
+
+    let mut pipeline = Vec::new();
+    let mut expected = keys.len();
+    if let Some(auth) = auth {
+        pipeline.extend_from_slice(&protocol::serialize_resp_array(auth));
+        expected += 1;
+    }
+    // A new connection may be authenticated by this very pipeline, so the
+    // database is only assumed to be selected once it was on this socket.
+    if socket.db != Some(db) {
+        let select = [b"SELECT".to_vec(), db.to_string().into_bytes()];
+        pipeline.extend_from_slice(&protocol::serialize_resp_array(&select));
+        expected += 1;
+    }
+    for (key, ttl, payload) in keys {
+        let mut restore = vec![
+            b"RESTORE".to_vec(),
+            key.to_vec(),
+            ttl.to_string().into_bytes(),
+            payload.to_vec(),
+        ];
+        if replace {
+            restore.push(b"REPLACE".to_vec());
+        }
+        pipeline.extend_from_slice(&protocol::serialize_resp_array(&restore));
+    }
+
+    let write = async {
+        socket.stream.write_all(&pipeline).await?;
+        socket.stream.flush().await
+    };
+    match tokio::time::timeout(timeout, write).await {
+        Ok(Ok(())) => {}
+        Ok(Err(_)) => return Err((WRITE_ERR, true)),
+        Err(_) => return Err((WRITE_ERR, false)),
+    }
+
+    let mut replies = Vec::with_capacity(expected);
+    for _ in 0..expected {
+        let mut line = Vec::new();
+        let read = tokio::time::timeout(timeout, socket.stream.read_until(b'\n', &mut line)).await;
+        if !matches!(read, Ok(Ok(n)) if n > 0) {
+            return Err((READ_ERR, false));
+        }
+        line.truncate(line.len().saturating_sub(2));
+        replies.push(line);
+    }
+
+    socket.db = Some(db);
+    socket.last_use = Instant::now();
+    Ok((socket, replies))
+}
@@ -0,0 +1,268 @@
+use crate::protocol;
+use crate::storage::{lookup_key, remove_if_expired, unix_time_ms, AppState};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+
+const INT_ERR: &str = "-ERR value is not an integer or out of range\r\n";
+
+// The active expiry cycle runs ten times a second and samples this many keys
+// with a deadline at a time, for at most a quarter of each period.
+const ACTIVE_EXPIRE_PERIOD: Duration = Duration::from_millis(100);
+const ACTIVE_EXPIRE_KEYS_PER_LOOP: usize = 20;
+const ACTIVE_EXPIRE_TIME_LIMIT: Duration = Duration::from_millis(25);
+// Buckets moved per cycle in a database whose table is being resized.
+const ACTIVE_REHASH_BUCKETS: usize = 100;
+
+pub async fn handle_expire<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    expire(stream, state, args, 1000, false, "expire").await
+}
+
+pub async fn handle_pexpire<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    expire(stream, state, args, 1, false, "pexpire").await
+}
+
+pub async fn handle_expireat<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    expire(stream, state, args, 1000, true, "expireat").await
+}
+
+pub async fn handle_pexpireat<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    expire(stream, state, args, 1, true, "pexpireat").await
+}
+
+pub async fn handle_ttl<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    ttl(stream, state, args, false, false, "ttl").await
+}
+
+pub async fn handle_pttl<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    ttl(stream, state, args, true, false, "pttl").await
+}
+
+pub async fn handle_expiretime<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    ttl(stream, state, args, false, true, "expiretime").await
+}
+
+pub async fn handle_pexpiretime<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    ttl(stream, state, args, true, true, "pexpiretime").await
+}
+
+pub async fn handle_persist<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 1 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'persist' command\r\n")
+            .await;
+    }
+
+    let mut map = state.db().lock().await;
+    lookup_key(&mut map, &args[0]);
+    let persisted = match map.get_mut(&args[0]) {
+        Some(entry) => entry.expires_at.take().is_some(),
+        None => false,
+    };
+    drop(map);
+
+    if persisted {
+        stream.write_all(b":1\r\n").await?;
+        let command_with_args = vec![b"PERSIST".to_vec(), args[0].to_vec()];
+        protocol::replicate_command(state, command_with_args).await
+    } else {
+        stream.write_all(b":0\r\n").await
+    }
+}
+
+/// Removes expired keys that nobody reads. Each cycle samples keys with a
+/// deadline and deletes the expired ones, sampling again straight away while
+/// more than a quarter of a sample turned out to be expired. Every removal is
+/// propagated to replicas as a `DEL`. Each cycle also moves a few buckets of
+/// any database whose table is being resized.
+pub async fn active_expire_cycle(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(ACTIVE_EXPIRE_PERIOD);
+    loop {
+        interval.tick().await;
+        let started = Instant::now();
+        for db in &state.databases {
+            db.lock().await.rehash(ACTIVE_REHASH_BUCKETS);
+            loop {
+                let mut map = db.lock().await;
+                let sampled = map.volatile_len().min(ACTIVE_EXPIRE_KEYS_PER_LOOP);
+                let expired = map.expire_sample(ACTIVE_EXPIRE_KEYS_PER_LOOP);
+                drop(map);
+
+                if expired > 0 {
+                    let _ = protocol::propagate_expired(&state).await;
+                }
+                if expired * 4 <= sampled || started.elapsed() >= ACTIVE_EXPIRE_TIME_LIMIT {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Shared implementation of EXPIRE, PEXPIRE, EXPIREAT and PEXPIREAT. The
+/// deadline is always replicated as an absolute PEXPIREAT, so a deadline that
+/// has already passed deletes the key on replicas too.
+async fn expire<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+    unit_ms: i64,
+    absolute: bool,
+    name: &str,
+) -> std::io::Result<()> {
+    if args.len() < 2 {
+        let err = format!("-ERR wrong number of arguments for '{}' command\r\n", name);
+        return stream.write_all(err.as_bytes()).await;
+    }
+    let key = &args[0];
+    let Some(when) = protocol::parse_arg::<i64>(&args[1]) else {
+        return stream.write_all(INT_ERR.as_bytes()).await;
+    };
+
+    let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+    for option in &args[2..] {
+        match protocol::to_upper(option).as_str() {
+            "NX" => nx = true,
+            "XX" => xx = true,
+            "GT" => gt = true,
+            "LT" => lt = true,
+            _ => {
+                let err = format!(
+                    "-ERR Unsupported option {}\r\n",
+                    String::from_utf8_lossy(option)
+                );
+                return stream.write_all(err.as_bytes()).await;
+            }
+        }
+    }
+    if nx && (xx || gt || lt) {
+        return stream
+            .write_all(b"-ERR NX and XX, GT or LT options at the same time are not compatible\r\n")
+            .await;
+    }
+    if gt && lt {
+        return stream
+            .write_all(b"-ERR GT and LT options at the same time are not compatible\r\n")
+            .await;
+    }
+
+    let now = unix_time_ms() as i64;
+    let deadline = when.checked_mul(unit_ms).and_then(|ms| {
+        if absolute {
+            Some(ms)
+        } else {
+            ms.checked_add(now)
+        }
+    });
+    let Some(deadline) = deadline else {
+        let err = format!("-ERR invalid expire time in '{}' command\r\n", name);
+        return stream.write_all(err.as_bytes()).await;
+    };
+
+    let mut map = state.db().lock().await;
+    lookup_key(&mut map, key);
+    let Some(entry) = map.get(key) else {
+        return stream.write_all(b":0\r\n").await;
+    };
+
+    // A key without a TTL counts as never expiring for GT and LT.
+    let allowed = match entry.expires_at {
+        Some(current) => {
+            !nx && (!gt || deadline > current as i64) && (!lt || deadline < current as i64)
+        }
+        None => !xx && !gt,
+    };
+    if !allowed {
+        return stream.write_all(b":0\r\n").await;
+    }
+
+    if deadline <= now {
+        map.remove(key);
+    } else {
+        map.set_expiry(key, Some(deadline as u64));
+    }
+    drop(map);
+
+    stream.write_all(b":1\r\n").await?;
+    let command_with_args = vec![
+        b"PEXPIREAT".to_vec(),
+        key.to_vec(),
+        deadline.to_string().into_bytes(),
+    ];
+    protocol::replicate_command(state, command_with_args).await
+}
+
+/// Shared implementation of TTL, PTTL, EXPIRETIME and PEXPIRETIME. Replies -2
+/// for a missing key and -1 for a key without a deadline.
+async fn ttl<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+    in_ms: bool,
+    absolute: bool,
+    name: &str,
+) -> std::io::Result<()> {
+    if args.len() != 1 {
+        let err = format!("-ERR wrong number of arguments for '{}' command\r\n", name);
+        return stream.write_all(err.as_bytes()).await;
+    }
+
+    let mut map = state.db().lock().await;
+    remove_if_expired(&mut map, &args[0]);
+    let reply = match map.get(&args[0]).map(|entry| entry.expires_at) {
+        None => -2,
+        Some(None) => -1,
+        Some(Some(deadline)) => {
+            let ms = if absolute {
+                deadline
+            } else {
+                deadline.saturating_sub(unix_time_ms())
+            };
+            match (in_ms, absolute) {
+                (true, _) => ms as i64,
+                (false, true) => (ms / 1000) as i64,
+                // Remaining seconds are rounded to the nearest second.
+                (false, false) => ((ms + 500) / 1000) as i64,
+            }
+        }
+    };
+    drop(map);
+
+    stream.write_all(format!(":{}\r\n", reply).as_bytes()).await
+}
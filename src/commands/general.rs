@@ -1,3 +1,5 @@
+use crate::memory;
+use crate::protocol;
 use crate::storage::AppState;
 use std::sync::Arc;
 use tokio::io::AsyncWriteExt;
@@ -8,11 +10,10 @@ pub async fn handle_ping<W: AsyncWriteExt + Unpin>(stream: &mut W) -> std::io::R
 
 pub async fn handle_echo<W: AsyncWriteExt + Unpin>(
     stream: &mut W,
-    args: &[String],
+    args: &[Vec<u8>],
 ) -> std::io::Result<()> {
-    if let Some(arg) = args.get(0) {
-        let data = format!("${}\r\n{}\r\n", arg.len(), arg);
-        stream.write_all(data.as_bytes()).await
+    if let Some(arg) = args.first() {
+        stream.write_all(&protocol::bulk_string(arg)).await
     } else {
         stream
             .write_all(b"-ERR wrong number of arguments for 'echo' command\r\n")
@@ -37,10 +38,18 @@ pub async fn handle_info<W: AsyncWriteExt + Unpin>(
     let replid_str = format!("master_replid:{}", state.master_replication_id);
     // Part 3: Replication Offset
     let reploff_str = format!("master_repl_offset:{}", state.master_replication_offset.lock().await);
+    // Part 4: Memory
+    let memory_str = format!(
+        "used_memory:{}\r\nused_memory_dataset:{}\r\nmaxmemory:{}\r\nmaxmemory_policy:{}",
+        memory::used_memory(),
+        memory::dataset_memory(),
+        state.maxmemory,
+        state.maxmemory_policy.name()
+    );
 
     // --- Construct the final RESP response ---
 
-    let response = format!("{}\r\n{}\r\n{}", role_str, replid_str, reploff_str);
+    let response = format!("{}\r\n{}\r\n{}\r\n{}", role_str, replid_str, reploff_str, memory_str);
 
     stream
         .write_all(format!("${}\r\n{}\r\n", response.len(), response).as_bytes())
@@ -0,0 +1,142 @@
+use crate::hyperloglog::{self, HLL_REGISTERS, INVALID_HLL_ERR};
+use crate::protocol;
+use crate::storage::{lookup_key, AppState, DataStoreValue, Keyspace, ValueEntry};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+
+const TYPE_ERR: &str = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+
+/// Looks up the HyperLogLog stored at `key`, returning the error to reply
+/// with when the key holds something else.
+fn get_hll<'a>(map: &'a Keyspace, key: &[u8]) -> Result<Option<&'a Vec<u8>>, &'static str> {
+    match map.get(key).map(|entry| &entry.value) {
+        None => Ok(None),
+        Some(DataStoreValue::String(val)) if hyperloglog::is_valid(val) => Ok(Some(val)),
+        Some(DataStoreValue::String(_)) => Err(INVALID_HLL_ERR),
+        Some(_) => Err(TYPE_ERR),
+    }
+}
+
+pub async fn handle_pfadd<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    let Some(key) = args.first() else {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'pfadd' command\r\n")
+            .await;
+    };
+
+    let mut map = state.db().lock().await;
+    lookup_key(&mut map, key);
+    if let Err(err) = get_hll(&map, key) {
+        return stream.write_all(err.as_bytes()).await;
+    }
+
+    let mut updated = false;
+    let entry = map.get_or_insert_with(key.to_vec(), || {
+        updated = true;
+        ValueEntry::new(DataStoreValue::String(hyperloglog::new_sparse()), None)
+    });
+    if let DataStoreValue::String(hll) = &mut entry.value {
+        let elements = args[1..].iter().map(|element| element.as_slice());
+        updated |= hyperloglog::add_all(hll, elements);
+    }
+    drop(map);
+
+    if updated {
+        stream.write_all(b":1\r\n").await?;
+        let mut command_with_args = vec![b"PFADD".to_vec()];
+        command_with_args.extend_from_slice(args);
+        protocol::replicate_command(state, command_with_args).await
+    } else {
+        stream.write_all(b":0\r\n").await
+    }
+}
+
+pub async fn handle_pfcount<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.is_empty() {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'pfcount' command\r\n")
+            .await;
+    }
+
+    let mut map = state.db().lock().await;
+    for key in args {
+        lookup_key(&mut map, key);
+        if let Err(err) = get_hll(&map, key) {
+            return stream.write_all(err.as_bytes()).await;
+        }
+    }
+
+    let card = if args.len() == 1 {
+        // A single key can use (and refresh) the cached cardinality.
+        match map.get_mut(&args[0]).map(|entry| &mut entry.value) {
+            Some(DataStoreValue::String(hll)) => hyperloglog::count(hll),
+            _ => 0,
+        }
+    } else {
+        let mut max = vec![0u8; HLL_REGISTERS];
+        for key in args {
+            if let Ok(Some(hll)) = get_hll(&map, key) {
+                hyperloglog::merge_into(&mut max, hll);
+            }
+        }
+        hyperloglog::count_registers(&max)
+    };
+
+    stream.write_all(format!(":{}\r\n", card).as_bytes()).await
+}
+
+pub async fn handle_pfmerge<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    let Some(dest) = args.first() else {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'pfmerge' command\r\n")
+            .await;
+    };
+
+    let mut map = state.db().lock().await;
+    for key in args {
+        lookup_key(&mut map, key);
+        if let Err(err) = get_hll(&map, key) {
+            return stream.write_all(err.as_bytes()).await;
+        }
+    }
+
+    // The destination is part of the union, and the result stays sparse only
+    // if every input was sparse.
+    let mut max = vec![0u8; HLL_REGISTERS];
+    let mut all_sparse = true;
+    for key in args {
+        if let Ok(Some(hll)) = get_hll(&map, key) {
+            all_sparse &= hyperloglog::is_sparse(hll);
+            hyperloglog::merge_into(&mut max, hll);
+        }
+    }
+    let merged = hyperloglog::from_registers(&max, all_sparse);
+
+    match map.get_mut(dest) {
+        Some(entry) => entry.value = DataStoreValue::String(merged),
+        None => {
+            map.insert(
+                dest.to_vec(),
+                ValueEntry::new(DataStoreValue::String(merged), None),
+            );
+        }
+    }
+    drop(map);
+
+    stream.write_all(b"+OK\r\n").await?;
+    let mut command_with_args = vec![b"PFMERGE".to_vec()];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await
+}
@@ -0,0 +1,732 @@
+use crate::dict::Dict;
+use crate::protocol;
+use crate::storage::{
+    lookup_key, remove_if_expired, selected_db, AppState, DataStoreValue, Keyspace, TransactionState,
+    ValueEntry,
+};
+use std::hash::Hash;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::MutexGuard;
+
+const SYNTAX_ERR: &str = "-ERR syntax error\r\n";
+const NO_SUCH_KEY_ERR: &str = "-ERR no such key\r\n";
+const INT_ERR: &str = "-ERR value is not an integer or out of range\r\n";
+const DB_RANGE_ERR: &str = "-ERR DB index is out of range\r\n";
+const SAME_OBJECT_ERR: &str = "-ERR source and destination objects are the same\r\n";
+
+// Values made of more allocations than this are freed on a background task
+// by UNLINK, as with Redis's lazyfree threshold.
+const LAZYFREE_THRESHOLD: usize = 64;
+
+pub async fn handle_del<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    delete(stream, state, args, false).await
+}
+
+pub async fn handle_unlink<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    delete(stream, state, args, true).await
+}
+
+pub async fn handle_exists<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.is_empty() {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'exists' command\r\n")
+            .await;
+    }
+
+    // A key named several times is counted several times.
+    let mut map = state.db().lock().await;
+    let mut count = 0;
+    for key in args {
+        remove_if_expired(&mut map, key);
+        if map.contains_key(key) {
+            count += 1;
+        }
+    }
+    drop(map);
+
+    stream.write_all(format!(":{}\r\n", count).as_bytes()).await
+}
+
+pub async fn handle_touch<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.is_empty() {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'touch' command\r\n")
+            .await;
+    }
+
+    let mut map = state.db().lock().await;
+    let mut count = 0;
+    for key in args {
+        lookup_key(&mut map, key);
+        if map.contains_key(key) {
+            count += 1;
+        }
+    }
+    drop(map);
+
+    stream.write_all(format!(":{}\r\n", count).as_bytes()).await
+}
+
+pub async fn handle_keys<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 1 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'keys' command\r\n")
+            .await;
+    }
+
+    let map = state.db().lock().await;
+    let keys: Vec<Vec<u8>> = map
+        .iter()
+        .filter(|(key, entry)| !entry.is_expired() && glob_match(&args[0], key))
+        .map(|(key, _)| key.to_vec())
+        .collect();
+    drop(map);
+
+    stream
+        .write_all(&protocol::serialize_resp_array(&keys))
+        .await
+}
+
+pub async fn handle_scan<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.is_empty() {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'scan' command\r\n")
+            .await;
+    }
+    let Some(cursor) = protocol::parse_arg::<u64>(&args[0]) else {
+        return stream.write_all(b"-ERR invalid cursor\r\n").await;
+    };
+    let options = match ScanOptions::parse(&args[1..], true) {
+        Ok(options) => options,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+
+    let mut map = state.db().lock().await;
+    let mut keys = Vec::new();
+    let mut expired = Vec::new();
+    let cursor = scan_dict(&map, cursor, options.count, |key, entry| {
+        if entry.is_expired() {
+            expired.push(key.to_vec());
+        } else if options.matches(key)
+            && options
+                .type_name
+                .as_ref()
+                .is_none_or(|name| name.eq_ignore_ascii_case(entry.value.type_name()))
+        {
+            keys.push(key.to_vec());
+        }
+    });
+    for key in expired {
+        remove_if_expired(&mut map, &key);
+    }
+    drop(map);
+
+    stream.write_all(&scan_reply(cursor, &keys)).await
+}
+
+/// The options shared by SCAN and the per-type HSCAN, SSCAN and ZSCAN.
+pub struct ScanOptions {
+    pub pattern: Option<Vec<u8>>,
+    pub count: usize,
+    /// Only accepted by SCAN.
+    pub type_name: Option<String>,
+}
+
+impl ScanOptions {
+    pub fn parse(args: &[Vec<u8>], allow_type: bool) -> Result<ScanOptions, &'static str> {
+        let mut options = ScanOptions {
+            pattern: None,
+            count: 10,
+            type_name: None,
+        };
+        for pair in args.chunks(2) {
+            let [option, value] = pair else {
+                return Err(SYNTAX_ERR);
+            };
+            match protocol::to_upper(option).as_str() {
+                "MATCH" => options.pattern = Some(value.to_vec()),
+                "COUNT" => {
+                    let count = protocol::parse_arg::<i64>(value).ok_or(INT_ERR)?;
+                    if count < 1 {
+                        return Err(SYNTAX_ERR);
+                    }
+                    options.count = count as usize;
+                }
+                "TYPE" if allow_type => {
+                    options.type_name = Some(String::from_utf8_lossy(value).into_owned());
+                }
+                _ => return Err(SYNTAX_ERR),
+            }
+        }
+        Ok(options)
+    }
+
+    pub fn matches(&self, key: &[u8]) -> bool {
+        // A lone `*` matches everything, so skip the matcher for it.
+        match &self.pattern {
+            Some(pattern) if pattern != b"*" => glob_match(pattern, key),
+            _ => true,
+        }
+    }
+}
+
+/// Advances a scan over `dict` from `cursor`, visiting whole buckets until
+/// about `count` entries were seen, and returns the cursor to resume from.
+/// Buckets are mostly short, but a sparse table could have many empty ones in
+/// a row, so at most ten buckets per requested entry are walked in one call.
+pub fn scan_dict<K, V, F>(dict: &Dict<K, V>, mut cursor: u64, count: usize, mut visit: F) -> u64
+where
+    K: Hash + Eq,
+    F: FnMut(&K, &V),
+{
+    let mut seen = 0;
+    let mut buckets_left = count.saturating_mul(10);
+    loop {
+        cursor = dict.scan(cursor, |k, v| {
+            seen += 1;
+            visit(k, v);
+        });
+        buckets_left -= 1;
+        if cursor == 0 || seen >= count || buckets_left == 0 {
+            return cursor;
+        }
+    }
+}
+
+/// Builds the two-element reply of the SCAN family: the next cursor and the
+/// elements returned by this call.
+pub fn scan_reply(cursor: u64, items: &[Vec<u8>]) -> Vec<u8> {
+    let mut response = b"*2\r\n".to_vec();
+    protocol::push_bulk_string(&mut response, cursor.to_string().as_bytes());
+    response.extend_from_slice(&protocol::serialize_resp_array(items));
+    response
+}
+
+pub async fn handle_rename<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    rename(stream, state, args, false).await
+}
+
+pub async fn handle_renamenx<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    rename(stream, state, args, true).await
+}
+
+pub async fn handle_copy<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() < 2 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'copy' command\r\n")
+            .await;
+    }
+    let (source, destination) = (&args[0], &args[1]);
+
+    let mut replace = false;
+    let mut db = selected_db();
+    let mut i = 2;
+    while i < args.len() {
+        match protocol::to_upper(&args[i]).as_str() {
+            "REPLACE" => replace = true,
+            "DB" if i + 1 < args.len() => {
+                db = match parse_db_index(state, &args[i + 1]) {
+                    Ok(db) => db,
+                    Err(err) => return stream.write_all(err.as_bytes()).await,
+                };
+                i += 1;
+            }
+            _ => return stream.write_all(SYNTAX_ERR.as_bytes()).await,
+        }
+        i += 1;
+    }
+    if source == destination && db == selected_db() {
+        return stream.write_all(SAME_OBJECT_ERR.as_bytes()).await;
+    }
+
+    let (mut src, mut other) = lock_pair(state, selected_db(), db).await;
+    lookup_key(&mut src, source);
+    let Some(entry) = src.get(source) else {
+        return stream.write_all(b":0\r\n").await;
+    };
+    let copy = entry.clone();
+    let dst = other.as_deref_mut().unwrap_or(&mut src);
+    lookup_key(dst, destination);
+    if dst.contains_key(destination) && !replace {
+        return stream.write_all(b":0\r\n").await;
+    }
+    dst.insert(destination.to_vec(), copy);
+    drop(other);
+    drop(src);
+
+    stream.write_all(b":1\r\n").await?;
+    let mut command_with_args = vec![b"COPY".to_vec()];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await
+}
+
+pub async fn handle_randomkey<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if !args.is_empty() {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'randomkey' command\r\n")
+            .await;
+    }
+
+    let mut map = state.db().lock().await;
+    let key = random_key(&mut map);
+    drop(map);
+
+    match key {
+        Some(key) => stream.write_all(&protocol::bulk_string(&key)).await,
+        None => stream.write_all(b"$-1\r\n").await,
+    }
+}
+
+pub async fn handle_dbsize<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if !args.is_empty() {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'dbsize' command\r\n")
+            .await;
+    }
+
+    let size = state.db().lock().await.len();
+    stream.write_all(format!(":{}\r\n", size).as_bytes()).await
+}
+
+pub async fn handle_select<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    transation_state: &mut TransactionState,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 1 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'select' command\r\n")
+            .await;
+    }
+
+    match parse_db_index(state, &args[0]) {
+        Ok(db) => {
+            transation_state.selected_db = db;
+            stream.write_all(b"+OK\r\n").await
+        }
+        Err(err) => stream.write_all(err.as_bytes()).await,
+    }
+}
+
+pub async fn handle_move<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 2 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'move' command\r\n")
+            .await;
+    }
+    let key = &args[0];
+    let db = match parse_db_index(state, &args[1]) {
+        Ok(db) => db,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    if db == selected_db() {
+        return stream.write_all(SAME_OBJECT_ERR.as_bytes()).await;
+    }
+
+    // A key that already exists in the target database is left alone.
+    let (mut src, dst) = lock_pair(state, selected_db(), db).await;
+    let mut dst = dst.unwrap();
+    lookup_key(&mut src, key);
+    lookup_key(&mut dst, key);
+    if !src.contains_key(key) || dst.contains_key(key) {
+        return stream.write_all(b":0\r\n").await;
+    }
+    let entry = src.remove(key).unwrap();
+    dst.insert(key.to_vec(), entry);
+    drop(src);
+    drop(dst);
+
+    stream.write_all(b":1\r\n").await?;
+    let mut command_with_args = vec![b"MOVE".to_vec()];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await
+}
+
+pub async fn handle_swapdb<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 2 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'swapdb' command\r\n")
+            .await;
+    }
+    let Some(first) = protocol::parse_arg::<i64>(&args[0]) else {
+        return stream.write_all(b"-ERR invalid first DB index\r\n").await;
+    };
+    let Some(second) = protocol::parse_arg::<i64>(&args[1]) else {
+        return stream.write_all(b"-ERR invalid second DB index\r\n").await;
+    };
+    let in_range = |index: i64| index >= 0 && (index as usize) < state.databases.len();
+    if !in_range(first) || !in_range(second) {
+        return stream.write_all(DB_RANGE_ERR.as_bytes()).await;
+    }
+
+    // Connections keep their index, so they see the other database's data
+    // from now on.
+    let (mut a, b) = lock_pair(state, first as usize, second as usize).await;
+    if let Some(mut b) = b {
+        std::mem::swap(&mut *a, &mut *b);
+    }
+    drop(a);
+
+    stream.write_all(b"+OK\r\n").await?;
+    let mut command_with_args = vec![b"SWAPDB".to_vec()];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await
+}
+
+pub async fn handle_flushdb<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    flush(stream, state, args, false).await
+}
+
+pub async fn handle_flushall<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    flush(stream, state, args, true).await
+}
+
+/// Shared implementation of FLUSHDB and FLUSHALL. With ASYNC the old contents
+/// are dropped on a blocking task instead of by the connection.
+async fn flush<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+    all: bool,
+) -> std::io::Result<()> {
+    let lazy = match args {
+        [] => false,
+        [mode] => match protocol::to_upper(mode).as_str() {
+            "ASYNC" => true,
+            "SYNC" => false,
+            _ => return stream.write_all(SYNTAX_ERR.as_bytes()).await,
+        },
+        _ => return stream.write_all(SYNTAX_ERR.as_bytes()).await,
+    };
+
+    let databases = if all {
+        &state.databases[..]
+    } else {
+        std::slice::from_ref(state.db())
+    };
+    let mut flushed = Vec::new();
+    for db in databases {
+        flushed.push(std::mem::take(&mut *db.lock().await));
+    }
+    if lazy {
+        tokio::task::spawn_blocking(move || drop(flushed));
+    } else {
+        drop(flushed);
+    }
+
+    stream.write_all(b"+OK\r\n").await?;
+    let command = if all {
+        b"FLUSHALL".to_vec()
+    } else {
+        b"FLUSHDB".to_vec()
+    };
+    let mut command_with_args = vec![command];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await
+}
+
+fn parse_db_index(state: &AppState, arg: &[u8]) -> Result<usize, &'static str> {
+    let index = protocol::parse_arg::<i64>(arg).ok_or(INT_ERR)?;
+    if index < 0 || index as usize >= state.databases.len() {
+        return Err(DB_RANGE_ERR);
+    }
+    Ok(index as usize)
+}
+
+/// Locks databases `a` and `b`, always in index order so two commands locking
+/// the same pair can't deadlock. The second guard is `None` when `a == b`.
+async fn lock_pair(
+    state: &AppState,
+    a: usize,
+    b: usize,
+) -> (MutexGuard<'_, Keyspace>, Option<MutexGuard<'_, Keyspace>>) {
+    if a == b {
+        (state.databases[a].lock().await, None)
+    } else if a < b {
+        let first = state.databases[a].lock().await;
+        (first, Some(state.databases[b].lock().await))
+    } else {
+        let second = state.databases[b].lock().await;
+        (state.databases[a].lock().await, Some(second))
+    }
+}
+
+/// Picks a random live key, removing any expired ones it lands on.
+fn random_key(map: &mut Keyspace) -> Option<Vec<u8>> {
+    while let Some((key, _)) = map.random_entry() {
+        let key = key.to_vec();
+        remove_if_expired(map, &key);
+        if map.contains_key(&key) {
+            return Some(key);
+        }
+    }
+    None
+}
+
+/// Shared implementation of DEL and UNLINK. UNLINK hands large values to a
+/// blocking task so freeing them doesn't hold up the connection.
+async fn delete<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+    lazy: bool,
+) -> std::io::Result<()> {
+    if args.is_empty() {
+        let name = if lazy { "unlink" } else { "del" };
+        let err = format!("-ERR wrong number of arguments for '{}' command\r\n", name);
+        return stream.write_all(err.as_bytes()).await;
+    }
+
+    let mut map = state.db().lock().await;
+    let mut deleted = 0;
+    let mut large_values = Vec::new();
+    for key in args {
+        remove_if_expired(&mut map, key);
+        if let Some(entry) = map.remove(key) {
+            deleted += 1;
+            if lazy && free_effort(&entry) > LAZYFREE_THRESHOLD {
+                large_values.push(entry);
+            }
+        }
+    }
+    drop(map);
+
+    if !large_values.is_empty() {
+        tokio::task::spawn_blocking(move || drop(large_values));
+    }
+
+    stream
+        .write_all(format!(":{}\r\n", deleted).as_bytes())
+        .await?;
+    if deleted > 0 {
+        let command = if lazy {
+            b"UNLINK".to_vec()
+        } else {
+            b"DEL".to_vec()
+        };
+        let mut command_with_args = vec![command];
+        command_with_args.extend_from_slice(args);
+        protocol::replicate_command(state, command_with_args).await?;
+    }
+    Ok(())
+}
+
+/// Roughly how many allocations freeing the value takes.
+fn free_effort(entry: &ValueEntry) -> usize {
+    match &entry.value {
+        DataStoreValue::String(_) => 1,
+        DataStoreValue::List(list) => list.len(),
+        DataStoreValue::Stream(stream) => stream.entries.len(),
+    }
+}
+
+/// Shared implementation of RENAME and RENAMENX. The value keeps its TTL.
+async fn rename<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+    nx: bool,
+) -> std::io::Result<()> {
+    let name = if nx { "renamenx" } else { "rename" };
+    if args.len() != 2 {
+        let err = format!("-ERR wrong number of arguments for '{}' command\r\n", name);
+        return stream.write_all(err.as_bytes()).await;
+    }
+    let (source, destination) = (&args[0], &args[1]);
+
+    let mut map = state.db().lock().await;
+    lookup_key(&mut map, source);
+    lookup_key(&mut map, destination);
+    if !map.contains_key(source) {
+        return stream.write_all(NO_SUCH_KEY_ERR.as_bytes()).await;
+    }
+    if nx && map.contains_key(destination) {
+        return stream.write_all(b":0\r\n").await;
+    }
+    if source != destination {
+        let entry = map.remove(source).unwrap();
+        map.insert(destination.to_vec(), entry);
+    }
+    drop(map);
+
+    if nx {
+        stream.write_all(b":1\r\n").await?;
+    } else {
+        stream.write_all(b"+OK\r\n").await?;
+    }
+    let mut command_with_args = vec![name.to_uppercase().into_bytes()];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await
+}
+
+/// Matches `string` against a glob-style pattern, supporting `*`, `?`,
+/// `[...]` classes with ranges and `^` negation, and `\` escapes.
+///
+/// Only the last `*` is ever backtracked to: whatever an earlier one matched,
+/// the later one can take up instead. That keeps matching within
+/// O(pattern * string) even for patterns like `*a*a*a*b`, which KEYS and SCAN
+/// run while holding the database lock.
+pub fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // The pattern after the last `*` seen, and where in the string it was
+    // last tried from.
+    let mut star: Option<(usize, usize)> = None;
+    while s < string.len() {
+        if pattern.get(p) == Some(&b'*') {
+            while pattern.get(p) == Some(&b'*') {
+                p += 1;
+            }
+            if p == pattern.len() {
+                return true;
+            }
+            star = Some((p, s));
+            continue;
+        }
+        if let Some(next) = match_one(pattern, p, string[s]) {
+            p = next;
+            s += 1;
+            continue;
+        }
+        // Let the last star take one more byte and try again from there.
+        let Some((star_p, star_s)) = star else {
+            return false;
+        };
+        star = Some((star_p, star_s + 1));
+        p = star_p;
+        s = star_s + 1;
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Matches `c` against the pattern element at `p`, returning where the next
+/// element starts if it matches.
+fn match_one(pattern: &[u8], mut p: usize, c: u8) -> Option<usize> {
+    match *pattern.get(p)? {
+        b'?' => Some(p + 1),
+        b'[' => {
+            p += 1;
+            let negate = pattern.get(p) == Some(&b'^');
+            if negate {
+                p += 1;
+            }
+            let mut matched = false;
+            while p < pattern.len() && pattern[p] != b']' {
+                if pattern[p] == b'\\' && p + 1 < pattern.len() {
+                    p += 1;
+                    matched |= pattern[p] == c;
+                } else if pattern.get(p + 1) == Some(&b'-') && p + 2 < pattern.len() {
+                    let (mut start, mut end) = (pattern[p], pattern[p + 2]);
+                    if start > end {
+                        std::mem::swap(&mut start, &mut end);
+                    }
+                    matched |= (start..=end).contains(&c);
+                    p += 2;
+                } else {
+                    matched |= pattern[p] == c;
+                }
+                p += 1;
+            }
+            // An unterminated class ends with the pattern.
+            (matched != negate).then_some((p + 1).min(pattern.len()))
+        }
+        b'\\' if p + 1 < pattern.len() => (pattern[p + 1] == c).then_some(p + 2),
+        literal => (literal == c).then_some(p + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_patterns_match() {
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"h?llo", b"hello"));
+        assert!(glob_match(b"h*llo", b"heeeello"));
+        assert!(glob_match(b"h*llo", b"hllo"));
+        assert!(!glob_match(b"h*llo", b"hellow"));
+        assert!(glob_match(b"*llo*", b"hellow"));
+        assert!(glob_match(b"a*b*c", b"aXbYbZc"));
+        assert!(!glob_match(b"a*b*c", b"aXbYbZ"));
+        assert!(glob_match(b"h[ae]llo", b"hallo"));
+        assert!(!glob_match(b"h[^e]llo", b"hello"));
+        assert!(glob_match(b"h[a-b]llo", b"hbllo"));
+        assert!(glob_match(b"h[b-a]llo", b"hallo"));
+        assert!(glob_match(b"h\\*llo", b"h*llo"));
+        assert!(!glob_match(b"h\\*llo", b"hello"));
+        assert!(glob_match(b"ab[c", b"abc"));
+        assert!(!glob_match(b"?", b""));
+    }
+
+    #[test]
+    fn pathological_patterns_match_quickly() {
+        // Backtracking into every star takes exponential time on this.
+        let pattern = b"*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*b";
+        let string = [b'a'; 60];
+        let start = std::time::Instant::now();
+        assert!(!glob_match(pattern, &string));
+        assert!(start.elapsed() < std::time::Duration::from_millis(100));
+    }
+}
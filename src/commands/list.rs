@@ -1,326 +1,1024 @@
 use crate::protocol;
-use crate::storage::{AppState, BlockedSender, DataStoreValue, ValueEntry};
-use nanoid::nanoid;
-use std::cmp::{max, min};
-use std::fmt::Write;
+use crate::storage::{
+    lookup_key, selected_db, AppState, BlockedSender, DataStoreValue, Keyspace, TransactionState,
+    ValueEntry,
+};
+use std::collections::VecDeque;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::io::AsyncWriteExt;
 use tokio::sync::oneshot;
 use tokio::time::timeout;
 
+const TYPE_ERR: &str = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+const INT_ERR: &str = "-ERR value is not an integer or out of range\r\n";
+const SYNTAX_ERR: &str = "-ERR syntax error\r\n";
+const POSITIVE_ERR: &str = "-ERR value is out of range, must be positive\r\n";
+const NO_SUCH_KEY_ERR: &str = "-ERR no such key\r\n";
+const INDEX_ERR: &str = "-ERR index out of range\r\n";
+const NUMKEYS_ERR: &str = "-ERR numkeys should be greater than 0\r\n";
+const COUNT_ERR: &str = "-ERR count should be greater than 0\r\n";
+const TIMEOUT_ERR: &str = "-ERR timeout is not a float or out of range\r\n";
+const NEGATIVE_TIMEOUT_ERR: &str = "-ERR timeout is negative\r\n";
+
+/// Shared implementation of LPUSH, RPUSH, LPUSHX and RPUSHX. The X variants
+/// only push onto a list that already exists.
 pub async fn handle_lpush_rpush<W: AsyncWriteExt + Unpin>(
     command: &str,
     stream: &mut W,
     state: &Arc<AppState>,
-    args: &[String],
+    args: &[Vec<u8>],
 ) -> std::io::Result<()> {
-    let type_err = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
-    if let (Some(key), Some(_value)) = (args.get(0), args.get(1)) {
-        let mut db_map = state.db.lock().await;
-        let entry = db_map.entry(key.to_string()).or_insert_with(|| ValueEntry {
-            value: DataStoreValue::List(Vec::new()),
-            expires_at: None,
+    if args.len() < 2 {
+        let err = format!(
+            "-ERR wrong number of arguments for '{}' command\r\n",
+            command.to_lowercase()
+        );
+        return stream.write_all(err.as_bytes()).await;
+    }
+    let key = &args[0];
+    let left = command.starts_with('L');
+    let only_existing = command.ends_with('X');
+
+    let mut db_map = state.db().lock().await;
+    lookup_key(&mut db_map, key);
+    if !only_existing {
+        db_map.get_or_insert_with(key.to_vec(), || {
+            ValueEntry::new(DataStoreValue::List(VecDeque::new()), None)
         });
+    }
+    let list = match db_map.get_mut(key).map(|entry| &mut entry.value) {
+        None => return stream.write_all(b":0\r\n").await,
+        Some(DataStoreValue::List(list)) => list,
+        Some(_) => return stream.write_all(TYPE_ERR.as_bytes()).await,
+    };
 
-        if let DataStoreValue::List(list) = &mut entry.value {
-            // Check if there is a blocked client BEFORE pushing the data.
-            // If so, we can wake them up.
-            let mut client_to_wake = None;
-            {
-                // Scoped lock for blocked_clients
-                let mut blocked_map = state.blocked_clients.lock().await;
-                if let Some(queue) = blocked_map.get_mut(key) {
-                    if !queue.is_empty() {
-                        // Get the sender, but don't remove it yet.
-                        client_to_wake = queue.pop_front();
-                    }
-                    if queue.is_empty() {
-                        blocked_map.remove(key);
-                    }
-                }
-            }
+    for element in &args[1..] {
+        if left {
+            list.push_front(element.to_vec());
+        } else {
+            list.push_back(element.to_vec());
+        }
+    }
+    let response = format!(":{}\r\n", list.len());
+    let served = serve_blocked(state, &mut db_map, key).await;
+    drop(db_map);
+    stream.write_all(response.as_bytes()).await?;
 
-            // Now, perform the push operations
-            for element in &args[1..] {
-                if command == "LPUSH" {
-                    list.insert(0, element.to_string());
-                } else {
-                    list.push(element.to_string());
-                }
-            }
+    let mut command_with_args = vec![command.as_bytes().to_vec()];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await?;
+    for command in served {
+        protocol::replicate_command(state, command).await?;
+    }
+    Ok(())
+}
 
-            // Wake the client *after* data is pushed.
-            if let Some(waiter) = client_to_wake {
-                // The receiving BLPOP will handle popping the data.
-                let _ = waiter.sender.send(());
-            }
+pub async fn handle_lrange<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 3 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'lrange' command\r\n")
+            .await;
+    }
+    let (Some(start), Some(end)) = (
+        protocol::parse_arg::<i64>(&args[1]),
+        protocol::parse_arg::<i64>(&args[2]),
+    ) else {
+        return stream.write_all(INT_ERR.as_bytes()).await;
+    };
+
+    let mut map = state.db().lock().await;
+    let list = match lookup_list_mut(&mut map, &args[0]) {
+        Ok(Some(list)) => list,
+        Ok(None) => return stream.write_all(b"*0\r\n").await,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let Some((start, end)) = range_bounds(start, end, list.len()) else {
+        return stream.write_all(b"*0\r\n").await;
+    };
+    let mut response = format!("*{}\r\n", end - start + 1).into_bytes();
+    for element in list.range(start..=end) {
+        protocol::push_bulk_string(&mut response, element);
+    }
+    drop(map);
 
-            let response = format!(":{}\r\n", list.len());
-            let _ = stream.write_all(response.as_bytes()).await;
+    stream.write_all(&response).await
+}
 
-            let mut command_with_args = if command == "LPUSH" {
-                vec!["LPUSH".to_string()]
-            } else {
-                vec!["RPUSH".to_string()]
-            };
-            command_with_args.extend_from_slice(args);
-            protocol::replicate_command(state, command_with_args).await?;
-            return Ok(());
-        } else {
-            stream.write_all(type_err.as_bytes()).await
-        }
+pub async fn handle_llen<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 1 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'llen' command\r\n")
+            .await;
+    }
+
+    let mut map = state.db().lock().await;
+    let response = match lookup_list_mut(&mut map, &args[0]) {
+        Ok(list) => format!(":{}\r\n", list.map_or(0, |list| list.len())),
+        Err(err) => err.to_string(),
+    };
+    drop(map);
+
+    stream.write_all(response.as_bytes()).await
+}
+
+/// Shared implementation of LPOP and RPOP. Without a count a single element
+/// is returned as a bulk string, with one an array of up to that many.
+pub async fn handle_lpop_rpop<W: AsyncWriteExt + Unpin>(
+    command: &str,
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.is_empty() || args.len() > 2 {
+        let err = format!(
+            "-ERR wrong number of arguments for '{}' command\r\n",
+            command.to_lowercase()
+        );
+        return stream.write_all(err.as_bytes()).await;
+    }
+    let key = &args[0];
+    let count = match args.get(1).map(|count| protocol::parse_arg::<i64>(count)) {
+        None => None,
+        Some(Some(count)) if count >= 0 => Some(count as usize),
+        Some(_) => return stream.write_all(POSITIVE_ERR.as_bytes()).await,
+    };
+    let end = if command == "LPOP" {
+        End::Left
     } else {
-        stream
-            .write_all(b"-ERR wrong number of arguments for command\r\n")
-            .await
+        End::Right
+    };
+
+    let mut map = state.db().lock().await;
+    let list = match lookup_list_mut(&mut map, key) {
+        Ok(Some(list)) => list,
+        Ok(None) if count.is_some() => return stream.write_all(b"*-1\r\n").await,
+        Ok(None) => return stream.write_all(b"$-1\r\n").await,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    if count == Some(0) {
+        return stream.write_all(b"*0\r\n").await;
+    }
+
+    let mut popped = Vec::new();
+    while popped.len() < count.unwrap_or(1) {
+        match end.pop(list) {
+            Some(element) => popped.push(element),
+            None => break,
+        }
+    }
+    if list.is_empty() {
+        map.remove(key);
     }
+    drop(map);
+
+    let response = match count {
+        Some(_) => protocol::serialize_resp_array(&popped),
+        None => protocol::bulk_string(&popped[0]),
+    };
+    stream.write_all(&response).await?;
+    let mut command_with_args = vec![command.as_bytes().to_vec()];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await
 }
 
-pub async fn handle_lrange<W: AsyncWriteExt + Unpin>(
+pub async fn handle_lindex<W: AsyncWriteExt + Unpin>(
     stream: &mut W,
     state: &Arc<AppState>,
-    args: &[String],
+    args: &[Vec<u8>],
 ) -> std::io::Result<()> {
-    let null = "$-1\r\n";
-    let empty_arr = "*0\r\n";
-    if let (Some(key), Some(start_ind), Some(end_ind)) = (args.get(0), args.get(1), args.get(2)) {
-        let map = state.db.lock().await;
-        if let Some(entry) = map.get(key) {
-            match &entry.value {
-                DataStoreValue::List(val) => {
-                    let mut start = match start_ind.parse::<i32>() {
-                        Ok(s) => s,
-                        Err(_) => {
-                            return stream
-                                .write_all(b"-ERR value is not an integer or out of range\r\n")
-                                .await;
-                        }
-                    };
-
-                    let mut end = match end_ind.parse::<i32>() {
-                        Ok(e) => e,
-                        Err(_) => {
-                            return stream
-                                .write_all(b"-ERR value is not an integer or out of range\r\n")
-                                .await;
-                        }
-                    };
-
-                    let n = val.len() as i32;
-
-                    if start < 0 {
-                        start = n + start;
-                        start = max(start, 0);
-                    }
+    if args.len() != 2 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'lindex' command\r\n")
+            .await;
+    }
+    let Some(index) = protocol::parse_arg::<i64>(&args[1]) else {
+        return stream.write_all(INT_ERR.as_bytes()).await;
+    };
 
-                    if end < 0 {
-                        end = n + end;
-                        start = max(start, 0);
-                    }
+    let mut map = state.db().lock().await;
+    let response = match lookup_list_mut(&mut map, &args[0]) {
+        Ok(Some(list)) => match element_index(index, list.len()) {
+            Some(index) => protocol::bulk_string(&list[index]),
+            None => b"$-1\r\n".to_vec(),
+        },
+        Ok(None) => b"$-1\r\n".to_vec(),
+        Err(err) => err.as_bytes().to_vec(),
+    };
+    drop(map);
 
-                    end = min(end, n - 1);
-                    if start > end || start >= n {
-                        return stream.write_all(empty_arr.as_bytes()).await;
-                    }
+    stream.write_all(&response).await
+}
 
-                    let mut response = format!("*{}\r\n", end - start + 1);
-                    for i in start..=end {
-                        write!(&mut response, "${}\r\n", val[i as usize].len()).unwrap();
-                        write!(&mut response, "{}\r\n", val[i as usize]).unwrap();
-                    }
+pub async fn handle_lset<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 3 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'lset' command\r\n")
+            .await;
+    }
+    let Some(index) = protocol::parse_arg::<i64>(&args[1]) else {
+        return stream.write_all(INT_ERR.as_bytes()).await;
+    };
 
-                    stream.write_all(response.as_bytes()).await
-                }
+    let mut map = state.db().lock().await;
+    let list = match lookup_list_mut(&mut map, &args[0]) {
+        Ok(Some(list)) => list,
+        Ok(None) => return stream.write_all(NO_SUCH_KEY_ERR.as_bytes()).await,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let Some(index) = element_index(index, list.len()) else {
+        return stream.write_all(INDEX_ERR.as_bytes()).await;
+    };
+    list[index] = args[2].clone();
+    drop(map);
 
-                _ => stream.write_all(null.as_bytes()).await,
-            }
-        } else {
-            stream.write_all(empty_arr.as_bytes()).await
-        }
-    } else {
-        stream
-            .write_all(b"-ERR wrong number of arguments for 'lrange' command\r\n")
-            .await
+    stream.write_all(b"+OK\r\n").await?;
+    let mut command_with_args = vec![b"LSET".to_vec()];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await
+}
+
+pub async fn handle_linsert<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 4 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'linsert' command\r\n")
+            .await;
     }
+    let after = match protocol::to_upper(&args[1]).as_str() {
+        "BEFORE" => false,
+        "AFTER" => true,
+        _ => return stream.write_all(SYNTAX_ERR.as_bytes()).await,
+    };
+    let (pivot, element) = (&args[2], &args[3]);
+
+    let mut map = state.db().lock().await;
+    let list = match lookup_list_mut(&mut map, &args[0]) {
+        Ok(Some(list)) => list,
+        Ok(None) => return stream.write_all(b":0\r\n").await,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let Some(position) = list.iter().position(|item| item == pivot) else {
+        return stream.write_all(b":-1\r\n").await;
+    };
+    list.insert(position + after as usize, element.clone());
+    let response = format!(":{}\r\n", list.len());
+    drop(map);
+
+    stream.write_all(response.as_bytes()).await?;
+    let mut command_with_args = vec![b"LINSERT".to_vec()];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await
 }
 
-pub async fn handle_llen<W: AsyncWriteExt + Unpin>(
+/// LREM: a positive count removes that many matches from the head, a
+/// negative one from the tail, and zero removes them all.
+pub async fn handle_lrem<W: AsyncWriteExt + Unpin>(
     stream: &mut W,
     state: &Arc<AppState>,
-    args: &[String],
+    args: &[Vec<u8>],
 ) -> std::io::Result<()> {
-    if let Some(key) = args.get(0) {
-        let map = state.db.lock().await;
-        if let Some(entry) = map.get(key) {
-            match &entry.value {
-                DataStoreValue::List(val) => {
-                    let response = format!(":{}\r\n", val.len());
-                    stream.write_all(response.as_bytes()).await
-                }
+    if args.len() != 3 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'lrem' command\r\n")
+            .await;
+    }
+    let Some(count) = protocol::parse_arg::<i64>(&args[1]) else {
+        return stream.write_all(INT_ERR.as_bytes()).await;
+    };
+    let key = &args[0];
+    let element = &args[2];
 
-                _ => stream.write_all(b":0\r\n").await,
-            }
-        } else {
-            stream.write_all(b":0\r\n").await
-        }
+    let mut map = state.db().lock().await;
+    let list = match lookup_list_mut(&mut map, key) {
+        Ok(Some(list)) => list,
+        Ok(None) => return stream.write_all(b":0\r\n").await,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let limit = match count.unsigned_abs() {
+        0 => usize::MAX,
+        limit => limit as usize,
+    };
+    // Counting from the tail is the same as keeping the first matches that
+    // won't be removed.
+    let keep = if count < 0 {
+        let matches = list.iter().filter(|item| *item == element).count();
+        matches.saturating_sub(limit)
     } else {
-        stream
-            .write_all(b"-ERR wrong number of arguments for 'llen' command\r\n")
-            .await
+        0
+    };
+    let (mut seen, mut removed) = (0, 0);
+    list.retain(|item| {
+        if item != element {
+            return true;
+        }
+        seen += 1;
+        if seen <= keep || removed == limit {
+            return true;
+        }
+        removed += 1;
+        false
+    });
+    if list.is_empty() {
+        map.remove(key);
+    }
+    drop(map);
+
+    stream
+        .write_all(format!(":{}\r\n", removed).as_bytes())
+        .await?;
+    if removed > 0 {
+        let mut command_with_args = vec![b"LREM".to_vec()];
+        command_with_args.extend_from_slice(args);
+        protocol::replicate_command(state, command_with_args).await?;
     }
+    Ok(())
 }
 
-pub async fn handle_lpop<W: AsyncWriteExt + Unpin>(
+pub async fn handle_ltrim<W: AsyncWriteExt + Unpin>(
     stream: &mut W,
     state: &Arc<AppState>,
-    args: &[String],
+    args: &[Vec<u8>],
 ) -> std::io::Result<()> {
-    let null = "$-1\r\n";
-    let type_err = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
-    if let Some(key) = args.get(0) {
-        let mut map = state.db.lock().await;
-        if let Some(entry) = map.get_mut(key) {
-            let _ = match &mut entry.value {
-                DataStoreValue::List(val) => {
-                    if let Some(num_of_ele) = args.get(1) {
-                        let num_of_ele = num_of_ele.parse::<u32>().unwrap();
-                        let mut response = format!("*{}\r\n", num_of_ele);
-                        for _ in 0..num_of_ele {
-                            let ele = val.remove(0);
-                            write!(&mut response, "${}\r\n{}\r\n", ele.len(), ele).unwrap();
-                        }
-                        stream.write_all(response.as_bytes()).await
-                    } else {
-                        let ele = val.remove(0);
-                        let response = format!("${}\r\n{}\r\n", ele.len(), ele);
-                        stream.write_all(response.as_bytes()).await
-                    }
-                }
-                _ => stream.write_all(type_err.as_bytes()).await,
-            };
+    if args.len() != 3 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'ltrim' command\r\n")
+            .await;
+    }
+    let (Some(start), Some(end)) = (
+        protocol::parse_arg::<i64>(&args[1]),
+        protocol::parse_arg::<i64>(&args[2]),
+    ) else {
+        return stream.write_all(INT_ERR.as_bytes()).await;
+    };
+    let key = &args[0];
 
-            let mut command_with_args = vec!["LPOP".to_string()];
-            command_with_args.extend_from_slice(args);
-            protocol::replicate_command(state, command_with_args).await?;
-            return Ok(());
-        } else {
-            stream.write_all(null.as_bytes()).await
+    let mut map = state.db().lock().await;
+    let list = match lookup_list_mut(&mut map, key) {
+        Ok(Some(list)) => list,
+        Ok(None) => return stream.write_all(b"+OK\r\n").await,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let len = list.len();
+    match range_bounds(start, end, len) {
+        Some((start, end)) => {
+            list.truncate(end + 1);
+            list.drain(..start);
         }
+        None => list.clear(),
+    }
+    let trimmed = list.len() != len;
+    if list.is_empty() {
+        map.remove(key);
+    }
+    drop(map);
+
+    stream.write_all(b"+OK\r\n").await?;
+    if trimmed {
+        let mut command_with_args = vec![b"LTRIM".to_vec()];
+        command_with_args.extend_from_slice(args);
+        protocol::replicate_command(state, command_with_args).await?;
+    }
+    Ok(())
+}
+
+/// LPOS: the index of the matches of an element. RANK picks which match to
+/// start from, negative ranks searching from the tail; COUNT returns that many
+/// matches (0 for all) as an array; MAXLEN caps how many elements are compared.
+pub async fn handle_lpos<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() < 2 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'lpos' command\r\n")
+            .await;
+    }
+    let (key, element) = (&args[0], &args[1]);
+
+    let mut rank: i64 = 1;
+    let mut count: Option<usize> = None;
+    let mut max_len: usize = 0;
+    for option in args[2..].chunks(2) {
+        let [name, value] = option else {
+            return stream.write_all(SYNTAX_ERR.as_bytes()).await;
+        };
+        let Some(value) = protocol::parse_arg::<i64>(value) else {
+            return stream.write_all(INT_ERR.as_bytes()).await;
+        };
+        let err: &[u8] = match protocol::to_upper(name).as_str() {
+            "RANK" if value == 0 => b"-ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list\r\n",
+            "RANK" if value == i64::MIN => b"-ERR value is out of range, value must between -9223372036854775807 and 9223372036854775807\r\n",
+            "RANK" => {
+                rank = value;
+                continue;
+            }
+            "COUNT" if value < 0 => b"-ERR COUNT can't be negative\r\n",
+            "COUNT" => {
+                count = Some(value as usize);
+                continue;
+            }
+            "MAXLEN" if value < 0 => b"-ERR MAXLEN can't be negative\r\n",
+            "MAXLEN" => {
+                max_len = value as usize;
+                continue;
+            }
+            _ => SYNTAX_ERR.as_bytes(),
+        };
+        return stream.write_all(err).await;
+    }
+
+    let mut map = state.db().lock().await;
+    let list = match lookup_list_mut(&mut map, key) {
+        Ok(Some(list)) => list,
+        Ok(None) if count.is_some() => return stream.write_all(b"*0\r\n").await,
+        Ok(None) => return stream.write_all(b"$-1\r\n").await,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let len = list.len();
+    let scanned = if max_len == 0 { len } else { max_len.min(len) };
+    let indexes: Box<dyn Iterator<Item = usize>> = if rank > 0 {
+        Box::new(0..scanned)
     } else {
-        stream
-            .write_all(b"-ERR wrong number of arguments for 'lpop' command\r\n")
-            .await
+        Box::new((len - scanned..len).rev())
+    };
+    let wanted = match count {
+        None => 1,
+        Some(0) => usize::MAX,
+        Some(count) => count,
+    };
+    let mut skip = rank.unsigned_abs() - 1;
+    let mut matches = Vec::new();
+    for index in indexes {
+        if list[index] != *element {
+            continue;
+        }
+        if skip > 0 {
+            skip -= 1;
+            continue;
+        }
+        matches.push(index);
+        if matches.len() == wanted {
+            break;
+        }
+    }
+    drop(map);
+
+    let response = match (count, matches.first()) {
+        (None, Some(index)) => format!(":{}\r\n", index),
+        (None, None) => "$-1\r\n".to_string(),
+        (Some(_), _) => {
+            let mut response = format!("*{}\r\n", matches.len());
+            for index in matches {
+                response.push_str(&format!(":{}\r\n", index));
+            }
+            response
+        }
+    };
+    stream.write_all(response.as_bytes()).await
+}
+
+pub async fn handle_lmove<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 4 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'lmove' command\r\n")
+            .await;
+    }
+    let (Some(from), Some(to)) = (End::parse(&args[2]), End::parse(&args[3])) else {
+        return stream.write_all(SYNTAX_ERR.as_bytes()).await;
+    };
+    let op = ListPop::Move {
+        from,
+        to,
+        destination: args[1].clone(),
+    };
+    pop_now(stream, state, &args[..1], &op).await
+}
+
+pub async fn handle_rpoplpush<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 2 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'rpoplpush' command\r\n")
+            .await;
+    }
+    let op = ListPop::Move {
+        from: End::Right,
+        to: End::Left,
+        destination: args[1].clone(),
+    };
+    pop_now(stream, state, &args[..1], &op).await
+}
+
+pub async fn handle_lmpop<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() < 3 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'lmpop' command\r\n")
+            .await;
+    }
+    match parse_lmpop(args) {
+        Ok((keys, op)) => pop_now(stream, state, keys, &op).await,
+        Err(err) => stream.write_all(err.as_bytes()).await,
     }
 }
 
-pub async fn handle_blpop<W: AsyncWriteExt + Unpin>(
+/// Shared implementation of BLPOP and BRPOP.
+pub async fn handle_blpop_brpop<W: AsyncWriteExt + Unpin>(
+    command: &str,
     stream: &mut W,
     state: &Arc<AppState>,
-    args: &[String],
+    transation_state: &TransactionState,
+    args: &[Vec<u8>],
 ) -> std::io::Result<()> {
-    let null = "*-1\r\n";
     if args.len() < 2 {
+        let err = format!(
+            "-ERR wrong number of arguments for '{}' command\r\n",
+            command.to_lowercase()
+        );
+        return stream.write_all(err.as_bytes()).await;
+    }
+    let wait = match parse_timeout(&args[args.len() - 1]) {
+        Ok(wait) => wait,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let end = if command == "BLPOP" {
+        End::Left
+    } else {
+        End::Right
+    };
+    let keys = &args[..args.len() - 1];
+    blocking_pop(
+        stream,
+        state,
+        transation_state,
+        keys,
+        wait,
+        ListPop::Pop(end),
+    )
+    .await
+}
+
+pub async fn handle_blmove<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    transation_state: &TransactionState,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 5 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'blmove' command\r\n")
+            .await;
+    }
+    let (Some(from), Some(to)) = (End::parse(&args[2]), End::parse(&args[3])) else {
+        return stream.write_all(SYNTAX_ERR.as_bytes()).await;
+    };
+    let wait = match parse_timeout(&args[4]) {
+        Ok(wait) => wait,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let op = ListPop::Move {
+        from,
+        to,
+        destination: args[1].clone(),
+    };
+    blocking_pop(stream, state, transation_state, &args[..1], wait, op).await
+}
+
+pub async fn handle_brpoplpush<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    transation_state: &TransactionState,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 3 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'brpoplpush' command\r\n")
+            .await;
+    }
+    let wait = match parse_timeout(&args[2]) {
+        Ok(wait) => wait,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let op = ListPop::Move {
+        from: End::Right,
+        to: End::Left,
+        destination: args[1].clone(),
+    };
+    blocking_pop(stream, state, transation_state, &args[..1], wait, op).await
+}
+
+pub async fn handle_blmpop<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    transation_state: &TransactionState,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() < 4 {
         return stream
-            .write_all(b"-ERR wrong number of arguments for 'blpop' command\r\n")
+            .write_all(b"-ERR wrong number of arguments for 'blmpop' command\r\n")
             .await;
     }
+    let (keys, op) = match parse_lmpop(&args[1..]) {
+        Ok(parsed) => parsed,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let wait = match parse_timeout(&args[0]) {
+        Ok(wait) => wait,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    blocking_pop(stream, state, transation_state, keys, wait, op).await
+}
+
+/// Runs `op` on the first of `keys` that holds a list, replying with the
+/// operation's null reply when none does.
+async fn pop_now<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    keys: &[Vec<u8>],
+    op: &ListPop,
+) -> std::io::Result<()> {
+    let mut map = state.db().lock().await;
+    for key in keys {
+        match run_op(state, &mut map, key, op).await {
+            Ok(Some(served)) => {
+                drop(map);
+                return reply_served(stream, state, served).await;
+            }
+            Ok(None) => {}
+            Err(err) => return stream.write_all(err.as_bytes()).await,
+        }
+    }
+    drop(map);
 
-    let timeout_secs = match args.last().unwrap().parse::<f32>() {
-        Ok(t) => t,
-        Err(_) => {
-            return stream
-                .write_all(b"-ERR value is not an integer or out of range\r\n")
-                .await;
+    stream.write_all(op.null_reply()).await
+}
+
+/// Like `pop_now`, but when none of `keys` holds a list the client waits on
+/// all of them at once, until a push hands it what it asked for or `wait`
+/// runs out (`None` waits forever). Inside EXEC it never blocks.
+async fn blocking_pop<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    transation_state: &TransactionState,
+    keys: &[Vec<u8>],
+    wait: Option<Duration>,
+    op: ListPop,
+) -> std::io::Result<()> {
+    let mut map = state.db().lock().await;
+    for key in keys {
+        match run_op(state, &mut map, key, &op).await {
+            Ok(Some(served)) => {
+                drop(map);
+                return reply_served(stream, state, served).await;
+            }
+            Ok(None) => {}
+            Err(err) => return stream.write_all(err.as_bytes()).await,
         }
+    }
+    let null_reply = op.null_reply();
+    if transation_state.in_exec {
+        drop(map);
+        return stream.write_all(null_reply).await;
+    }
+
+    // Queued before the database is unlocked, so a push can't land between
+    // the check above and the client starting to wait.
+    let (sender, mut receiver) = oneshot::channel();
+    let id = state.blocked_clients.lock().await.block(BlockedSender {
+        db: selected_db(),
+        keys: keys.to_vec(),
+        op,
+        sender,
+    });
+    drop(map);
+
+    let reply = match wait {
+        None => receiver.await.ok(),
+        Some(wait) => match timeout(wait, &mut receiver).await {
+            Ok(reply) => reply.ok(),
+            // A client that was taken off the queues has been served just as
+            // it timed out, and its reply is already in the channel.
+            Err(_) => match state.blocked_clients.lock().await.unblock(id) {
+                Some(_) => None,
+                None => receiver.await.ok(),
+            },
+        },
     };
+    stream
+        .write_all(reply.as_deref().unwrap_or(null_reply))
+        .await
+}
+
+/// Runs `op` on the list at `key`. An element moved onto another list is
+/// offered to the clients blocked on that list, whose commands are propagated
+/// after this one.
+async fn run_op(
+    state: &AppState,
+    map: &mut Keyspace,
+    key: &[u8],
+    op: &ListPop,
+) -> Result<Option<Served>, &'static str> {
+    let Some((reply, mut propagated)) = op.run(map, key)? else {
+        return Ok(None);
+    };
+    if let ListPop::Move { destination, .. } = op {
+        propagated.extend(serve_blocked(state, map, destination).await);
+    }
+    Ok(Some((reply, propagated)))
+}
 
-    for key in &args[0..(args.len() - 1)] {
-        let mut db_map = state.db.lock().await;
-        if let Some(entry) = db_map.get_mut(key) {
-            if let DataStoreValue::List(val) = &mut entry.value {
-                if !val.is_empty() {
-                    let ele = val.remove(0);
-                    let response = format!(
-                        "*2\r\n${}\r\n{}\r\n${}\r\n{}\r\n",
-                        key.len(),
-                        key,
-                        ele.len(),
-                        ele
-                    );
-                    return stream.write_all(response.as_bytes()).await; // Early return, no blocking needed
+async fn reply_served<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    (reply, propagated): Served,
+) -> std::io::Result<()> {
+    stream.write_all(&reply).await?;
+    for command in propagated {
+        protocol::replicate_command(state, command).await?;
+    }
+    Ok(())
+}
+
+/// Hands the list at `key` to the clients blocked on it, longest waiting
+/// first, until it runs out or nobody is left waiting. Each client's pop or
+/// move runs right here and its reply is sent to it, so nothing can take the
+/// elements in between. Elements moved onto another list are offered to the
+/// clients blocked on that one in turn. Returns the commands the served
+/// clients ran, to be propagated after the push that served them.
+async fn serve_blocked(state: &AppState, map: &mut Keyspace, key: &[u8]) -> Vec<Vec<Vec<u8>>> {
+    let db = selected_db();
+    let mut propagated = Vec::new();
+    let mut blocked = state.blocked_clients.lock().await;
+    let mut ready = vec![key.to_vec()];
+    while let Some(key) = ready.pop() {
+        while map.contains_key(&key) {
+            let Some(waiter) = blocked.pop_waiter(db, &key) else {
+                break;
+            };
+            if waiter.sender.is_closed() {
+                continue;
+            }
+            let reply = match waiter.op.run(map, &key) {
+                Ok(Some((reply, commands))) => {
+                    if let ListPop::Move { destination, .. } = &waiter.op {
+                        ready.push(destination.clone());
+                    }
+                    propagated.extend(commands);
+                    reply
                 }
+                Ok(None) => waiter.op.null_reply().to_vec(),
+                Err(err) => err.as_bytes().to_vec(),
+            };
+            let _ = waiter.sender.send(reply);
+        }
+    }
+    propagated
+}
+
+/// Parses the `numkeys key [key ...] LEFT|RIGHT [COUNT count]` arguments of
+/// LMPOP and BLMPOP.
+fn parse_lmpop(args: &[Vec<u8>]) -> Result<(&[Vec<u8>], ListPop), &'static str> {
+    let numkeys = match protocol::parse_arg::<i64>(&args[0]) {
+        Some(numkeys) if numkeys > 0 => numkeys as usize,
+        _ => return Err(NUMKEYS_ERR),
+    };
+    if numkeys >= args.len() - 1 {
+        return Err(SYNTAX_ERR);
+    }
+    let end = End::parse(&args[numkeys + 1]).ok_or(SYNTAX_ERR)?;
+    let count = match &args[numkeys + 2..] {
+        [] => 1,
+        [option, count] if option.eq_ignore_ascii_case(b"COUNT") => {
+            match protocol::parse_arg::<i64>(count) {
+                Some(count) if count > 0 => count as usize,
+                _ => return Err(COUNT_ERR),
             }
         }
-        // --- IMPORTANT: Drop the lock before waiting ---
-        drop(db_map);
-
-        // --- Step 2: If no data, prepare to block ---
-        let (tx, rx) = oneshot::channel::<()>(); // We only need a signal, not data
-        let blocked_id = nanoid!();
-        {
-            // Lock, modify, and quickly unlock the blocked clients map
-            let mut blocked_map = state.blocked_clients.lock().await;
-            blocked_map
-                .entry(key.to_string())
-                .or_default()
-                .push_back(BlockedSender {
-                    id: blocked_id.clone(),
-                    sender: tx,
-                });
+        _ => return Err(SYNTAX_ERR),
+    };
+    Ok((&args[1..=numkeys], ListPop::MultiPop(end, count)))
+}
+
+/// Parses the timeout of a blocking command in seconds, where 0 means
+/// forever.
+fn parse_timeout(arg: &[u8]) -> Result<Option<Duration>, &'static str> {
+    match protocol::parse_arg::<f64>(arg) {
+        Some(secs) if !secs.is_finite() => Err(TIMEOUT_ERR),
+        Some(secs) if secs < 0.0 => Err(NEGATIVE_TIMEOUT_ERR),
+        Some(0.0) => Ok(None),
+        Some(secs) => Ok(Some(Duration::from_secs_f64(secs))),
+        None => Err(TIMEOUT_ERR),
+    }
+}
+
+/// The reply to a pop or move, and the commands to propagate in its place.
+type Served = (Vec<u8>, Vec<Vec<Vec<u8>>>);
+
+/// An end of a list, as named by LMOVE and LMPOP.
+#[derive(Clone, Copy)]
+pub enum End {
+    Left,
+    Right,
+}
+
+impl End {
+    fn parse(arg: &[u8]) -> Option<End> {
+        match protocol::to_upper(arg).as_str() {
+            "LEFT" => Some(End::Left),
+            "RIGHT" => Some(End::Right),
+            _ => None,
         }
+    }
 
-        // --- Step 3: Wait for the signal (or timeout) ---
-        let wait_result = if timeout_secs == 0.0 {
-            rx.await.map_err(|_| "channel closed")
-        } else {
-            match timeout(Duration::from_secs_f32(timeout_secs), rx).await {
-                Ok(Ok(_)) => Ok(()),                 // Signal received
-                Ok(Err(_)) => Err("channel closed"), // Sender was dropped
-                Err(_) => Err("timeout"),            // Timeout elapsed
+    fn name(self) -> &'static [u8] {
+        match self {
+            End::Left => b"LEFT",
+            End::Right => b"RIGHT",
+        }
+    }
+
+    /// The single-key pop that takes from this end.
+    fn pop_command(self) -> &'static [u8] {
+        match self {
+            End::Left => b"LPOP",
+            End::Right => b"RPOP",
+        }
+    }
+
+    fn pop(self, list: &mut VecDeque<Vec<u8>>) -> Option<Vec<u8>> {
+        match self {
+            End::Left => list.pop_front(),
+            End::Right => list.pop_back(),
+        }
+    }
+
+    fn push(self, list: &mut VecDeque<Vec<u8>>, element: Vec<u8>) {
+        match self {
+            End::Left => list.push_front(element),
+            End::Right => list.push_back(element),
+        }
+    }
+}
+
+/// What the pop and move commands do to the first of their keys that holds
+/// a list. The blocking variants run the same operation once they're served.
+pub enum ListPop {
+    /// BLPOP and BRPOP: one element, replied with its key.
+    Pop(End),
+    /// LMPOP and BLMPOP: up to `count` elements, replied with their key.
+    MultiPop(End, usize),
+    /// LMOVE, BLMOVE, RPOPLPUSH and BRPOPLPUSH: one element pushed onto
+    /// `destination`.
+    Move {
+        from: End,
+        to: End,
+        destination: Vec<u8>,
+    },
+}
+
+impl ListPop {
+    /// Runs the operation on the list at `key`, or returns `None` when there
+    /// is no list there. Whatever the command, what gets propagated is the
+    /// plain pop or LMOVE that was executed, so replicas never block.
+    fn run(&self, map: &mut Keyspace, key: &[u8]) -> Result<Option<Served>, &'static str> {
+        if lookup_list_mut(map, key)?.is_none() {
+            return Ok(None);
+        }
+        if let ListPop::Move { destination, .. } = self {
+            // Checked before popping, so a failed move leaves the source alone.
+            let wrong_type = map.get(destination).is_some_and(|entry| {
+                !entry.is_expired() && !matches!(entry.value, DataStoreValue::List(_))
+            });
+            if wrong_type {
+                return Err(TYPE_ERR);
             }
+        }
+        let Some(DataStoreValue::List(list)) = map.get_mut(key).map(|entry| &mut entry.value)
+        else {
+            return Ok(None);
         };
 
-        // --- Step 4: Handle the result after waking up ---
-        if wait_result.is_ok() {
-            // We were woken up by a push command.
-            // The data is now guaranteed to be in the list.
-            let mut db_map = state.db.lock().await; // Re-acquire the lock
-            if let Some(entry) = db_map.get_mut(key) {
-                if let DataStoreValue::List(val) = &mut entry.value {
-                    if !val.is_empty() {
-                        let ele = val.remove(0);
-                        let response = format!(
-                            "*2\r\n${}\r\n{}\r\n${}\r\n{}\r\n",
-                            key.len(),
-                            key,
-                            ele.len(),
-                            ele
-                        );
-                        stream.write_all(response.as_bytes()).await?;
-                    } else {
-                        // This case is unlikely if woken up correctly, but handle it defensively.
-                        stream.write_all(null.as_bytes()).await?;
-                    }
-                }
+        let (end, count) = match self {
+            ListPop::Pop(end) => (*end, 1),
+            ListPop::MultiPop(end, count) => (*end, *count),
+            ListPop::Move { from, .. } => (*from, 1),
+        };
+        let mut popped = Vec::new();
+        while popped.len() < count {
+            match end.pop(list) {
+                Some(element) => popped.push(element),
+                None => break,
             }
-        } else {
-            // We timed out or the channel was closed.
-            // Clean up the waiting client entry.
-            let mut blocked_map = state.blocked_clients.lock().await;
-            if let Some(queue) = blocked_map.get_mut(key) {
-                if let Some(pos) = queue.iter().position(|bs| bs.id == blocked_id) {
-                    queue.remove(pos);
-                }
-                if queue.is_empty() {
-                    blocked_map.remove(key);
+        }
+        if list.is_empty() {
+            map.remove(key);
+        }
+
+        Ok(Some(match self {
+            ListPop::Pop(end) => (
+                protocol::serialize_resp_array(&[key.to_vec(), popped.remove(0)]),
+                vec![vec![end.pop_command().to_vec(), key.to_vec()]],
+            ),
+            ListPop::MultiPop(end, _) => {
+                let mut reply = b"*2\r\n".to_vec();
+                protocol::push_bulk_string(&mut reply, key);
+                reply.extend_from_slice(&protocol::serialize_resp_array(&popped));
+                let count = popped.len().to_string().into_bytes();
+                (
+                    reply,
+                    vec![vec![end.pop_command().to_vec(), key.to_vec(), count]],
+                )
+            }
+            ListPop::Move {
+                from,
+                to,
+                destination,
+            } => {
+                let element = popped.remove(0);
+                lookup_key(map, destination);
+                let entry = map.get_or_insert_with(destination.clone(), || {
+                    ValueEntry::new(DataStoreValue::List(VecDeque::new()), None)
+                });
+                if let DataStoreValue::List(list) = &mut entry.value {
+                    to.push(list, element.clone());
                 }
+                let command = vec![
+                    b"LMOVE".to_vec(),
+                    key.to_vec(),
+                    destination.clone(),
+                    from.name().to_vec(),
+                    to.name().to_vec(),
+                ];
+                (protocol::bulk_string(&element), vec![command])
             }
-            stream.write_all(null.as_bytes()).await?;
+        }))
+    }
+
+    /// The reply when there is nothing to pop, or a blocked client times out.
+    fn null_reply(&self) -> &'static [u8] {
+        match self {
+            ListPop::Move { .. } => b"$-1\r\n",
+            _ => b"*-1\r\n",
         }
     }
-    let mut command_with_args = vec!["BLPOP".to_string()];
-    command_with_args.extend_from_slice(args);
-    protocol::replicate_command(state, command_with_args).await?;
-    Ok(())
+}
+
+/// Looks up the list stored at `key`, treating expired keys as missing.
+fn lookup_list_mut<'a>(
+    map: &'a mut Keyspace,
+    key: &[u8],
+) -> Result<Option<&'a mut VecDeque<Vec<u8>>>, &'static str> {
+    lookup_key(map, key);
+    match map.get_mut(key).map(|entry| &mut entry.value) {
+        None => Ok(None),
+        Some(DataStoreValue::List(list)) => Ok(Some(list)),
+        Some(_) => Err(TYPE_ERR),
+    }
+}
+
+/// Resolves a possibly negative index into a position in a list of `len`
+/// elements.
+fn element_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { index + len as i64 } else { index };
+    (0..len as i64).contains(&index).then_some(index as usize)
+}
+
+/// Resolves the inclusive `start`/`end` range of LRANGE and LTRIM, where
+/// negative values count from the tail. `None` means the range is empty.
+fn range_bounds(start: i64, end: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (start + len).max(0)
+    } else {
+        start
+    };
+    let end = if end < 0 { end + len } else { end.min(len - 1) };
+    (start <= end && start < len).then_some((start as usize, end as usize))
 }
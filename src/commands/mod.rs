@@ -1,24 +1,59 @@
+pub mod bitmap;
+pub mod consumer_group;
+pub mod dump;
+pub mod expire;
 pub mod general;
+pub mod hyperloglog;
+pub mod keys;
 pub mod list;
+pub mod object;
 pub mod stream;
 pub mod string;
 pub mod transaction;
 pub mod replication;
 pub mod pubsub;
 
-use crate::storage::{AppState, TransactionState};
+use crate::evict;
+use crate::protocol;
+use crate::storage::{AppState, TransactionState, SELECTED_DB};
 use std::sync::Arc;
 use tokio::io::AsyncWriteExt;
 
+// Commands that can grow memory use, which are refused while the server is
+// over `maxmemory` and can't evict anything.
+const DENYOOM_COMMANDS: &[&str] = &[
+    "SET", "SETNX", "SETEX", "PSETEX", "GETSET", "APPEND", "SETRANGE", "MSET", "MSETNX",
+    "INCR", "DECR", "INCRBY", "DECRBY", "INCRBYFLOAT", "COPY", "LPUSH", "RPUSH",
+    "LPUSHX", "RPUSHX", "LINSERT", "LSET", "LMOVE", "RPOPLPUSH", "BLMOVE", "BRPOPLPUSH",
+    "SETBIT", "BITOP", "BITFIELD", "PFADD", "PFMERGE", "XADD", "RESTORE",
+];
+
 // Central function to process commands.
 pub async fn handle_command<W: AsyncWriteExt + Unpin>(
-    parsed: Vec<String>,
+    parsed: Vec<Vec<u8>>,
     stream: &mut W,
     state: &Arc<AppState>,
     transation_state: &mut TransactionState,
     stream_id: String
 ) -> std::io::Result<()> {
-    let command = parsed.get(0).unwrap().to_uppercase();
+    // Handlers reach the connection's database through `state.db()`.
+    let selected_db = transation_state.selected_db;
+    SELECTED_DB
+        .scope(
+            selected_db,
+            dispatch(parsed, stream, state, transation_state, stream_id),
+        )
+        .await
+}
+
+async fn dispatch<W: AsyncWriteExt + Unpin>(
+    parsed: Vec<Vec<u8>>,
+    stream: &mut W,
+    state: &Arc<AppState>,
+    transation_state: &mut TransactionState,
+    stream_id: String
+) -> std::io::Result<()> {
+    let command = protocol::to_upper(parsed.first().unwrap());
     let args = &parsed[1..];
 
     if transation_state.in_transaction
@@ -31,22 +66,122 @@ pub async fn handle_command<W: AsyncWriteExt + Unpin>(
         return Ok(());
     }
 
-    match command.as_str() {
+    // Replicas leave eviction to their master, which sends a DEL for every
+    // key it evicts.
+    if state.replica_of.is_none()
+        && !evict::free_memory_if_needed(state).await
+        && DENYOOM_COMMANDS.contains(&command.as_str())
+    {
+        protocol::propagate_expired(state).await?;
+        return stream
+            .write_all(b"-OOM command not allowed when used memory > 'maxmemory'.\r\n")
+            .await;
+    }
+
+    let result = match command.as_str() {
         "PING" => general::handle_ping(stream).await,
         "ECHO" => general::handle_echo(stream, args).await,
         "INFO" => general::handle_info(stream, state).await,
         "SET" => string::handle_set(stream, state, args).await,
         "GET" => string::handle_get(stream, state, args).await,
         "INCR" => string::handle_incr(stream, state, args).await,
-        "LPUSH" | "RPUSH" => list::handle_lpush_rpush(&command, stream, state, args).await,
+        "DECR" => string::handle_decr(stream, state, args).await,
+        "INCRBY" => string::handle_incrby(stream, state, args).await,
+        "DECRBY" => string::handle_decrby(stream, state, args).await,
+        "INCRBYFLOAT" => string::handle_incrbyfloat(stream, state, args).await,
+        "APPEND" => string::handle_append(stream, state, args).await,
+        "STRLEN" => string::handle_strlen(stream, state, args).await,
+        "GETRANGE" => string::handle_getrange(stream, state, args).await,
+        "SETRANGE" => string::handle_setrange(stream, state, args).await,
+        "MGET" => string::handle_mget(stream, state, args).await,
+        "MSET" => string::handle_mset(stream, state, args).await,
+        "MSETNX" => string::handle_msetnx(stream, state, args).await,
+        "SETNX" => string::handle_setnx(stream, state, args).await,
+        "SETEX" => string::handle_setex(stream, state, args).await,
+        "PSETEX" => string::handle_psetex(stream, state, args).await,
+        "GETSET" => string::handle_getset(stream, state, args).await,
+        "GETDEL" => string::handle_getdel(stream, state, args).await,
+        "GETEX" => string::handle_getex(stream, state, args).await,
+        "LCS" => string::handle_lcs(stream, state, args).await,
+        "DEL" => keys::handle_del(stream, state, args).await,
+        "UNLINK" => keys::handle_unlink(stream, state, args).await,
+        "EXISTS" => keys::handle_exists(stream, state, args).await,
+        "TOUCH" => keys::handle_touch(stream, state, args).await,
+        "KEYS" => keys::handle_keys(stream, state, args).await,
+        "SCAN" => keys::handle_scan(stream, state, args).await,
+        "RENAME" => keys::handle_rename(stream, state, args).await,
+        "RENAMENX" => keys::handle_renamenx(stream, state, args).await,
+        "COPY" => keys::handle_copy(stream, state, args).await,
+        "RANDOMKEY" => keys::handle_randomkey(stream, state, args).await,
+        "DBSIZE" => keys::handle_dbsize(stream, state, args).await,
+        "SELECT" => keys::handle_select(stream, state, transation_state, args).await,
+        "MOVE" => keys::handle_move(stream, state, args).await,
+        "SWAPDB" => keys::handle_swapdb(stream, state, args).await,
+        "FLUSHDB" => keys::handle_flushdb(stream, state, args).await,
+        "FLUSHALL" => keys::handle_flushall(stream, state, args).await,
+        "DUMP" => dump::handle_dump(stream, state, args).await,
+        "RESTORE" => dump::handle_restore(stream, state, args).await,
+        "MIGRATE" => dump::handle_migrate(stream, state, args).await,
+        "OBJECT" => object::handle_object(stream, state, args).await,
+        "MEMORY" => object::handle_memory(stream, state, args).await,
+        "EXPIRE" => expire::handle_expire(stream, state, args).await,
+        "PEXPIRE" => expire::handle_pexpire(stream, state, args).await,
+        "EXPIREAT" => expire::handle_expireat(stream, state, args).await,
+        "PEXPIREAT" => expire::handle_pexpireat(stream, state, args).await,
+        "TTL" => expire::handle_ttl(stream, state, args).await,
+        "PTTL" => expire::handle_pttl(stream, state, args).await,
+        "EXPIRETIME" => expire::handle_expiretime(stream, state, args).await,
+        "PEXPIRETIME" => expire::handle_pexpiretime(stream, state, args).await,
+        "PERSIST" => expire::handle_persist(stream, state, args).await,
+        "LPUSH" | "RPUSH" | "LPUSHX" | "RPUSHX" => {
+            list::handle_lpush_rpush(&command, stream, state, args).await
+        }
         "LRANGE" => list::handle_lrange(stream, state, args).await,
         "LLEN" => list::handle_llen(stream, state, args).await,
-        "LPOP" => list::handle_lpop(stream, state, args).await,
-        "BLPOP" => list::handle_blpop(stream, state, args).await,
+        "LPOP" | "RPOP" => list::handle_lpop_rpop(&command, stream, state, args).await,
+        "LINDEX" => list::handle_lindex(stream, state, args).await,
+        "LSET" => list::handle_lset(stream, state, args).await,
+        "LINSERT" => list::handle_linsert(stream, state, args).await,
+        "LREM" => list::handle_lrem(stream, state, args).await,
+        "LTRIM" => list::handle_ltrim(stream, state, args).await,
+        "LPOS" => list::handle_lpos(stream, state, args).await,
+        "LMOVE" => list::handle_lmove(stream, state, args).await,
+        "RPOPLPUSH" => list::handle_rpoplpush(stream, state, args).await,
+        "LMPOP" => list::handle_lmpop(stream, state, args).await,
+        "BLPOP" | "BRPOP" => {
+            list::handle_blpop_brpop(&command, stream, state, transation_state, args).await
+        }
+        "BLMOVE" => list::handle_blmove(stream, state, transation_state, args).await,
+        "BRPOPLPUSH" => list::handle_brpoplpush(stream, state, transation_state, args).await,
+        "BLMPOP" => list::handle_blmpop(stream, state, transation_state, args).await,
+        "SETBIT" => bitmap::handle_setbit(stream, state, args).await,
+        "GETBIT" => bitmap::handle_getbit(stream, state, args).await,
+        "BITCOUNT" => bitmap::handle_bitcount(stream, state, args).await,
+        "BITPOS" => bitmap::handle_bitpos(stream, state, args).await,
+        "BITOP" => bitmap::handle_bitop(stream, state, args).await,
+        "BITFIELD" => bitmap::handle_bitfield(stream, state, args).await,
+        "BITFIELD_RO" => bitmap::handle_bitfield_ro(stream, state, args).await,
+        "PFADD" => hyperloglog::handle_pfadd(stream, state, args).await,
+        "PFCOUNT" => hyperloglog::handle_pfcount(stream, state, args).await,
+        "PFMERGE" => hyperloglog::handle_pfmerge(stream, state, args).await,
         "TYPE" => stream::handle_type(stream, state, args).await,
         "XADD" => stream::handle_xadd(stream, state, args).await,
-        "XRANGE" => stream::handle_xrange(stream, state, args).await,
-        "XREAD" => stream::handle_xread(stream, state, args).await,
+        "XRANGE" | "XREVRANGE" => {
+            stream::handle_xrange_xrevrange(&command, stream, state, args).await
+        }
+        "XLEN" => stream::handle_xlen(stream, state, args).await,
+        "XINFO" => stream::handle_xinfo(stream, state, args).await,
+        "XTRIM" => stream::handle_xtrim(stream, state, args).await,
+        "XDEL" => stream::handle_xdel(stream, state, args).await,
+        "XREAD" => stream::handle_xread(stream, state, transation_state, args).await,
+        "XGROUP" => consumer_group::handle_xgroup(stream, state, args).await,
+        "XREADGROUP" => {
+            consumer_group::handle_xreadgroup(stream, state, transation_state, args).await
+        }
+        "XACK" => consumer_group::handle_xack(stream, state, args).await,
+        "XPENDING" => consumer_group::handle_xpending(stream, state, args).await,
+        "XCLAIM" => consumer_group::handle_xclaim(stream, state, args).await,
+        "XAUTOCLAIM" => consumer_group::handle_xautoclaim(stream, state, args).await,
         "MULTI" => transaction::handle_multi(stream, transation_state).await,
         "EXEC" => transaction::handle_exec(stream, state, transation_state).await,
         "DISCARD" => transaction::handle_discard(stream, transation_state).await,
@@ -58,9 +193,17 @@ pub async fn handle_command<W: AsyncWriteExt + Unpin>(
             let err_msg = format!(
                 "-ERR unknown command `{}`, with args beginning with: {:?}\r\n",
                 command,
-                args.get(0)
+                args.first().map(|arg| String::from_utf8_lossy(arg))
             );
             stream.write_all(err_msg.as_bytes()).await
         }
-    }
+    };
+
+    // Values the command changed in place are measured again for `maxmemory`.
+    state.db().lock().await.update_memory_usage();
+
+    // Keys that expired while serving a read still have to be deleted on the
+    // replicas.
+    protocol::propagate_expired(state).await?;
+    result
 }
@@ -0,0 +1,281 @@
+use crate::evict::EvictionPolicy;
+use crate::memory;
+use crate::protocol;
+use crate::storage::{remove_if_expired, AppState, MEMORY_USAGE_SAMPLES};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+
+const SYNTAX_ERR: &str = "-ERR syntax error\r\n";
+const INT_ERR: &str = "-ERR value is not an integer or out of range\r\n";
+const POLICY_SWITCH_NOTE: &str = "Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.";
+
+// Below this much memory MEMORY DOCTOR has nothing useful to say.
+const DOCTOR_MIN_MEMORY: usize = 5 * 1024 * 1024;
+
+const OBJECT_HELP: &[&str] = &[
+    "OBJECT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+    "ENCODING <key>",
+    "    Return the kind of internal representation used in order to store the value",
+    "    associated with a <key>.",
+    "FREQ <key>",
+    "    Return the access frequency index of the <key>. The returned integer is",
+    "    proportional to the logarithm of the recent access frequency of the key.",
+    "IDLETIME <key>",
+    "    Return the idle time of the <key>, that is the approximated number of",
+    "    seconds elapsed since the last access to the key.",
+    "REFCOUNT <key>",
+    "    Return the number of references of the value associated with the specified",
+    "    <key>.",
+    "HELP",
+    "    Print this help.",
+];
+
+const MEMORY_HELP: &[&str] = &[
+    "MEMORY <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+    "DOCTOR",
+    "    Return memory problems reports.",
+    "STATS",
+    "    Return information about the memory usage of the server.",
+    "USAGE <key> [SAMPLES <count>]",
+    "    Return memory in bytes used by <key> and its value. Nested values are",
+    "    sampled up to <count> times (default: 5, 0 means sample all).",
+    "HELP",
+    "    Print this help.",
+];
+
+pub async fn handle_object<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    let Some(subcommand) = args.first() else {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'object' command\r\n")
+            .await;
+    };
+    let subcommand = protocol::to_upper(subcommand);
+    if subcommand == "HELP" {
+        return stream.write_all(&help_reply(OBJECT_HELP)).await;
+    }
+    if !matches!(
+        subcommand.as_str(),
+        "ENCODING" | "FREQ" | "IDLETIME" | "REFCOUNT"
+    ) {
+        return stream
+            .write_all(unknown_subcommand(&args[0], "OBJECT").as_bytes())
+            .await;
+    }
+    if args.len() != 2 {
+        let err = format!(
+            "-ERR wrong number of arguments for 'object|{}' command\r\n",
+            subcommand.to_lowercase()
+        );
+        return stream.write_all(err.as_bytes()).await;
+    }
+
+    let lfu = matches!(
+        state.maxmemory_policy,
+        EvictionPolicy::AllKeysLfu | EvictionPolicy::VolatileLfu
+    );
+
+    // Looking at a key doesn't count as an access to it.
+    let mut map = state.db().lock().await;
+    remove_if_expired(&mut map, &args[1]);
+    let Some(entry) = map.get(&args[1]) else {
+        return stream.write_all(b"$-1\r\n").await;
+    };
+    let reply = match subcommand.as_str() {
+        "ENCODING" => protocol::bulk_string(entry.value.encoding().as_bytes()),
+        "REFCOUNT" => b":1\r\n".to_vec(),
+        "IDLETIME" if lfu => format!(
+            "-ERR An LFU maxmemory policy is selected, idle time not tracked. {}\r\n",
+            POLICY_SWITCH_NOTE
+        )
+        .into_bytes(),
+        "IDLETIME" => format!(":{}\r\n", entry.idle_ms() / 1000).into_bytes(),
+        "FREQ" if !lfu => format!(
+            "-ERR An LFU maxmemory policy is not selected, access frequency not tracked. {}\r\n",
+            POLICY_SWITCH_NOTE
+        )
+        .into_bytes(),
+        _ => format!(":{}\r\n", entry.lfu_count()).into_bytes(),
+    };
+    drop(map);
+
+    stream.write_all(&reply).await
+}
+
+pub async fn handle_memory<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    let Some(subcommand) = args.first() else {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'memory' command\r\n")
+            .await;
+    };
+    match protocol::to_upper(subcommand).as_str() {
+        "USAGE" => memory_usage(stream, state, &args[1..]).await,
+        "STATS" if args.len() == 1 => memory_stats(stream, state).await,
+        "DOCTOR" if args.len() == 1 => memory_doctor(stream).await,
+        "HELP" if args.len() == 1 => stream.write_all(&help_reply(MEMORY_HELP)).await,
+        name @ ("STATS" | "DOCTOR" | "HELP") => {
+            let err = format!(
+                "-ERR wrong number of arguments for 'memory|{}' command\r\n",
+                name.to_lowercase()
+            );
+            stream.write_all(err.as_bytes()).await
+        }
+        _ => {
+            stream
+                .write_all(unknown_subcommand(subcommand, "MEMORY").as_bytes())
+                .await
+        }
+    }
+}
+
+/// MEMORY USAGE: the bytes taken by the key, its slot in the hash table and
+/// its value.
+async fn memory_usage<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    let Some(key) = args.first() else {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'memory|usage' command\r\n")
+            .await;
+    };
+    let samples = match &args[1..] {
+        [] => MEMORY_USAGE_SAMPLES,
+        [option, count] if option.eq_ignore_ascii_case(b"SAMPLES") => {
+            match protocol::parse_arg::<i64>(count) {
+                Some(count) if count >= 0 => count as usize,
+                _ => return stream.write_all(INT_ERR.as_bytes()).await,
+            }
+        }
+        _ => return stream.write_all(SYNTAX_ERR.as_bytes()).await,
+    };
+
+    let mut map = state.db().lock().await;
+    remove_if_expired(&mut map, key);
+    let usage = map.get(key).map(|entry| entry.memory_usage(key, samples));
+    drop(map);
+
+    match usage {
+        Some(usage) => stream.write_all(format!(":{}\r\n", usage).as_bytes()).await,
+        None => stream.write_all(b"$-1\r\n").await,
+    }
+}
+
+/// MEMORY STATS: where the allocated memory goes, split between the fixed
+/// cost of the server, the hash tables of each database and the data itself.
+async fn memory_stats<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+) -> std::io::Result<()> {
+    let mut fields = Vec::new();
+    let mut field_count = 0;
+    let mut push_field = |fields: &mut Vec<u8>, name: &str, value: Vec<u8>| {
+        protocol::push_bulk_string(fields, name.as_bytes());
+        fields.extend_from_slice(&value);
+        field_count += 1;
+    };
+    let integer = |value: usize| format!(":{}\r\n", value).into_bytes();
+    let float = |value: f64| protocol::bulk_string(value.to_string().as_bytes());
+
+    let startup = memory::startup_memory();
+    let mut overhead = startup;
+    let mut keys = 0;
+    let mut db_fields = Vec::new();
+    for (index, db) in state.databases.iter().enumerate() {
+        let map = db.lock().await;
+        if map.is_empty() {
+            continue;
+        }
+        let (main, expires) = (map.table_overhead(), map.expires_overhead());
+        overhead += main + expires;
+        keys += map.len();
+
+        let mut table = b"*4\r\n".to_vec();
+        protocol::push_bulk_string(&mut table, b"overhead.hashtable.main");
+        table.extend_from_slice(&integer(main));
+        protocol::push_bulk_string(&mut table, b"overhead.hashtable.expires");
+        table.extend_from_slice(&integer(expires));
+        db_fields.push((format!("db.{}", index), table));
+    }
+
+    // Read after walking the databases so the totals include everything
+    // counted above.
+    let used = memory::used_memory();
+    let peak = memory::peak_memory().max(used);
+    let dataset = used.saturating_sub(overhead);
+    let net = used.saturating_sub(startup);
+
+    push_field(&mut fields, "peak.allocated", integer(peak));
+    push_field(&mut fields, "total.allocated", integer(used));
+    push_field(&mut fields, "startup.allocated", integer(startup));
+    for (name, table) in db_fields {
+        push_field(&mut fields, &name, table);
+    }
+    push_field(&mut fields, "overhead.total", integer(overhead));
+    push_field(&mut fields, "keys.count", integer(keys));
+    let bytes_per_key = net.checked_div(keys).unwrap_or(0);
+    push_field(&mut fields, "keys.bytes-per-key", integer(bytes_per_key));
+    push_field(&mut fields, "dataset.bytes", integer(dataset));
+    let dataset_percentage = if net == 0 {
+        0.0
+    } else {
+        dataset as f64 * 100.0 / net as f64
+    };
+    push_field(&mut fields, "dataset.percentage", float(dataset_percentage));
+    push_field(
+        &mut fields,
+        "peak.percentage",
+        float(used as f64 * 100.0 / peak as f64),
+    );
+
+    let mut response = format!("*{}\r\n", field_count * 2).into_bytes();
+    response.extend_from_slice(&fields);
+    stream.write_all(&response).await
+}
+
+/// MEMORY DOCTOR: a short report of anything unusual about memory use.
+async fn memory_doctor<W: AsyncWriteExt + Unpin>(stream: &mut W) -> std::io::Result<()> {
+    let used = memory::used_memory();
+    let peak = memory::peak_memory();
+
+    let report = if used < DOCTOR_MIN_MEMORY {
+        "Hi Sam, this instance is empty or is using very little memory, my issues detector can't be used in these conditions. Please, leave for your mission on Earth and fill it with some data. The new Sam and I will be back to our programming as soon as I finished rebooting.".to_string()
+    } else if peak as f64 > used as f64 * 1.5 {
+        "Sam, I detected a few issues in this Redis instance memory implants:\n\n \
+         * Peak memory: In the past this instance used more than 150% the memory that is currently using. \
+         The allocator is normally not able to release memory after a peak, so the resident set size may stay \
+         well above the memory in use until the instance is filled with more data or restarted.\n\n\
+         I'm here to keep you safe, Sam. I want to help you.\n"
+            .to_string()
+    } else {
+        "Hi Sam, I can't find any memory issue in your instance. I can only account for what occurs on this base.".to_string()
+    };
+
+    stream
+        .write_all(&protocol::bulk_string(report.as_bytes()))
+        .await
+}
+
+pub fn help_reply(lines: &[&str]) -> Vec<u8> {
+    let mut response = format!("*{}\r\n", lines.len()).into_bytes();
+    for line in lines {
+        response.extend_from_slice(format!("+{}\r\n", line).as_bytes());
+    }
+    response
+}
+
+pub fn unknown_subcommand(subcommand: &[u8], command: &str) -> String {
+    format!(
+        "-ERR unknown subcommand '{}'. Try {} HELP.\r\n",
+        String::from_utf8_lossy(subcommand),
+        command
+    )
+}
@@ -2,12 +2,13 @@ use std::sync::Arc;
 
 use tokio::{io::AsyncWriteExt, sync::oneshot};
 
+use crate::protocol;
 use crate::storage::AppState;
 
 pub async fn handle_subscribe<W: AsyncWriteExt + Unpin>(
     stream: &mut W,
     state: &Arc<AppState>,
-    args: &[String],
+    args: &[Vec<u8>],
     stream_id: String,
 ) -> std::io::Result<()> {
     if args.is_empty() {
@@ -34,14 +35,11 @@ pub async fn handle_subscribe<W: AsyncWriteExt + Unpin>(
         entry.push(tx);
         client_channels.push(channel.clone());
 
-        let response = format!(
-            "*3\r\n$9\r\nsubscribe\r\n${}\r\n{}\r\n:{}\r\n",
-            channel.len(),
-            channel,
-            client_channels.len().to_string(),
-        );
+        let mut response = b"*3\r\n$9\r\nsubscribe\r\n".to_vec();
+        protocol::push_bulk_string(&mut response, &channel);
+        response.extend_from_slice(format!(":{}\r\n", client_channels.len()).as_bytes());
 
-        stream.write_all(response.as_bytes()).await?;
+        stream.write_all(&response).await?;
         return Ok(());
     }
 
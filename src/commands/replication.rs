@@ -7,19 +7,19 @@ use tokio::net::TcpStream;
 pub async fn handle_replconf<W: AsyncWriteExt + Unpin>(
     stream: &mut W,
     state: &Arc<AppState>,
-    args: &[String],
+    args: &[Vec<u8>],
 ) -> std::io::Result<()> {
     if args.len() >= 2 {
-        match args[0].to_uppercase().as_str() {
+        match protocol::to_upper(&args[0]).as_str() {
             "GETACK" => {
                 let offset = state.slave_replication_offset.lock().await;
                 // Use the RESP array serializer for a correctly formatted response
                 let response = protocol::serialize_resp_array(&[
-                    "REPLCONF".to_string(),
-                    "ACK".to_string(),
-                    offset.to_string(),
+                    b"REPLCONF".to_vec(),
+                    b"ACK".to_vec(),
+                    offset.to_string().into_bytes(),
                 ]);
-                stream.write_all(response.as_bytes()).await?;
+                stream.write_all(&response).await?;
 
                 return Ok(());
             }
@@ -36,7 +36,7 @@ pub async fn handle_replconf<W: AsyncWriteExt + Unpin>(
 pub async fn handle_psync<W: AsyncWriteExt + Unpin>(
     stream: &mut W,
     state: &Arc<AppState>,
-    args: &[String],
+    args: &[Vec<u8>],
 ) -> std::io::Result<()> {
     if args.len() < 2 {
         let err_msg = "-ERR wrong number of arguments for 'psync' command\r\n";
@@ -54,6 +54,7 @@ pub async fn handle_psync<W: AsyncWriteExt + Unpin>(
             .as_bytes(),
         )
         .await?;
+
     // Sending empty rdb file as a placeholder
     let empty_rdb_base64 = "UkVESVMwMDEx+glyZWRpcy12ZXIFNy4yLjD6CnJlZGlzLWJpdHPAQPoFY3RpbWXCbQi8ZfoIdXNlZC1tZW3CsMQQAPoIYW9mLWJhc2XAAP/wbjv+wP9aog==";
     let empty_rdb = general_purpose::STANDARD.decode(empty_rdb_base64).unwrap();
@@ -68,7 +69,7 @@ pub async fn handle_psync<W: AsyncWriteExt + Unpin>(
 pub async fn handle_wait<W: AsyncWriteExt + Unpin>(
     stream: &mut W,
     state: &Arc<AppState>,
-    args: &[String],
+    args: &[Vec<u8>],
 ) -> std::io::Result<()> {
     if args.len() != 2 {
         let err_msg = "-ERR wrong number of arguments for 'wait' command\r\n";
@@ -77,18 +78,18 @@ pub async fn handle_wait<W: AsyncWriteExt + Unpin>(
     }
 
     // Parse arguments
-    let num_replicas: usize = match args[0].parse() {
-        Ok(n) => n,
-        Err(_) => {
+    let num_replicas: usize = match protocol::parse_arg(&args[0]) {
+        Some(n) => n,
+        None => {
             let err_msg = "-ERR value is not an integer or out of range\r\n";
             stream.write_all(err_msg.as_bytes()).await?;
             return Ok(());
         }
     };
 
-    let timeout_ms: u64 = match args[1].parse() {
-        Ok(t) => t,
-        Err(_) => {
+    let timeout_ms: u64 = match protocol::parse_arg(&args[1]) {
+        Some(t) => t,
+        None => {
             let err_msg = "-ERR value is not an integer or out of range\r\n";
             stream.write_all(err_msg.as_bytes()).await?;
             return Ok(());
@@ -124,13 +125,13 @@ pub async fn handle_wait<W: AsyncWriteExt + Unpin>(
         for replica in replicas.iter_mut() {
             // Send REPLCONF GETACK
             let cmd = protocol::serialize_resp_array(&[
-                "REPLCONF".to_string(),
-                "GETACK".to_string(),
-                "*".to_string(),
+                b"REPLCONF".to_vec(),
+                b"GETACK".to_vec(),
+                b"*".to_vec(),
             ]);
 
             let mut stream = TcpStream::from_std(replica.stream.try_clone().unwrap()).unwrap();
-            stream.write_all(cmd.as_bytes()).await?;
+            stream.write_all(&cmd).await?;
         }
         drop(replicas);
 
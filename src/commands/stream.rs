@@ -1,17 +1,45 @@
+use crate::commands::consumer_group::no_such_group;
+use crate::commands::object::{help_reply, unknown_subcommand};
 use crate::protocol;
-use crate::storage::{AppState, DataStoreValue, Db, Stream, ValueEntry};
-use std::collections::{BTreeMap, HashMap};
-use std::fmt::Write;
-use std::ops::Bound::{Excluded, Unbounded};
+use crate::storage::{
+    lookup_key, remove_if_expired, unix_time_ms, AppState, DataStoreValue, Keyspace, Stream,
+    StreamFields, StreamId, TransactionState, ValueEntry,
+};
+use crate::stream_entries::NODE_MAX_ENTRIES;
+use std::ops::Bound::{self, Excluded, Included, Unbounded};
 use std::sync::Arc;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::Duration;
 use tokio::io::AsyncWriteExt;
-use tokio::time::timeout;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{timeout_at, Instant};
+
+const TYPE_ERR: &str = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+const INT_ERR: &str = "-ERR value is not an integer or out of range\r\n";
+const SYNTAX_ERR: &str = "-ERR syntax error\r\n";
+const INVALID_ID_ERR: &str = "-ERR Invalid stream ID specified as stream command argument\r\n";
+
+// How many entries approximate trimming removes at most when no LIMIT is given.
+const TRIM_DEFAULT_LIMIT: usize = 100 * NODE_MAX_ENTRIES;
+
+// How many entries XINFO STREAM FULL lists by default.
+const XINFO_FULL_COUNT: usize = 10;
+
+const XINFO_HELP: &[&str] = &[
+    "XINFO <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+    "CONSUMERS <key> <groupname>",
+    "    Show consumers of <groupname>.",
+    "GROUPS <key>",
+    "    Show the stream consumer groups.",
+    "STREAM <key> [FULL [COUNT <count>]",
+    "    Show information about the stream.",
+    "HELP",
+    "    Print this help.",
+];
 
 pub async fn handle_type<W: AsyncWriteExt + Unpin>(
     stream: &mut W,
     state: &Arc<AppState>,
-    args: &[String],
+    args: &[Vec<u8>],
 ) -> std::io::Result<()> {
     if args.len() != 1 {
         return stream
@@ -19,15 +47,11 @@ pub async fn handle_type<W: AsyncWriteExt + Unpin>(
             .await;
     }
 
-    let map = state.db.lock().await;
+    let mut map = state.db().lock().await;
+    remove_if_expired(&mut map, &args[0]);
     if let Some(entry) = map.get(&args[0]) {
-        match &entry.value {
-            DataStoreValue::List(_) => stream.write_all(b"+list\r\n").await,
-
-            DataStoreValue::String(_) => stream.write_all(b"+string\r\n").await,
-
-            DataStoreValue::Stream(_) => stream.write_all(b"+stream\r\n").await,
-        }
+        let response = format!("+{}\r\n", entry.value.type_name());
+        stream.write_all(response.as_bytes()).await
     } else {
         stream.write_all(b"+none\r\n").await
     }
@@ -36,286 +60,971 @@ pub async fn handle_type<W: AsyncWriteExt + Unpin>(
 pub async fn handle_xadd<W: AsyncWriteExt + Unpin>(
     stream: &mut W,
     state: &Arc<AppState>,
-    args: &[String],
+    args: &[Vec<u8>],
 ) -> std::io::Result<()> {
-    if args.len() < 2 {
+    if args.len() < 4 {
         return stream
-            .write_all(b"-ERR wrong number of arguments for 'stream' command\r\n")
+            .write_all(b"-ERR wrong number of arguments for 'xadd' command\r\n")
             .await;
     }
-    let key = args[0].to_string();
-    let id = args[1].to_string();
-    let mut map = state.db.lock().await;
-    let entry = map.entry(key.to_string()).or_insert(ValueEntry {
-        value: DataStoreValue::Stream(Stream {
-            entries: BTreeMap::new(),
-            last_id: "0-0".to_string(),
-        }),
-        expires_at: None,
-    });
-
-    if let DataStoreValue::Stream(btreemap) = &mut entry.value {
-        let mut fields = HashMap::new();
-        for i in (2..args.len()).step_by(2) {
-            if i + 1 < args.len() {
-                fields.insert(args[i].to_string(), args[i + 1].to_string());
-            } else {
-                return stream
-                    .write_all(b"-ERR wrong number of arguments for 'xadd' command\r\n")
-                    .await;
+    let key = args[0].to_vec();
+    let (mut nomkstream, mut trim) = (false, None);
+    // Where the trim arguments are, to rewrite them for the replicas.
+    let mut trim_args = 0..0;
+    let mut id_index = 1;
+    while let Some(option) = args.get(id_index) {
+        match protocol::to_upper(option).as_str() {
+            "NOMKSTREAM" => {
+                nomkstream = true;
+                id_index += 1;
             }
-        }
-
-        let last_id = btreemap
-            .last_id
-            .split('-')
-            .map(|s| s.to_string())
-            .collect::<Vec<String>>();
-        let last_timestamp = last_id[0].parse::<u128>().unwrap_or(0);
-        let last_seq = last_id[1].parse::<u64>().unwrap_or(0);
-
-        let cur_timestamp: u128;
-        let cur_seq: u64;
-
-        if id == "*" {
-            cur_seq = 0;
-            cur_timestamp = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_millis();
-        } else {
-            let cur_id = id
-                .split('-')
-                .map(|s| s.to_string())
-                .collect::<Vec<String>>();
-
-            if cur_id.len() != 2 {
-                return stream
-                    .write_all(b"-ERR Invalid stream ID specified as stream command argument\r\n")
-                    .await;
-            }
-
-            cur_seq = if cur_id[1] == "*" {
-                if cur_id[0] == last_id[0] {
-                    last_seq + 1
-                } else {
-                    0
+            "MAXLEN" | "MINID" => match Trim::parse(&args[id_index..]) {
+                Ok((strategy, taken)) => {
+                    trim = Some(strategy);
+                    trim_args = id_index..id_index + taken;
+                    id_index += taken;
                 }
-            } else {
-                cur_id[1].parse::<u64>().unwrap_or(0)
-            };
-            cur_timestamp = cur_id[0].parse::<u128>().unwrap_or(0);
-        }
-
-        if cur_seq == 0 && cur_timestamp == 0 {
-            return stream
-                .write_all(b"-ERR The ID specified in XADD must be greater than 0-0\r\n")
-                .await;
-        }
-
-        if cur_timestamp < last_timestamp
-            || (cur_timestamp == last_timestamp && cur_seq <= last_seq)
-        {
-            return stream.write_all(b"-ERR The ID specified in XADD is equal or smaller than the target stream top item\r\n").await;
+                Err(err) => return stream.write_all(err.as_bytes()).await,
+            },
+            _ => break,
         }
+    }
+    let fields_args = args.get(id_index + 1..).unwrap_or_default();
+    if fields_args.is_empty() || fields_args.len() % 2 != 0 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'xadd' command\r\n")
+            .await;
+    }
+    let fields: StreamFields = fields_args
+        .chunks(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect();
 
-        let calc_id = format!("{}-{}", cur_timestamp, cur_seq);
+    let mut map = state.db().lock().await;
+    let last_id = match lookup_stream_mut(&mut map, &key) {
+        Ok(Some(stream_value)) => stream_value.last_id,
+        Ok(None) if nomkstream => return stream.write_all(b"$-1\r\n").await,
+        Ok(None) => StreamId::MIN,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    // The ID is checked before the stream is created, so a rejected one
+    // doesn't leave an empty stream behind.
+    let calc_id = match next_id(last_id, &args[id_index]) {
+        Ok(calc_id) => calc_id,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let entry = map.get_or_insert_with(key.to_vec(), || {
+        ValueEntry::new(DataStoreValue::Stream(Stream::default()), None)
+    });
+    let DataStoreValue::Stream(btreemap) = &mut entry.value else {
+        unreachable!("the key was just looked up as a stream");
+    };
 
-        btreemap.entries.insert(calc_id.clone(), fields);
-        btreemap.last_id = calc_id.clone();
+    btreemap.entries.push(calc_id, &fields);
+    btreemap.last_id = calc_id;
+    btreemap.entries_added += 1;
+    let mut exact_trim = None;
+    if let Some(trim) = &trim {
+        trim.apply(btreemap);
+        exact_trim = trim.approximate.then(|| trim.exact_args(btreemap));
+    }
+    drop(map);
 
-        let response = format!("${}\r\n{}\r\n", calc_id.len(), calc_id);
-        stream.write_all(response.as_bytes()).await?;
+    let calc_id = calc_id.to_string();
+    let response = format!("${}\r\n{}\r\n", calc_id.len(), calc_id);
+    stream.write_all(response.as_bytes()).await?;
 
-        let _ = state.stream_notifier.send(());
-    }
+    let _ = state.stream_notifier.send(());
 
-    let mut command_with_args = vec!["XADD".to_string()];
+    // Replicas get the ID that was generated, not `*`.
+    let mut command_with_args = vec![b"XADD".to_vec()];
     command_with_args.extend_from_slice(args);
+    command_with_args[id_index + 1] = calc_id.into_bytes();
+    if let Some(exact_trim) = exact_trim {
+        command_with_args.splice(trim_args.start + 1..trim_args.end + 1, exact_trim);
+    }
     protocol::replicate_command(state, command_with_args).await?;
     Ok(())
 }
 
-pub async fn handle_xrange<W: AsyncWriteExt + Unpin>(
+/// The ID XADD gives a new entry, from the one it was given and the last ID
+/// of the stream. `*` generates the whole ID, and `<ms>-*` or a bare `<ms>`
+/// just its sequence number.
+fn next_id(last_id: StreamId, id: &[u8]) -> Result<StreamId, &'static str> {
+    const TOO_SMALL_ERR: &str =
+        "-ERR The ID specified in XADD is equal or smaller than the target stream top item\r\n";
+    let id = if id == b"*" {
+        let now = unix_time_ms();
+        // With the clock behind the last ID, IDs keep counting up from it.
+        if now > last_id.ms {
+            StreamId { ms: now, seq: 0 }
+        } else if last_id.seq < u64::MAX {
+            StreamId {
+                ms: last_id.ms,
+                seq: last_id.seq + 1,
+            }
+        } else if last_id.ms < u64::MAX {
+            StreamId {
+                ms: last_id.ms + 1,
+                seq: 0,
+            }
+        } else {
+            return Err(
+                "-ERR The stream has exhausted the last possible ID, unable to add more items\r\n",
+            );
+        }
+    } else if let Some(ms) = id
+        .strip_suffix(b"-*")
+        .or((!id.contains(&b'-')).then_some(id))
+    {
+        let ms = protocol::parse_arg::<u64>(ms).ok_or(INVALID_ID_ERR)?;
+        if ms != last_id.ms {
+            StreamId { ms, seq: 0 }
+        } else {
+            let seq = last_id.seq.checked_add(1).ok_or(TOO_SMALL_ERR)?;
+            StreamId { ms, seq }
+        }
+    } else {
+        StreamId::parse(id, 0).ok_or(INVALID_ID_ERR)?
+    };
+
+    if id == StreamId::MIN {
+        return Err("-ERR The ID specified in XADD must be greater than 0-0\r\n");
+    }
+    if id <= last_id {
+        return Err(TOO_SMALL_ERR);
+    }
+    Ok(id)
+}
+
+pub async fn handle_xtrim<W: AsyncWriteExt + Unpin>(
     stream: &mut W,
     state: &Arc<AppState>,
-    args: &[String],
+    args: &[Vec<u8>],
 ) -> std::io::Result<()> {
-    let null = "$-1\r\n";
-    let type_err = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
     if args.len() < 3 {
         return stream
-            .write_all(b"-ERR wrong number of arguments for 'xrange' command\r\n")
+            .write_all(b"-ERR wrong number of arguments for 'xtrim' command\r\n")
             .await;
     }
+    let trim = match Trim::parse(&args[1..]) {
+        Ok((trim, taken)) if taken == args.len() - 1 => trim,
+        Ok(_) => return stream.write_all(SYNTAX_ERR.as_bytes()).await,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
 
-    let key = args[0].to_string();
-    let mut start = args[1].to_string();
-    let mut end = args[2].to_string();
+    let mut map = state.db().lock().await;
+    let (removed, exact_trim) = match lookup_stream_mut(&mut map, &args[0]) {
+        Ok(Some(stream_value)) => {
+            let removed = trim.apply(stream_value);
+            let exact_trim = trim.approximate.then(|| trim.exact_args(stream_value));
+            (removed, exact_trim)
+        }
+        Ok(None) => (0, None),
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    drop(map);
 
-    if !start.contains("-") {
-        start = format!("{}-0", start);
+    stream
+        .write_all(format!(":{}\r\n", removed).as_bytes())
+        .await?;
+    if removed == 0 {
+        return Ok(());
     }
-    if !end.contains("-") && end != "+" {
-        end = format!("{}-0", end);
+    let mut command_with_args = vec![b"XTRIM".to_vec(), args[0].to_vec()];
+    match exact_trim {
+        Some(exact_trim) => command_with_args.extend(exact_trim),
+        None => command_with_args.extend_from_slice(&args[1..]),
     }
+    protocol::replicate_command(state, command_with_args).await
+}
 
-    let map = state.db.lock().await;
-    if let Some(entry) = map.get(&key) {
-        if let DataStoreValue::Stream(btreemap) = &entry.value {
-            let mut response = String::new();
-            let range = if end == "+" {
-                btreemap.entries.range(start..)
-            } else {
-                btreemap.entries.range(start..=end)
-            };
-            response.push_str(&format!("*{}\r\n", range.clone().count()));
-            for (id, fields) in range {
-                response.push_str("*2\r\n");
-                response.push_str(&format!("${}\r\n{}\r\n", id.len(), id));
-                response.push_str(&format!("*{}\r\n", fields.len() * 2));
-                for (field, value) in fields {
-                    response.push_str(&format!("${}\r\n{}\r\n", field.len(), field));
-                    response.push_str(&format!("${}\r\n{}\r\n", value.len(), value));
-                }
+pub async fn handle_xdel<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() < 2 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'xdel' command\r\n")
+            .await;
+    }
+    let Some(ids) = args[1..]
+        .iter()
+        .map(|id| StreamId::parse(id, 0))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return stream.write_all(INVALID_ID_ERR.as_bytes()).await;
+    };
+
+    let mut map = state.db().lock().await;
+    let stream_value = match lookup_stream_mut(&mut map, &args[0]) {
+        Ok(stream_value) => stream_value,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let mut deleted = 0;
+    if let Some(stream_value) = stream_value {
+        for id in ids {
+            if !stream_value.entries.remove(&id) {
+                continue;
+            }
+            deleted += 1;
+            if id > stream_value.max_deleted_entry_id {
+                stream_value.max_deleted_entry_id = id;
             }
-            stream.write_all(response.as_bytes()).await
-        } else {
-            stream.write_all(type_err.as_bytes()).await
         }
-    } else {
-        stream.write_all(null.as_bytes()).await
     }
+    drop(map);
+
+    stream
+        .write_all(format!(":{}\r\n", deleted).as_bytes())
+        .await?;
+    if deleted == 0 {
+        return Ok(());
+    }
+    let mut command_with_args = vec![b"XDEL".to_vec()];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await
 }
 
-pub async fn handle_xread<W: AsyncWriteExt + Unpin>(
+pub async fn handle_xrange_xrevrange<W: AsyncWriteExt + Unpin>(
+    command: &str,
     stream: &mut W,
     state: &Arc<AppState>,
-    args: &[String],
+    args: &[Vec<u8>],
 ) -> std::io::Result<()> {
-    let null = "*-1\r\n";
-    let (no_of_keys, start_idx, timeout_ms) = if args[0].to_lowercase() == "block" {
-        let timeout_ms = args[1].parse::<u64>().unwrap_or(0);
-        (((args.len() - 3) / 2), 3, timeout_ms)
+    let rev = command == "XREVRANGE";
+    if args.len() != 3 && args.len() != 5 {
+        let err = format!(
+            "-ERR wrong number of arguments for '{}' command\r\n",
+            command.to_lowercase()
+        );
+        return stream.write_all(err.as_bytes()).await;
+    }
+    // XREVRANGE takes the end of the range first.
+    let (start, end) = if rev {
+        (&args[2], &args[1])
     } else {
-        (((args.len() - 1) / 2), 1, 0)
+        (&args[1], &args[2])
     };
-
-    let is_blocking = args[0].to_lowercase() == "block";
-
-    async fn check_for_data(
-        db: &Db,
-        args: &[String],
-        no_of_keys: usize,
-        start_idx: usize,
-    ) -> Option<Vec<(String, Vec<(String, HashMap<String, String>)>)>> {
-        let mut results = Vec::new();
-        let db_map = db.lock().await;
-
-        for i in 0..no_of_keys {
-            let key = &args[i + start_idx];
-            let id = args[args.len() - no_of_keys + i].to_string();
-
-            if let Some(entry) = db_map.get(key) {
-                if let DataStoreValue::Stream(stream_data) = &entry.value {
-                    let entries: Vec<_> = stream_data
-                        .entries
-                        .range((Excluded(id.to_string()), Unbounded))
-                        .map(|(id, fields)| (id.clone(), fields.clone()))
-                        .collect();
-
-                    if !entries.is_empty() {
-                        results.push((key.to_string(), entries));
-                    }
-                }
-            }
+    let (Some(start), Some(end)) = (
+        parse_range_bound(start, true),
+        parse_range_bound(end, false),
+    ) else {
+        return stream.write_all(INVALID_ID_ERR.as_bytes()).await;
+    };
+    let mut count = None;
+    if args.len() == 5 {
+        if !args[3].eq_ignore_ascii_case(b"COUNT") {
+            return stream.write_all(SYNTAX_ERR.as_bytes()).await;
+        }
+        match protocol::parse_arg::<i64>(&args[4]) {
+            Some(value) => count = Some(value.max(0) as usize),
+            None => return stream.write_all(INT_ERR.as_bytes()).await,
         }
+    }
+    if count == Some(0) {
+        return stream.write_all(b"*-1\r\n").await;
+    }
 
-        if results.is_empty() {
-            None
+    let mut map = state.db().lock().await;
+    let stream_value = match lookup_stream_mut(&mut map, &args[0]) {
+        Ok(stream_value) => stream_value,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let mut response = b"*0\r\n".to_vec();
+    if let Some(stream_value) = stream_value.filter(|_| !range_is_empty(&start, &end)) {
+        let range = stream_value.entries.range((start, end));
+        let limit = count.unwrap_or(usize::MAX);
+        let selected: Vec<_> = if rev {
+            range.rev().take(limit).collect()
         } else {
-            Some(results)
+            range.take(limit).collect()
+        };
+        response = format!("*{}\r\n", selected.len()).into_bytes();
+        for (id, fields) in selected {
+            push_entry(&mut response, &id, Some(&fields));
         }
     }
+    drop(map);
 
-    let mut mod_args = Vec::<String>::new();
-    for i in 0..args.len() {
-        mod_args.push(args[i].to_string());
+    stream.write_all(&response).await
+}
+
+pub async fn handle_xlen<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 1 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'xlen' command\r\n")
+            .await;
+    }
+
+    let mut map = state.db().lock().await;
+    let len = match lookup_stream_mut(&mut map, &args[0]) {
+        Ok(stream_value) => stream_value.map_or(0, |stream_value| stream_value.entries.len()),
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    drop(map);
+
+    stream.write_all(format!(":{}\r\n", len).as_bytes()).await
+}
+
+pub async fn handle_xread<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    transation_state: &TransactionState,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() < 3 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'xread' command\r\n")
+            .await;
     }
-    for i in 0..no_of_keys {
-        let key = args[i + start_idx].to_string();
-        let mut id = args[args.len() - no_of_keys + i].to_string();
-        let db_map = state.db.lock().await;
-        if let Some(entry) = db_map.get(&key) {
-            if let DataStoreValue::Stream(stream_data) = &entry.value {
-                if id == "$" {
-                    id = stream_data.last_id.to_string();
+    let (mut count, mut block) = (None, None);
+    let mut i = 0;
+    let streams = loop {
+        let Some(option) = args.get(i) else {
+            return stream.write_all(SYNTAX_ERR.as_bytes()).await;
+        };
+        match protocol::to_upper(option).as_str() {
+            "COUNT" if i + 1 < args.len() => match protocol::parse_arg::<i64>(&args[i + 1]) {
+                // A count of zero or less reads everything.
+                Some(value) => count = usize::try_from(value).ok().filter(|&count| count > 0),
+                None => return stream.write_all(INT_ERR.as_bytes()).await,
+            },
+            "BLOCK" if i + 1 < args.len() => match protocol::parse_arg::<i64>(&args[i + 1]) {
+                Some(ms) if ms >= 0 => block = Some(Duration::from_millis(ms as u64)),
+                Some(_) => return stream.write_all(b"-ERR timeout is negative\r\n").await,
+                None => {
+                    return stream
+                        .write_all(b"-ERR timeout is not an integer or out of range\r\n")
+                        .await
                 }
+            },
+            "STREAMS" => break &args[i + 1..],
+            _ => return stream.write_all(SYNTAX_ERR.as_bytes()).await,
+        }
+        i += 2;
+    };
+    if streams.is_empty() || streams.len() % 2 != 0 {
+        return stream
+            .write_all(b"-ERR Unbalanced 'xread' list of streams: for each stream key an ID, '+', or '$' must be specified.\r\n")
+            .await;
+    }
+    let (keys, ids) = streams.split_at(streams.len() / 2);
+
+    // Each key with the ID to read after, `$` being the last ID of the
+    // stream when the call is made, or `None` for `+`, the last entry.
+    let mut reads = Vec::with_capacity(keys.len());
+    let mut map = state.db().lock().await;
+    for (key, id) in keys.iter().zip(ids) {
+        let stream_value = match lookup_stream_mut(&mut map, key) {
+            Ok(stream_value) => stream_value,
+            Err(err) => return stream.write_all(err.as_bytes()).await,
+        };
+        let after = match id.as_slice() {
+            b"$" => Some(stream_value.map_or(StreamId::MIN, |s| s.last_id)),
+            b"+" => None,
+            id => match StreamId::parse(id, 0) {
+                Some(id) => Some(id),
+                None => return stream.write_all(INVALID_ID_ERR.as_bytes()).await,
+            },
+        };
+        reads.push((key.clone(), after));
+    }
+    drop(map);
+
+    let deadline = block
+        .filter(|wait| !wait.is_zero())
+        .map(|wait| Instant::now() + wait);
+    // Subscribed before the first look, so an XADD in between still wakes us.
+    let mut notifications = state.stream_notifier.subscribe();
+    loop {
+        let mut map = state.db().lock().await;
+        let reply = read_streams(&mut map, &reads, count.unwrap_or(usize::MAX));
+        drop(map);
+
+        match reply {
+            Ok(Some(reply)) => return stream.write_all(&reply).await,
+            Ok(None) => {}
+            Err(err) => return stream.write_all(err.as_bytes()).await,
+        }
+        if block.is_none() || transation_state.in_exec {
+            break;
+        }
+        let notified = match deadline {
+            Some(deadline) => match timeout_at(deadline, notifications.recv()).await {
+                Ok(result) => result,
+                Err(_) => break,
+            },
+            None => notifications.recv().await,
+        };
+        // Missed notifications still mean something was added.
+        if let Err(RecvError::Closed) = notified {
+            break;
+        }
+    }
+    stream.write_all(b"*-1\r\n").await
+}
+
+/// The XREAD reply for the entries after each ID, at most `limit` per
+/// stream, or `None` when there are none yet.
+fn read_streams(
+    map: &mut Keyspace,
+    reads: &[(Vec<u8>, Option<StreamId>)],
+    limit: usize,
+) -> Result<Option<Vec<u8>>, &'static str> {
+    let mut replies = Vec::new();
+    for (key, after) in reads {
+        let Some(stream_value) = lookup_stream_mut(map, key)? else {
+            continue;
+        };
+        let entries: Vec<_> = match after {
+            Some(after) => stream_value
+                .entries
+                .range((Excluded(*after), Unbounded))
+                .take(limit)
+                .collect(),
+            None => stream_value
+                .entries
+                .iter()
+                .next_back()
+                .into_iter()
+                .collect(),
+        };
+        if entries.is_empty() {
+            continue;
+        }
+        let mut reply = b"*2\r\n".to_vec();
+        protocol::push_bulk_string(&mut reply, key);
+        reply.extend_from_slice(format!("*{}\r\n", entries.len()).as_bytes());
+        for (id, fields) in entries {
+            push_entry(&mut reply, &id, Some(&fields));
+        }
+        replies.push(reply);
+    }
+    if replies.is_empty() {
+        return Ok(None);
+    }
+    let mut response = format!("*{}\r\n", replies.len()).into_bytes();
+    replies
+        .iter()
+        .for_each(|reply| response.extend_from_slice(reply));
+    Ok(Some(response))
+}
+
+pub async fn handle_xinfo<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    let Some(subcommand) = args.first() else {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'xinfo' command\r\n")
+            .await;
+    };
+    let subcommand = protocol::to_upper(subcommand);
+    let arity = match subcommand.as_str() {
+        "HELP" => 1..=1,
+        "STREAM" => 2..=5,
+        "GROUPS" => 2..=2,
+        "CONSUMERS" => 3..=3,
+        _ => {
+            return stream
+                .write_all(unknown_subcommand(&args[0], "XINFO").as_bytes())
+                .await
+        }
+    };
+    if !arity.contains(&args.len()) {
+        let err = format!(
+            "-ERR wrong number of arguments for 'xinfo|{}' command\r\n",
+            subcommand.to_lowercase()
+        );
+        return stream.write_all(err.as_bytes()).await;
+    }
+    if subcommand == "HELP" {
+        return stream.write_all(&help_reply(XINFO_HELP)).await;
+    }
+
+    // With FULL, how many entries, and pending entries of each group and
+    // consumer, to list at most.
+    let full = match &args[2..] {
+        _ if subcommand != "STREAM" => None,
+        [] => None,
+        [option] if option.eq_ignore_ascii_case(b"FULL") => Some(XINFO_FULL_COUNT),
+        [option, count_option, count]
+            if option.eq_ignore_ascii_case(b"FULL")
+                && count_option.eq_ignore_ascii_case(b"COUNT") =>
+        {
+            match protocol::parse_arg::<i64>(count) {
+                Some(0) => Some(usize::MAX),
+                Some(count) if count > 0 => Some(count as usize),
+                Some(_) => Some(XINFO_FULL_COUNT),
+                None => return stream.write_all(INT_ERR.as_bytes()).await,
             }
         }
-        mod_args[args.len() - no_of_keys + i] = id;
+        _ => return stream.write_all(SYNTAX_ERR.as_bytes()).await,
+    };
+
+    let mut map = state.db().lock().await;
+    let stream_value = match lookup_stream_mut(&mut map, &args[1]) {
+        Ok(Some(stream_value)) => stream_value,
+        Ok(None) => return stream.write_all(b"-ERR no such key\r\n").await,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let reply = match subcommand.as_str() {
+        "STREAM" => stream_info(stream_value, full),
+        "GROUPS" => {
+            let groups = stream_value.groups.iter().map(|(name, group)| {
+                let (entries_read, lag) = stream_value.group_lag(group);
+                map_reply(vec![
+                    ("name", protocol::bulk_string(name)),
+                    ("consumers", integer_reply(group.consumers.len() as u64)),
+                    ("pending", integer_reply(group.pending.len() as u64)),
+                    ("last-delivered-id", id_reply(&group.last_delivered_id)),
+                    ("entries-read", optional_integer_reply(entries_read)),
+                    ("lag", optional_integer_reply(lag)),
+                ])
+            });
+            array_reply(groups.collect())
+        }
+        _ => match stream_value.groups.get(&args[2]) {
+            Some(group) => {
+                let now = unix_time_ms();
+                let consumers = group.consumers.iter().map(|(name, consumer)| {
+                    let pending = group
+                        .pending
+                        .values()
+                        .filter(|pending| pending.consumer == *name)
+                        .count();
+                    map_reply(vec![
+                        ("name", protocol::bulk_string(name)),
+                        ("pending", integer_reply(pending as u64)),
+                        (
+                            "idle",
+                            integer_reply(now.saturating_sub(consumer.seen_time)),
+                        ),
+                        (
+                            "inactive",
+                            signed_integer_reply(
+                                consumer
+                                    .active_time
+                                    .map(|active_time| now.saturating_sub(active_time)),
+                            ),
+                        ),
+                    ])
+                });
+                array_reply(consumers.collect())
+            }
+            None => no_such_group(&args[1], &args[2]).into_bytes(),
+        },
+    };
+    drop(map);
+
+    stream.write_all(&reply).await
+}
+
+/// The XINFO STREAM reply. With FULL, it lists up to `count` entries and
+/// every group with its pending entries and consumers instead of the first
+/// and last entries.
+fn stream_info(stream_value: &Stream, full: Option<usize>) -> Vec<u8> {
+    let entries = &stream_value.entries;
+    let first_id = entries.first_id().unwrap_or(StreamId::MIN);
+    let mut fields = vec![
+        ("length", integer_reply(entries.len() as u64)),
+        (
+            "radix-tree-keys",
+            integer_reply(entries.node_count() as u64),
+        ),
+        (
+            "radix-tree-nodes",
+            integer_reply(entries.radix_tree_nodes() as u64),
+        ),
+        ("last-generated-id", id_reply(&stream_value.last_id)),
+        (
+            "max-deleted-entry-id",
+            id_reply(&stream_value.max_deleted_entry_id),
+        ),
+        ("entries-added", integer_reply(stream_value.entries_added)),
+        ("recorded-first-entry-id", id_reply(&first_id)),
+    ];
+    let entry_reply = |entry: Option<(StreamId, StreamFields)>| match entry {
+        Some((id, entry_fields)) => {
+            let mut reply = Vec::new();
+            push_entry(&mut reply, &id, Some(&entry_fields));
+            reply
+        }
+        None => b"$-1\r\n".to_vec(),
+    };
+
+    let Some(count) = full else {
+        let groups = stream_value.groups.len() as u64;
+        fields.push(("groups", integer_reply(groups)));
+        fields.push(("first-entry", entry_reply(entries.iter().next())));
+        fields.push(("last-entry", entry_reply(entries.iter().next_back())));
+        return map_reply(fields);
+    };
+
+    let listed = entries
+        .iter()
+        .take(count)
+        .map(|entry| entry_reply(Some(entry)));
+    fields.push(("entries", array_reply(listed.collect())));
+    let groups = stream_value.groups.iter().map(|(name, group)| {
+        let (entries_read, lag) = stream_value.group_lag(group);
+        let pending = group.pending.iter().take(count).map(|(id, pending)| {
+            let mut reply = b"*4\r\n".to_vec();
+            reply.extend_from_slice(&id_reply(id));
+            protocol::push_bulk_string(&mut reply, &pending.consumer);
+            reply.extend_from_slice(&integer_reply(pending.delivery_time));
+            reply.extend_from_slice(&integer_reply(pending.delivery_count));
+            reply
+        });
+        let consumers = group.consumers.iter().map(|(consumer_name, consumer)| {
+            let owned: Vec<_> = group
+                .pending
+                .iter()
+                .filter(|(_, pending)| pending.consumer == *consumer_name)
+                .collect();
+            let consumer_pending = owned.iter().take(count).map(|(id, pending)| {
+                let mut reply = b"*3\r\n".to_vec();
+                reply.extend_from_slice(&id_reply(id));
+                reply.extend_from_slice(&integer_reply(pending.delivery_time));
+                reply.extend_from_slice(&integer_reply(pending.delivery_count));
+                reply
+            });
+            map_reply(vec![
+                ("name", protocol::bulk_string(consumer_name)),
+                ("seen-time", integer_reply(consumer.seen_time)),
+                ("active-time", signed_integer_reply(consumer.active_time)),
+                ("pel-count", integer_reply(owned.len() as u64)),
+                ("pending", array_reply(consumer_pending.collect())),
+            ])
+        });
+        map_reply(vec![
+            ("name", protocol::bulk_string(name)),
+            ("last-delivered-id", id_reply(&group.last_delivered_id)),
+            ("entries-read", optional_integer_reply(entries_read)),
+            ("lag", optional_integer_reply(lag)),
+            ("pel-count", integer_reply(group.pending.len() as u64)),
+            ("pending", array_reply(pending.collect())),
+            ("consumers", array_reply(consumers.collect())),
+        ])
+    });
+    fields.push(("groups", array_reply(groups.collect())));
+    map_reply(fields)
+}
+
+fn map_reply(fields: Vec<(&str, Vec<u8>)>) -> Vec<u8> {
+    let mut reply = format!("*{}\r\n", fields.len() * 2).into_bytes();
+    for (name, value) in fields {
+        protocol::push_bulk_string(&mut reply, name.as_bytes());
+        reply.extend_from_slice(&value);
+    }
+    reply
+}
+
+fn array_reply(items: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut reply = format!("*{}\r\n", items.len()).into_bytes();
+    items.iter().for_each(|item| reply.extend_from_slice(item));
+    reply
+}
+
+fn integer_reply(value: u64) -> Vec<u8> {
+    format!(":{}\r\n", value).into_bytes()
+}
+
+/// An integer, or -1 when there is none, as Redis gives for times that
+/// haven't happened.
+fn signed_integer_reply(value: Option<u64>) -> Vec<u8> {
+    value.map_or(b":-1\r\n".to_vec(), integer_reply)
+}
+
+fn optional_integer_reply(value: Option<u64>) -> Vec<u8> {
+    value.map_or(b"$-1\r\n".to_vec(), integer_reply)
+}
+
+fn id_reply(id: &StreamId) -> Vec<u8> {
+    protocol::bulk_string(id.to_string().as_bytes())
+}
+
+/// Looks up the stream stored at `key`, treating expired keys as missing.
+pub fn lookup_stream_mut<'a>(
+    map: &'a mut Keyspace,
+    key: &[u8],
+) -> Result<Option<&'a mut Stream>, &'static str> {
+    lookup_key(map, key);
+    match map.get_mut(key).map(|entry| &mut entry.value) {
+        None => Ok(None),
+        Some(DataStoreValue::Stream(stream)) => Ok(Some(stream)),
+        Some(_) => Err(TYPE_ERR),
+    }
+}
+
+/// Parses the start or end of an ID range: `-` and `+` for the first and last
+/// possible IDs, an ID, or an ID prefixed with `(` to leave it out.
+pub fn parse_range_bound(arg: &[u8], is_start: bool) -> Option<Bound<StreamId>> {
+    let default_seq = if is_start { 0 } else { u64::MAX };
+    match arg {
+        b"-" if is_start => Some(Unbounded),
+        b"+" if !is_start => Some(Unbounded),
+        b"-" => Some(Included(StreamId::MIN)),
+        b"+" => Some(Included(StreamId::MAX)),
+        [b'(', id @ ..] => StreamId::parse(id, default_seq).map(Excluded),
+        id => StreamId::parse(id, default_seq).map(Included),
+    }
+}
+
+/// Whether no ID can fall between `start` and `end`. Such ranges must not be
+/// passed to `BTreeMap::range`, which panics on them.
+pub fn range_is_empty(start: &Bound<StreamId>, end: &Bound<StreamId>) -> bool {
+    match (start, end) {
+        (Included(start), Included(end)) => start > end,
+        (Included(start) | Excluded(start), Included(end) | Excluded(end)) => start >= end,
+        _ => false,
     }
+}
 
-    // 1. Fast Path: Check for data immediately.
-    let mut final_results = check_for_data(&state.db, &mod_args, no_of_keys, start_idx).await;
+/// Appends an entry as the `[id, [field, value, ...]]` pair stream commands
+/// reply with, or as `[id, nil]` for an entry that has since been deleted.
+pub fn push_entry(response: &mut Vec<u8>, id: &StreamId, fields: Option<&StreamFields>) {
+    response.extend_from_slice(b"*2\r\n");
+    protocol::push_bulk_string(response, id.to_string().as_bytes());
+    let Some(fields) = fields else {
+        response.extend_from_slice(b"*-1\r\n");
+        return;
+    };
+    response.extend_from_slice(format!("*{}\r\n", fields.len() * 2).as_bytes());
+    for (field, value) in fields {
+        protocol::push_bulk_string(response, field);
+        protocol::push_bulk_string(response, value);
+    }
+}
 
-    // 2. Blocking Path: If no data and BLOCK was specified.
-    if final_results.is_none() && is_blocking {
-        let mut rx = state.stream_notifier.subscribe();
+/// A trimming strategy, as given to XTRIM and XADD.
+pub struct Trim {
+    threshold: TrimThreshold,
+    /// With `~`, only whole nodes are removed, and at most `limit` entries
+    /// (0 for no limit).
+    approximate: bool,
+    limit: usize,
+}
+
+enum TrimThreshold {
+    MaxLen(usize),
+    MinId(StreamId),
+}
 
-        if timeout_ms > 0 {
-            // Timed block
-            if let Ok(Ok(_)) = timeout(Duration::from_millis(timeout_ms), rx.recv()).await {
-                // Woken by a notification, check again.
-                final_results = check_for_data(&state.db, &mod_args, no_of_keys, start_idx).await;
+impl Trim {
+    /// Parses `MAXLEN|MINID [=|~] <threshold> [LIMIT <count>]` from the start
+    /// of `args`, returning the strategy and how many arguments it took.
+    fn parse(args: &[Vec<u8>]) -> Result<(Trim, usize), &'static str> {
+        let max_len = args[0].eq_ignore_ascii_case(b"MAXLEN");
+        let modifier = args.get(1).map(Vec::as_slice);
+        let approximate = modifier == Some(b"~");
+        let mut taken = if matches!(modifier, Some(b"~" | b"=")) {
+            2
+        } else {
+            1
+        };
+        let Some(threshold) = args.get(taken) else {
+            return Err(SYNTAX_ERR);
+        };
+        let threshold = if max_len {
+            match protocol::parse_arg::<i64>(threshold) {
+                Some(len) if len >= 0 => TrimThreshold::MaxLen(len as usize),
+                Some(_) => return Err("-ERR The MAXLEN argument must be >= 0.\r\n"),
+                None => return Err(INT_ERR),
             }
         } else {
-            // Indefinite block (timeout is 0)
-            loop {
-                if rx.recv().await.is_ok() {
-                    // Woken by a notification, check for data.
-                    final_results =
-                        check_for_data(&state.db, &mod_args, no_of_keys, start_idx).await;
-                    if final_results.is_some() {
-                        // Data found for our keys, break the wait loop.
-                        break;
-                    }
-                // Spurious wakeup (data was for other keys), loop and wait again.
-                } else {
-                    // Channel closed, server is likely shutting down.
-                    break;
+            TrimThreshold::MinId(StreamId::parse(threshold, 0).ok_or(INVALID_ID_ERR)?)
+        };
+        taken += 1;
+
+        let mut limit = None;
+        if let (Some(option), Some(count)) = (args.get(taken), args.get(taken + 1)) {
+            if option.eq_ignore_ascii_case(b"LIMIT") {
+                match protocol::parse_arg::<i64>(count) {
+                    Some(count) if count >= 0 => limit = Some(count as usize),
+                    Some(_) => return Err("-ERR The LIMIT argument must be >= 0.\r\n"),
+                    None => return Err(INT_ERR),
                 }
+                taken += 2;
             }
         }
+        if limit.is_some() && !approximate {
+            return Err("-ERR syntax error, LIMIT cannot be used without the special ~ option\r\n");
+        }
+        let trim = Trim {
+            threshold,
+            approximate,
+            limit: limit.unwrap_or(TRIM_DEFAULT_LIMIT),
+        };
+        Ok((trim, taken))
     }
 
-    // 3. Format and send the response.
-    if let Some(results) = final_results {
-        let mut response = String::new();
-        response.push_str(&format!("*{}\r\n", results.len()));
-        for (key, entries) in results {
-            response.push_str("*2\r\n");
-            response.push_str(&format!("${}\r\n{}\r\n", key.len(), key));
-            response.push_str(&format!("*{}\r\n", entries.len()));
-            for (entry_id, fields) in entries {
-                response.push_str("*2\r\n");
-                response.push_str(&format!("${}\r\n{}\r\n", entry_id.len(), entry_id));
-                response.push_str(&format!("*{}\r\n", fields.len() * 2));
-                for (field_key, field_value) in fields {
-                    write!(&mut response, "${}\r\n{}\r\n", field_key.len(), field_key).unwrap();
-                    write!(
-                        &mut response,
-                        "${}\r\n{}\r\n",
-                        field_value.len(),
-                        field_value
-                    )
-                    .unwrap();
-                }
+    /// The arguments of an exact trim that leaves a stream as this one left
+    /// `stream`. An approximate trim is propagated as one, so replicas remove
+    /// the same entries whatever their nodes look like.
+    fn exact_args(&self, stream: &Stream) -> Vec<Vec<u8>> {
+        let (strategy, threshold) = match &self.threshold {
+            TrimThreshold::MaxLen(_) => ("MAXLEN", stream.entries.len().to_string()),
+            // Only entries before the threshold went if none are left.
+            TrimThreshold::MinId(min_id) => {
+                let first_id = stream.entries.first_id().unwrap_or(*min_id);
+                ("MINID", first_id.to_string())
+            }
+        };
+        vec![strategy.into(), b"=".to_vec(), threshold.into_bytes()]
+    }
+
+    /// Removes entries from the start of the stream, returning how many.
+    pub fn apply(&self, stream: &mut Stream) -> usize {
+        let entries = &mut stream.entries;
+        let mut removed = 0;
+        // Whole nodes go first, for as long as all of their entries have to.
+        while let Some((node_len, node_last_id)) = entries.first_node() {
+            let whole = match &self.threshold {
+                TrimThreshold::MaxLen(max_len) => entries.len() - node_len >= *max_len,
+                TrimThreshold::MinId(min_id) => node_last_id < *min_id,
+            };
+            if !whole || (self.approximate && self.limit > 0 && removed + node_len > self.limit) {
+                break;
             }
+            removed += entries.pop_first_node();
         }
-        stream.write_all(response.as_bytes()).await
-    } else {
-        // No results found (either non-blocking or timed out).
-        stream.write_all(null.as_bytes()).await
+        if self.approximate {
+            return removed;
+        }
+        // Exact trimming goes on into the node that's left.
+        while let Some(first_id) = entries.first_id() {
+            let trim = match &self.threshold {
+                TrimThreshold::MaxLen(max_len) => entries.len() > *max_len,
+                TrimThreshold::MinId(min_id) => first_id < *min_id,
+            };
+            if !trim {
+                break;
+            }
+            entries.remove(&first_id);
+            removed += 1;
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX: u64 = u64::MAX;
+
+    fn id(ms: u64, seq: u64) -> StreamId {
+        StreamId { ms, seq }
+    }
+
+    #[test]
+    fn next_id_counts_up_to_the_last_possible_id() {
+        assert_eq!(next_id(id(MAX, 5), b"*"), Ok(id(MAX, 6)));
+        assert_eq!(next_id(id(MAX - 1, MAX), b"*"), Ok(id(MAX, 0)));
+        assert!(next_id(id(MAX, MAX), b"*")
+            .is_err_and(|err| err.contains("exhausted the last possible ID")));
+
+        assert_eq!(next_id(id(7, MAX), b"8-*"), Ok(id(8, 0)));
+        assert!(next_id(id(7, MAX), b"7-*").is_err_and(|err| err.contains("equal or smaller")));
+        assert_eq!(next_id(id(MAX, 1), b"18446744073709551615"), Ok(id(MAX, 2)));
+        let last = b"18446744073709551615-18446744073709551615";
+        assert_eq!(next_id(id(MAX, MAX - 1), last), Ok(id(MAX, MAX)));
+        assert!(next_id(id(MAX, MAX), last).is_err_and(|err| err.contains("equal or smaller")));
+        assert_eq!(
+            next_id(id(0, 0), b"18446744073709551616-0"),
+            Err(INVALID_ID_ERR)
+        );
+        assert_eq!(
+            next_id(id(0, 0), b"18446744073709551616-*"),
+            Err(INVALID_ID_ERR)
+        );
+        assert!(next_id(id(0, 0), b"0-0").is_err_and(|err| err.contains("greater than 0-0")));
+    }
+
+    /// A stream of entries 1-0 to 250-0, in nodes of 100, 100 and 50.
+    fn stream() -> Stream {
+        let mut stream = Stream::default();
+        for ms in 1..=250 {
+            stream
+                .entries
+                .push(id(ms, 0), &[(b"f".to_vec(), b"v".to_vec())]);
+        }
+        stream.last_id = id(250, 0);
+        stream
+    }
+
+    /// Trims `stream()` with the given arguments, returning how many entries
+    /// went, the first one left and the exact trim to propagate.
+    fn trim(args: &str) -> (usize, Option<u64>, String) {
+        let args: Vec<Vec<u8>> = args.split(' ').map(|arg| arg.into()).collect();
+        let (trim, taken) = Trim::parse(&args).unwrap();
+        assert_eq!(taken, args.len());
+        let mut stream = stream();
+        let removed = trim.apply(&mut stream);
+        let exact = trim.exact_args(&stream);
+        let exact: Vec<_> = exact
+            .iter()
+            .map(|arg| String::from_utf8_lossy(arg))
+            .collect();
+        let first_id = stream.entries.first_id().map(|id| id.ms);
+        (removed, first_id, exact.join(" "))
+    }
+
+    #[test]
+    fn exact_trims_remove_entries_up_to_the_threshold() {
+        assert_eq!(trim("MAXLEN 120"), (130, Some(131), "MAXLEN = 120".into()));
+        assert_eq!(trim("MAXLEN = 0"), (250, None, "MAXLEN = 0".into()));
+        assert_eq!(trim("MAXLEN 300"), (0, Some(1), "MAXLEN = 250".into()));
+        assert_eq!(trim("MINID 150"), (149, Some(150), "MINID = 150-0".into()));
+        assert_eq!(trim("MINID = 300"), (250, None, "MINID = 300-0".into()));
+        let args = [
+            b"MAXLEN".to_vec(),
+            b"5".to_vec(),
+            b"LIMIT".to_vec(),
+            b"10".to_vec(),
+        ];
+        assert!(Trim::parse(&args).is_err());
+    }
+
+    #[test]
+    fn approximate_trims_remove_whole_nodes_within_the_limit() {
+        assert_eq!(
+            trim("MAXLEN ~ 120"),
+            (100, Some(101), "MAXLEN = 150".into())
+        );
+        assert_eq!(trim("MAXLEN ~ 0"), (250, None, "MAXLEN = 0".into()));
+        assert_eq!(
+            trim("MINID ~ 150"),
+            (100, Some(101), "MINID = 101-0".into())
+        );
+        assert_eq!(trim("MINID ~ 300"), (250, None, "MINID = 300-0".into()));
+
+        // A node only goes if it fits within what is left of the limit.
+        assert_eq!(
+            trim("MAXLEN ~ 0 LIMIT 99"),
+            (0, Some(1), "MAXLEN = 250".into())
+        );
+        assert_eq!(
+            trim("MAXLEN ~ 0 LIMIT 100"),
+            (100, Some(101), "MAXLEN = 150".into())
+        );
+        assert_eq!(
+            trim("MAXLEN ~ 0 LIMIT 249"),
+            (200, Some(201), "MAXLEN = 50".into())
+        );
+        assert_eq!(
+            trim("MINID ~ 300 LIMIT 199"),
+            (100, Some(101), "MINID = 101-0".into())
+        );
+        // LIMIT 0 means no limit.
+        assert_eq!(trim("MAXLEN ~ 0 LIMIT 0"), (250, None, "MAXLEN = 0".into()));
     }
 }
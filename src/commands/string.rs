@@ -1,66 +1,176 @@
 use crate::protocol;
-use crate::storage::{AppState, DataStoreValue, ValueEntry};
+use crate::storage::{
+    lookup_key, unix_time_ms, AppState, DataStoreValue, Keyspace, ValueEntry,
+};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
 use tokio::io::AsyncWriteExt;
 
+const TYPE_ERR: &str = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+const INT_ERR: &str = "-ERR value is not an integer or out of range\r\n";
+const FLOAT_ERR: &str = "-ERR value is not a valid float\r\n";
+const SYNTAX_ERR: &str = "-ERR syntax error\r\n";
+const MAX_LEN_ERR: &str = "-ERR string exceeds maximum allowed size (proto-max-bulk-len)\r\n";
+
+// Strings are capped at 512MB, like Redis.
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
 pub async fn handle_set<W: AsyncWriteExt + Unpin>(
     stream: &mut W,
     state: &Arc<AppState>,
-    args: &[String],
+    args: &[Vec<u8>],
 ) -> std::io::Result<()> {
     let ok = "+OK\r\n";
-    if let (Some(key), Some(value)) = (args.get(0), args.get(1)) {
-        let mut expires_at = None;
-        if args.len() > 2 && args[2].to_uppercase() == "PX" {
-            if let Some(ms_str) = args.get(3) {
-                if let Ok(ms) = ms_str.parse::<u64>() {
-                    expires_at = Some(Instant::now() + Duration::from_millis(ms));
-                }
+    let (Some(key), Some(value)) = (args.first(), args.get(1)) else {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'set' command\r\n")
+            .await;
+    };
+    let options = match parse_set_options(&args[2..]) {
+        Ok(options) => options,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+
+    let mut map = state.db().lock().await;
+    let old = match lookup_string(&mut map, key) {
+        Ok(old) => old.cloned(),
+        // Only SET ... GET cares about the type of the old value.
+        Err(err) if options.get => return stream.write_all(err.as_bytes()).await,
+        Err(_) => None,
+    };
+    let exists = map.contains_key(key);
+    let allowed = match options.condition {
+        Some(SetCondition::Nx) => !exists,
+        Some(SetCondition::Xx) => exists,
+        None => true,
+    };
+
+    let mut propagate = None;
+    if allowed {
+        let kept_ttl = match options.expiry {
+            SetExpiry::KeepTtl => map.get(key).and_then(|entry| entry.expires_at),
+            _ => None,
+        };
+        let deadline = match options.expiry {
+            SetExpiry::At(deadline) => Some(deadline),
+            _ => None,
+        };
+
+        if deadline.is_some_and(|d| d <= unix_time_ms()) {
+            // A deadline in the past leaves nothing behind.
+            map.remove(key);
+        } else {
+            let expires_at = deadline.or(kept_ttl);
+            let entry = ValueEntry::new(DataStoreValue::String(value.to_vec()), expires_at);
+            map.insert(key.to_vec(), entry);
+        }
+
+        // Expiries are replicated as absolute times so replicas don't drift.
+        let mut command_with_args = vec![b"SET".to_vec(), key.to_vec(), value.to_vec()];
+        match options.expiry {
+            SetExpiry::At(deadline) => {
+                command_with_args.push(b"PXAT".to_vec());
+                command_with_args.push(deadline.to_string().into_bytes());
             }
+            SetExpiry::KeepTtl => command_with_args.push(b"KEEPTTL".to_vec()),
+            SetExpiry::None => {}
         }
-        let mut map = state.db.lock().await;
-        let entry = ValueEntry {
-            value: DataStoreValue::String(value.to_string()),
-            expires_at,
-        };
-        map.insert(key.to_string(), entry);
-        let _ = stream.write_all(ok.as_bytes()).await;
+        propagate = Some(command_with_args);
+    }
+    drop(map);
+
+    if options.get {
+        match old {
+            Some(old) => stream.write_all(&protocol::bulk_string(&old)).await?,
+            None => stream.write_all(b"$-1\r\n").await?,
+        }
+    } else if allowed {
+        stream.write_all(ok.as_bytes()).await?;
+    } else {
+        stream.write_all(b"$-1\r\n").await?;
+    }
 
-        let mut command_with_args = vec!["SET".to_string()];
-        command_with_args.extend_from_slice(args);
+    if let Some(command_with_args) = propagate {
         protocol::replicate_command(state, command_with_args).await?;
+    }
+    Ok(())
+}
 
-        return Ok(());
-    } else {
-        stream
-            .write_all(b"-ERR wrong number of arguments for 'set' command\r\n")
-            .await
+enum SetCondition {
+    Nx,
+    Xx,
+}
+
+enum SetExpiry {
+    None,
+    KeepTtl,
+    /// Absolute Unix time in milliseconds.
+    At(u64),
+}
+
+struct SetOptions {
+    condition: Option<SetCondition>,
+    expiry: SetExpiry,
+    get: bool,
+}
+
+/// Parses the options following `SET key value`, in any order.
+fn parse_set_options(args: &[Vec<u8>]) -> Result<SetOptions, &'static str> {
+    let mut options = SetOptions {
+        condition: None,
+        expiry: SetExpiry::None,
+        get: false,
+    };
+    let mut i = 0;
+    while i < args.len() {
+        let option = protocol::to_upper(&args[i]);
+        match option.as_str() {
+            "NX" | "XX" if options.condition.is_none() => {
+                options.condition = Some(if option == "NX" {
+                    SetCondition::Nx
+                } else {
+                    SetCondition::Xx
+                });
+            }
+            "GET" if !options.get => options.get = true,
+            "KEEPTTL" if matches!(options.expiry, SetExpiry::None) => {
+                options.expiry = SetExpiry::KeepTtl;
+            }
+            "EX" | "PX" | "EXAT" | "PXAT" if matches!(options.expiry, SetExpiry::None) => {
+                let value = args.get(i + 1).ok_or(SYNTAX_ERR)?;
+                let value = protocol::parse_arg::<i64>(value).ok_or(INT_ERR)?;
+                let unit_ms = if option.starts_with('E') { 1000 } else { 1 };
+                let value_ms = value as i128 * unit_ms;
+                if value <= 0 || value_ms > i64::MAX as i128 {
+                    return Err("-ERR invalid expire time in 'set' command\r\n");
+                }
+                let deadline = if option.ends_with("AT") {
+                    value_ms as u64
+                } else {
+                    unix_time_ms() + value_ms as u64
+                };
+                options.expiry = SetExpiry::At(deadline);
+                i += 1;
+            }
+            _ => return Err(SYNTAX_ERR),
+        }
+        i += 1;
     }
+    Ok(options)
 }
 
 pub async fn handle_get<W: AsyncWriteExt + Unpin>(
     stream: &mut W,
     state: &Arc<AppState>,
-    args: &[String],
+    args: &[Vec<u8>],
 ) -> std::io::Result<()> {
     let null = "$-1\r\n";
     let type_err = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
-    if let Some(key) = args.get(0) {
-        let mut map = state.db.lock().await;
+    if let Some(key) = args.first() {
+        let mut map = state.db().lock().await;
+        lookup_key(&mut map, key);
         if let Some(entry) = map.get(key) {
-            // Check expiry
-            if entry.expires_at.map_or(false, |e| Instant::now() > e) {
-                map.remove(key);
-                stream.write_all(null.as_bytes()).await?;
-                return Ok(());
-            }
-
             match &entry.value {
-                DataStoreValue::String(val) => {
-                    let response = format!("${}\r\n{}\r\n", val.len(), val);
-                    stream.write_all(response.as_bytes()).await
-                }
+                DataStoreValue::String(val) => stream.write_all(&protocol::bulk_string(val)).await,
 
                 _ => stream.write_all(type_err.as_bytes()).await,
             }
@@ -77,49 +187,775 @@ pub async fn handle_get<W: AsyncWriteExt + Unpin>(
 pub async fn handle_incr<W: AsyncWriteExt + Unpin>(
     stream: &mut W,
     state: &Arc<AppState>,
-    args: &[String],
-) -> std::io::Result<()> {
-    if let Some(key) = args.get(0) {
-        let mut map = state.db.lock().await;
-        if let Some(entry) = map.get_mut(key) {
-            match &mut entry.value {
-                DataStoreValue::String(val) => {
-                    let prev = match val.parse::<i64>() {
-                        Ok(t) => t,
-                        _ => {
-                            return stream
-                                .write_all(b"-ERR value is not an integer or out of range\r\n")
-                                .await;
-                        }
-                    };
-                    *val = (prev + 1).to_string();
-                    stream.write_all(format!(":{}\r\n", val).as_bytes()).await
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 1 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'incr' command\r\n")
+            .await;
+    }
+    incr_by(stream, state, &args[0], 1, b"INCR", args).await
+}
+
+pub async fn handle_decr<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 1 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'decr' command\r\n")
+            .await;
+    }
+    incr_by(stream, state, &args[0], -1, b"DECR", args).await
+}
+
+pub async fn handle_incrby<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 2 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'incrby' command\r\n")
+            .await;
+    }
+    let Some(delta) = parse_integer(&args[1]) else {
+        return stream.write_all(INT_ERR.as_bytes()).await;
+    };
+    incr_by(stream, state, &args[0], delta, b"INCRBY", args).await
+}
+
+pub async fn handle_decrby<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 2 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'decrby' command\r\n")
+            .await;
+    }
+    let Some(delta) = parse_integer(&args[1]) else {
+        return stream.write_all(INT_ERR.as_bytes()).await;
+    };
+    let Some(delta) = delta.checked_neg() else {
+        return stream.write_all(b"-ERR decrement would overflow\r\n").await;
+    };
+    incr_by(stream, state, &args[0], delta, b"DECRBY", args).await
+}
+
+/// Shared implementation of the integer counters. The key keeps its TTL.
+async fn incr_by<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    key: &[u8],
+    delta: i64,
+    command: &[u8],
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    let mut map = state.db().lock().await;
+    // Only a missing key counts as 0; an empty string is not a number.
+    let missing = matches!(lookup_string_mut(&mut map, key), Ok(None));
+    let result = update_string(&mut map, key, |val| {
+        let current = if missing { Some(0) } else { parse_integer(val) };
+        let new = current
+            .ok_or(INT_ERR)?
+            .checked_add(delta)
+            .ok_or("-ERR increment or decrement would overflow\r\n")?;
+        *val = new.to_string().into_bytes();
+        Ok(new)
+    });
+    drop(map);
+    let new = match result {
+        Ok(new) => new,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+
+    stream.write_all(format!(":{}\r\n", new).as_bytes()).await?;
+    let mut command_with_args = vec![command.to_vec()];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await
+}
+
+pub async fn handle_incrbyfloat<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 2 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'incrbyfloat' command\r\n")
+            .await;
+    }
+    let Some(delta) = parse_float(&args[1]) else {
+        return stream.write_all(FLOAT_ERR.as_bytes()).await;
+    };
+
+    let mut map = state.db().lock().await;
+    let missing = matches!(lookup_string_mut(&mut map, &args[0]), Ok(None));
+    let result = update_string(&mut map, &args[0], |val| {
+        let current = if missing { Some(0.0) } else { parse_float(val) };
+        let new = current.ok_or(FLOAT_ERR)? + delta;
+        if !new.is_finite() {
+            return Err("-ERR increment would produce NaN or Infinity\r\n");
+        }
+        *val = new.to_string().into_bytes();
+        Ok(val.clone())
+    });
+    drop(map);
+    let new = match result {
+        Ok(new) => new,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+
+    stream.write_all(&protocol::bulk_string(&new)).await?;
+
+    // Replicas get the exact resulting value, so float formatting can never
+    // make them diverge.
+    let command_with_args = vec![b"SET".to_vec(), args[0].to_vec(), new, b"KEEPTTL".to_vec()];
+    protocol::replicate_command(state, command_with_args).await
+}
+
+/// Parses an integer the way Redis does: no sign but `-`, no whitespace and
+/// no leading zeros, so a value only counts if it's how the number prints.
+fn parse_integer(arg: &[u8]) -> Option<i64> {
+    protocol::parse_arg::<i64>(arg).filter(|n| n.to_string().as_bytes() == arg)
+}
+
+fn parse_float(arg: &[u8]) -> Option<f64> {
+    if arg.first().is_some_and(|c| *c == b'+' || c.is_ascii_whitespace()) {
+        return None;
+    }
+    protocol::parse_arg::<f64>(arg).filter(|f| !f.is_nan())
+}
+
+pub async fn handle_append<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 2 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'append' command\r\n")
+            .await;
+    }
+
+    let mut map = state.db().lock().await;
+    let result = update_string(&mut map, &args[0], |val| {
+        if val.len() + args[1].len() > MAX_STRING_LEN {
+            return Err(MAX_LEN_ERR);
+        }
+        val.extend_from_slice(&args[1]);
+        Ok(val.len())
+    });
+    drop(map);
+    let len = match result {
+        Ok(len) => len,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+
+    stream.write_all(format!(":{}\r\n", len).as_bytes()).await?;
+    let mut command_with_args = vec![b"APPEND".to_vec()];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await
+}
+
+pub async fn handle_strlen<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 1 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'strlen' command\r\n")
+            .await;
+    }
+
+    let mut map = state.db().lock().await;
+    match lookup_string(&mut map, &args[0]) {
+        Ok(val) => {
+            let len = val.map_or(0, |v| v.len());
+            stream.write_all(format!(":{}\r\n", len).as_bytes()).await
+        }
+        Err(err) => stream.write_all(err.as_bytes()).await,
+    }
+}
+
+pub async fn handle_getrange<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 3 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'getrange' command\r\n")
+            .await;
+    }
+    let (Some(mut start), Some(mut end)) = (
+        protocol::parse_arg::<i64>(&args[1]),
+        protocol::parse_arg::<i64>(&args[2]),
+    ) else {
+        return stream.write_all(INT_ERR.as_bytes()).await;
+    };
+
+    let mut map = state.db().lock().await;
+    let val = match lookup_string(&mut map, &args[0]) {
+        Ok(val) => val.map_or(&[][..], |v| v.as_slice()),
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+
+    let len = val.len() as i64;
+    if start < 0 && end < 0 && start > end {
+        return stream.write_all(b"$0\r\n\r\n").await;
+    }
+    if start < 0 {
+        start = (len + start).max(0);
+    }
+    if end < 0 {
+        end = (len + end).max(0);
+    }
+    end = end.min(len - 1);
+    if start > end || len == 0 {
+        return stream.write_all(b"$0\r\n\r\n").await;
+    }
+
+    let range = &val[start as usize..=end as usize];
+    stream.write_all(&protocol::bulk_string(range)).await
+}
+
+pub async fn handle_setrange<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 3 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'setrange' command\r\n")
+            .await;
+    }
+    let Some(offset) = protocol::parse_arg::<i64>(&args[1]) else {
+        return stream.write_all(INT_ERR.as_bytes()).await;
+    };
+    if offset < 0 {
+        return stream.write_all(b"-ERR offset is out of range\r\n").await;
+    }
+    let offset = offset as usize;
+    let value = &args[2];
+
+    let mut map = state.db().lock().await;
+    lookup_key(&mut map, &args[0]);
+    if value.is_empty() {
+        // Nothing is written, and a missing key is not created.
+        return match lookup_string(&mut map, &args[0]) {
+            Ok(val) => {
+                let len = val.map_or(0, |v| v.len());
+                stream.write_all(format!(":{}\r\n", len).as_bytes()).await
+            }
+            Err(err) => stream.write_all(err.as_bytes()).await,
+        };
+    }
+    if offset + value.len() > MAX_STRING_LEN {
+        return stream.write_all(MAX_LEN_ERR.as_bytes()).await;
+    }
+
+    let result = update_string(&mut map, &args[0], |val| {
+        if val.len() < offset + value.len() {
+            val.resize(offset + value.len(), 0);
+        }
+        val[offset..offset + value.len()].copy_from_slice(value);
+        Ok(val.len())
+    });
+    drop(map);
+    let len = match result {
+        Ok(len) => len,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+
+    stream.write_all(format!(":{}\r\n", len).as_bytes()).await?;
+    let mut command_with_args = vec![b"SETRANGE".to_vec()];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await
+}
+
+pub async fn handle_mget<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.is_empty() {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'mget' command\r\n")
+            .await;
+    }
+
+    let mut map = state.db().lock().await;
+    let mut response = format!("*{}\r\n", args.len()).into_bytes();
+    for key in args {
+        // Keys holding other types are reported as missing.
+        match lookup_string(&mut map, key) {
+            Ok(Some(val)) => protocol::push_bulk_string(&mut response, val),
+            _ => response.extend_from_slice(b"$-1\r\n"),
+        }
+    }
+    stream.write_all(&response).await
+}
+
+pub async fn handle_mset<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'mset' command\r\n")
+            .await;
+    }
+
+    let mut map = state.db().lock().await;
+    for pair in args.chunks(2) {
+        map.insert(pair[0].to_vec(), new_string_entry(pair[1].to_vec()));
+    }
+    drop(map);
+
+    stream.write_all(b"+OK\r\n").await?;
+    let mut command_with_args = vec![b"MSET".to_vec()];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await
+}
+
+pub async fn handle_msetnx<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'msetnx' command\r\n")
+            .await;
+    }
+
+    let mut map = state.db().lock().await;
+    for pair in args.chunks(2) {
+        lookup_key(&mut map, &pair[0]);
+        if map.contains_key(&pair[0]) {
+            return stream.write_all(b":0\r\n").await;
+        }
+    }
+    for pair in args.chunks(2) {
+        map.insert(pair[0].to_vec(), new_string_entry(pair[1].to_vec()));
+    }
+    drop(map);
+
+    stream.write_all(b":1\r\n").await?;
+    let mut command_with_args = vec![b"MSETNX".to_vec()];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await
+}
+
+pub async fn handle_setnx<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 2 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'setnx' command\r\n")
+            .await;
+    }
+
+    let mut map = state.db().lock().await;
+    lookup_key(&mut map, &args[0]);
+    if map.contains_key(&args[0]) {
+        return stream.write_all(b":0\r\n").await;
+    }
+    map.insert(args[0].to_vec(), new_string_entry(args[1].to_vec()));
+    drop(map);
+
+    stream.write_all(b":1\r\n").await?;
+    let mut command_with_args = vec![b"SETNX".to_vec()];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await
+}
+
+pub async fn handle_setex<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    set_with_ttl(stream, state, args, 1000, "setex").await
+}
+
+pub async fn handle_psetex<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    set_with_ttl(stream, state, args, 1, "psetex").await
+}
+
+/// Shared implementation of SETEX and PSETEX, where the TTL argument is
+/// multiplied by `unit_ms`.
+async fn set_with_ttl<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+    unit_ms: u64,
+    name: &str,
+) -> std::io::Result<()> {
+    if args.len() != 3 {
+        let err = format!("-ERR wrong number of arguments for '{}' command\r\n", name);
+        return stream.write_all(err.as_bytes()).await;
+    }
+    let Some(ttl) = protocol::parse_arg::<i64>(&args[1]) else {
+        return stream.write_all(INT_ERR.as_bytes()).await;
+    };
+    let deadline = u64::try_from(ttl)
+        .ok()
+        .and_then(|t| t.checked_mul(unit_ms))
+        .filter(|&t| t > 0)
+        .and_then(|t| unix_time_ms().checked_add(t));
+    let Some(deadline) = deadline else {
+        let err = format!("-ERR invalid expire time in '{}' command\r\n", name);
+        return stream.write_all(err.as_bytes()).await;
+    };
+
+    let mut map = state.db().lock().await;
+    map.insert(
+        args[0].to_vec(),
+        ValueEntry::new(DataStoreValue::String(args[2].to_vec()), Some(deadline)),
+    );
+    drop(map);
+
+    stream.write_all(b"+OK\r\n").await?;
+    // Like SET, the expiry is replicated as an absolute time.
+    let command_with_args = vec![
+        b"SET".to_vec(),
+        args[0].to_vec(),
+        args[2].to_vec(),
+        b"PXAT".to_vec(),
+        deadline.to_string().into_bytes(),
+    ];
+    protocol::replicate_command(state, command_with_args).await
+}
+
+pub async fn handle_getset<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 2 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'getset' command\r\n")
+            .await;
+    }
+
+    let mut map = state.db().lock().await;
+    let old = match lookup_string(&mut map, &args[0]) {
+        Ok(val) => val.cloned(),
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    map.insert(args[0].to_vec(), new_string_entry(args[1].to_vec()));
+    drop(map);
+
+    match old {
+        Some(old) => stream.write_all(&protocol::bulk_string(&old)).await?,
+        None => stream.write_all(b"$-1\r\n").await?,
+    }
+    let command_with_args = vec![b"SET".to_vec(), args[0].to_vec(), args[1].to_vec()];
+    protocol::replicate_command(state, command_with_args).await
+}
+
+pub async fn handle_getdel<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() != 1 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'getdel' command\r\n")
+            .await;
+    }
+
+    let mut map = state.db().lock().await;
+    let val = match lookup_string(&mut map, &args[0]) {
+        Ok(Some(val)) => val.clone(),
+        Ok(None) => return stream.write_all(b"$-1\r\n").await,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    map.remove(&args[0]);
+    drop(map);
+
+    stream.write_all(&protocol::bulk_string(&val)).await?;
+    let mut command_with_args = vec![b"GETDEL".to_vec()];
+    command_with_args.extend_from_slice(args);
+    protocol::replicate_command(state, command_with_args).await
+}
+
+pub async fn handle_getex<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.is_empty() {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'getex' command\r\n")
+            .await;
+    }
+
+    // `None` leaves the TTL alone, `Some(None)` persists the key and
+    // `Some(Some(ms))` sets an absolute Unix deadline in milliseconds.
+    let mut new_expiry: Option<Option<u64>> = None;
+    let mut i = 1;
+    while i < args.len() {
+        let option = protocol::to_upper(&args[i]);
+        if option == "PERSIST" && new_expiry.is_none() {
+            new_expiry = Some(None);
+            i += 1;
+            continue;
+        }
+        let unit_ms = match option.as_str() {
+            "EX" | "EXAT" => 1000,
+            "PX" | "PXAT" => 1,
+            _ => return stream.write_all(SYNTAX_ERR.as_bytes()).await,
+        };
+        if new_expiry.is_some() || i + 1 >= args.len() {
+            return stream.write_all(SYNTAX_ERR.as_bytes()).await;
+        }
+        let Some(value) = protocol::parse_arg::<i64>(&args[i + 1]) else {
+            return stream.write_all(INT_ERR.as_bytes()).await;
+        };
+        let value_ms = (value as i128) * unit_ms;
+        if value <= 0 || value_ms > i64::MAX as i128 {
+            return stream
+                .write_all(b"-ERR invalid expire time in 'getex' command\r\n")
+                .await;
+        }
+        let deadline = if option.ends_with("AT") {
+            value_ms as u64
+        } else {
+            unix_time_ms() + value_ms as u64
+        };
+        new_expiry = Some(Some(deadline));
+        i += 2;
+    }
+
+    let mut map = state.db().lock().await;
+    let val = match lookup_string(&mut map, &args[0]) {
+        Ok(Some(val)) => val.clone(),
+        Ok(None) => return stream.write_all(b"$-1\r\n").await,
+        Err(err) => return stream.write_all(err.as_bytes()).await,
+    };
+    let mut command_with_args = None;
+    if let Some(expiry) = new_expiry {
+        match expiry {
+            Some(deadline) if deadline <= unix_time_ms() => {
+                map.remove(&args[0]);
+            }
+            Some(deadline) => map.set_expiry(&args[0], Some(deadline)),
+            None => map.set_expiry(&args[0], None),
+        }
+
+        // Relative TTLs are replicated as absolute deadlines.
+        let option = match expiry {
+            Some(deadline) => vec![b"PXAT".to_vec(), deadline.to_string().into_bytes()],
+            None => vec![b"PERSIST".to_vec()],
+        };
+        let mut command = vec![b"GETEX".to_vec(), args[0].to_vec()];
+        command.extend(option);
+        command_with_args = Some(command);
+    }
+    drop(map);
+
+    stream.write_all(&protocol::bulk_string(&val)).await?;
+    if let Some(command_with_args) = command_with_args {
+        protocol::replicate_command(state, command_with_args).await?;
+    }
+    Ok(())
+}
+
+pub async fn handle_lcs<W: AsyncWriteExt + Unpin>(
+    stream: &mut W,
+    state: &Arc<AppState>,
+    args: &[Vec<u8>],
+) -> std::io::Result<()> {
+    if args.len() < 2 {
+        return stream
+            .write_all(b"-ERR wrong number of arguments for 'lcs' command\r\n")
+            .await;
+    }
+
+    let mut get_len = false;
+    let mut get_idx = false;
+    let mut with_match_len = false;
+    let mut min_match_len = 0;
+    let mut i = 2;
+    while i < args.len() {
+        match protocol::to_upper(&args[i]).as_str() {
+            "LEN" => get_len = true,
+            "IDX" => get_idx = true,
+            "WITHMATCHLEN" => with_match_len = true,
+            "MINMATCHLEN" if i + 1 < args.len() => {
+                let Some(len) = protocol::parse_arg::<i64>(&args[i + 1]) else {
+                    return stream.write_all(INT_ERR.as_bytes()).await;
+                };
+                min_match_len = len.max(0) as usize;
+                i += 1;
+            }
+            _ => return stream.write_all(SYNTAX_ERR.as_bytes()).await,
+        }
+        i += 1;
+    }
+    if get_len && get_idx {
+        return stream
+            .write_all(b"-ERR If you want both the length and indexes, please just use IDX.\r\n")
+            .await;
+    }
+
+    let mut map = state.db().lock().await;
+    let mut values = Vec::with_capacity(2);
+    for key in &args[..2] {
+        match lookup_string(&mut map, key) {
+            Ok(val) => values.push(val.cloned().unwrap_or_default()),
+            Err(_) => {
+                return stream
+                    .write_all(b"-ERR The specified keys must contain string values\r\n")
+                    .await;
+            }
+        }
+    }
+    drop(map);
+    let (a, b) = (&values[0], &values[1]);
+
+    // lcs[i][j] holds the LCS length of a[..i] and b[..j].
+    let width = b.len() + 1;
+    let mut lcs = vec![0u32; (a.len() + 1) * width];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            lcs[i * width + j] = if a[i - 1] == b[j - 1] {
+                lcs[(i - 1) * width + j - 1] + 1
+            } else {
+                lcs[(i - 1) * width + j].max(lcs[i * width + j - 1])
+            };
+        }
+    }
+    let total = lcs[a.len() * width + b.len()] as usize;
+
+    if get_len {
+        return stream.write_all(format!(":{}\r\n", total).as_bytes()).await;
+    }
+
+    // Walk back from the end, rebuilding the sequence and the matching ranges
+    // (reported from last to first, as Redis does).
+    let mut result = vec![0u8; total];
+    let mut matches = Vec::new();
+    let mut idx = total;
+    let (mut i, mut j) = (a.len(), b.len());
+    let mut range: Option<(usize, usize, usize, usize)> = None;
+    while i > 0 && j > 0 {
+        let mut emit = false;
+        if a[i - 1] == b[j - 1] {
+            result[idx - 1] = a[i - 1];
+            match range.as_mut() {
+                None => range = Some((i - 1, i - 1, j - 1, j - 1)),
+                Some((a_start, _, b_start, _)) if *a_start == i && *b_start == j => {
+                    *a_start -= 1;
+                    *b_start -= 1;
                 }
-                _ => {
-                    stream
-                        .write_all(b"-ERR value is not an integer or out of range\r\n")
-                        .await
+                Some(_) => emit = true,
+            }
+            if range.is_some_and(|(a_start, _, b_start, _)| a_start == 0 || b_start == 0) {
+                emit = true;
+            }
+            idx -= 1;
+            i -= 1;
+            j -= 1;
+        } else {
+            if lcs[(i - 1) * width + j] > lcs[i * width + j - 1] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+            emit = range.is_some();
+        }
+
+        if emit {
+            if let Some(current) = range.take() {
+                if current.1 - current.0 + 1 >= min_match_len {
+                    matches.push(current);
                 }
             }
+        }
+    }
+
+    if !get_idx {
+        return stream.write_all(&protocol::bulk_string(&result)).await;
+    }
+
+    let mut response = format!("*4\r\n$7\r\nmatches\r\n*{}\r\n", matches.len()).into_bytes();
+    for (a_start, a_end, b_start, b_end) in matches {
+        let len = a_end - a_start + 1;
+        let entry = if with_match_len {
+            format!(
+                "*3\r\n*2\r\n:{}\r\n:{}\r\n*2\r\n:{}\r\n:{}\r\n:{}\r\n",
+                a_start, a_end, b_start, b_end, len
+            )
         } else {
-            map.insert(
-                key.to_string(),
-                ValueEntry {
-                    value: DataStoreValue::String("1".to_string()),
-                    expires_at: None,
-                },
-            );
-            let _ = stream.write_all(":1\r\n".as_bytes()).await;
+            format!(
+                "*2\r\n*2\r\n:{}\r\n:{}\r\n*2\r\n:{}\r\n:{}\r\n",
+                a_start, a_end, b_start, b_end
+            )
+        };
+        response.extend_from_slice(entry.as_bytes());
+    }
+    response.extend_from_slice(format!("$3\r\nlen\r\n:{}\r\n", total).as_bytes());
+    stream.write_all(&response).await
+}
 
-            let mut command_with_args = vec!["INCR".to_string()];
-            command_with_args.extend_from_slice(args);
-            protocol::replicate_command(state, command_with_args).await?;
+pub fn new_string_entry(value: Vec<u8>) -> ValueEntry {
+    ValueEntry::new(DataStoreValue::String(value), None)
+}
 
-            return Ok(());
-        }
-    } else {
-        stream
-            .write_all(b"-ERR wrong number of arguments for 'incr' command\r\n")
-            .await
+/// Looks up the string stored at `key`, treating expired keys as missing.
+pub fn lookup_string<'a>(
+    map: &'a mut Keyspace,
+    key: &[u8],
+) -> Result<Option<&'a Vec<u8>>, &'static str> {
+    lookup_key(map, key);
+    match map.get(key).map(|entry| &entry.value) {
+        None => Ok(None),
+        Some(DataStoreValue::String(val)) => Ok(Some(val)),
+        Some(_) => Err(TYPE_ERR),
+    }
+}
+
+/// Looks up the string stored at `key` for writing, treating expired keys as
+/// missing.
+pub fn lookup_string_mut<'a>(
+    map: &'a mut Keyspace,
+    key: &[u8],
+) -> Result<Option<&'a mut Vec<u8>>, &'static str> {
+    lookup_key(map, key);
+    match map.get_mut(key).map(|entry| &mut entry.value) {
+        None => Ok(None),
+        Some(DataStoreValue::String(val)) => Ok(Some(val)),
+        Some(_) => Err(TYPE_ERR),
+    }
+}
+
+/// Applies `update` to the string stored at `key`. A missing key starts out
+/// as an empty string, which is only stored once `update` has succeeded, so
+/// a failed write leaves no key behind.
+pub fn update_string<T>(
+    map: &mut Keyspace,
+    key: &[u8],
+    update: impl FnOnce(&mut Vec<u8>) -> Result<T, &'static str>,
+) -> Result<T, &'static str> {
+    if let Some(val) = lookup_string_mut(map, key)? {
+        return update(val);
     }
+    let mut val = Vec::new();
+    let result = update(&mut val)?;
+    map.insert(key.to_vec(), new_string_entry(val));
+    Ok(result)
 }
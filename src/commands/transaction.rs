@@ -1,7 +1,7 @@
 use crate::commands::handle_command;
 use crate::storage::{AppState, TransactionState};
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::AsyncWriteExt;
 
 pub async fn handle_multi<W: AsyncWriteExt + Unpin>(
     stream: &mut W,
@@ -31,29 +31,25 @@ pub async fn handle_exec<W: AsyncWriteExt + Unpin>(
     transation_state.queued_commands.clear();
     transation_state.in_transaction = false;
 
-    let mut response = String::new();
-    response.push_str(&format!("*{}\r\n", queued_commands.len()));
+    let mut response = format!("*{}\r\n", queued_commands.len()).into_bytes();
 
+    transation_state.in_exec = true;
     for commands in queued_commands {
-        let (mut reader, mut writer) = tokio::io::duplex(4096);
+        // Each reply is written straight into the response buffer, so replies
+        // of any size and content are collected as-is.
         let stream_id = String::from("");
         let _ = Box::pin(handle_command(
             commands.to_vec(),
-            &mut writer,
+            &mut response,
             state,
             transation_state,
             stream_id
         ))
         .await;
-
-        drop(writer);
-
-        let mut buf = Vec::new();
-        reader.read_to_end(&mut buf).await?;
-        response.push_str(String::from_utf8_lossy(&buf).as_ref());
     }
+    transation_state.in_exec = false;
 
-    stream.write_all(response.as_bytes()).await
+    stream.write_all(&response).await
 }
 
 pub async fn handle_discard<W: AsyncWriteExt + Unpin>(
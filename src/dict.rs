@@ -0,0 +1,398 @@
+// A chained hash table with a power-of-two number of buckets, built so it can
+// be iterated with a stateless cursor the way Redis's SCAN works.
+//
+// The cursor is a bucket index whose bits are incremented from the most
+// significant end. When the table grows from 2^n to 2^(n+1) buckets, bucket
+// `b` splits into `b` and `b | 2^n`; when it shrinks, those two merge back
+// into `b`. Because the reversed increment visits every bucket sharing the
+// same low bits before moving on, a cursor taken at one size stays valid at
+// any other size: buckets that were already visited are never skipped, at
+// worst some keys are returned twice.
+//
+// Like Redis's dict, the table is resized incrementally. The new table is
+// allocated next to the old one, and every write moves one more bucket of the
+// old table across, so no single command pays for rehashing the whole
+// keyspace. Lookups check both tables until the old one is empty, and a scan
+// visits a bucket of the smaller table together with all the buckets of the
+// larger one it expands to. Buckets are the heads of linked lists of boxed
+// entries: an empty bucket takes a single pointer, and moving an entry to the
+// new table only relinks it.
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::iter;
+
+use rand::Rng;
+
+const MIN_BUCKETS: usize = 4;
+
+/// How many empty buckets a rehash step may skip for every bucket it is
+/// asked to move, so a sparse old table can't make one write slow.
+const REHASH_EMPTY_VISITS: usize = 10;
+
+type Bucket<K, V> = Option<Box<Entry<K, V>>>;
+
+struct Entry<K, V> {
+    key: K,
+    value: V,
+    next: Bucket<K, V>,
+}
+
+pub struct Dict<K, V> {
+    /// The table in use, and while resizing, the one its entries are being
+    /// moved to. The second table is empty otherwise.
+    tables: [Vec<Bucket<K, V>>; 2],
+    /// The next bucket of the first table to move, while resizing.
+    rehash_index: Option<usize>,
+    len: usize,
+    hasher: RandomState,
+}
+
+impl<K: Hash + Eq, V> Default for Dict<K, V> {
+    fn default() -> Self {
+        Dict {
+            tables: [new_table(MIN_BUCKETS), Vec::new()],
+            rehash_index: None,
+            len: 0,
+            hasher: RandomState::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> Dict<K, V> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn bucket_count(&self) -> usize {
+        self.tables[0].len() + self.tables[1].len()
+    }
+
+    /// Bytes taken by the buckets and the entries' nodes, not counting what
+    /// the keys and values allocate themselves.
+    pub fn overhead(&self) -> usize {
+        self.bucket_count() * std::mem::size_of::<Bucket<K, V>>()
+            + self.len * std::mem::size_of::<Entry<K, V>>()
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hasher.hash_one(key) as usize;
+        self.tables
+            .iter()
+            .filter(|table| !table.is_empty())
+            .flat_map(|table| chain(&table[hash & (table.len() - 1)]))
+            .find(|entry| entry.key.borrow() == key)
+            .map(|entry| &entry.value)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.rehash(1);
+        let hash = self.hasher.hash_one(key) as usize;
+        for table in self.tables.iter_mut().filter(|table| !table.is_empty()) {
+            let mask = table.len() - 1;
+            let mut entry = table[hash & mask].as_deref_mut();
+            while let Some(current) = entry {
+                if current.key.borrow() == key {
+                    return Some(&mut current.value);
+                }
+                entry = current.next.as_deref_mut();
+            }
+        }
+        None
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Inserts a value, returning the one it replaced.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(current) = self.get_mut(&key) {
+            return Some(std::mem::replace(current, value));
+        }
+        self.insert_new(key, value);
+        None
+    }
+
+    /// Returns the value stored at `key`, inserting one made by `default`
+    /// first if there is none.
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, default: F) -> &mut V {
+        if self.contains_key(&key) {
+            self.get_mut(&key).unwrap()
+        } else {
+            self.insert_new(key, default())
+        }
+    }
+
+    // Adds a key known not to be present, starting to grow the table once it
+    // holds as many keys as buckets. While resizing, new keys go straight to
+    // the new table.
+    fn insert_new(&mut self, key: K, value: V) -> &mut V {
+        self.rehash(1);
+        if self.rehash_index.is_none() && self.len >= self.tables[0].len() {
+            self.start_rehash(self.tables[0].len() * 2);
+        }
+        let table = &mut self.tables[self.rehash_index.is_some() as usize];
+        let mask = table.len() - 1;
+        let bucket = &mut table[self.hasher.hash_one(&key) as usize & mask];
+        let next = bucket.take();
+        self.len += 1;
+        &mut bucket.insert(Box::new(Entry { key, value, next })).value
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.rehash(1);
+        let hash = self.hasher.hash_one(key) as usize;
+        let value = self
+            .tables
+            .iter_mut()
+            .filter(|table| !table.is_empty())
+            .find_map(|table| {
+                let mask = table.len() - 1;
+                let mut link = &mut table[hash & mask];
+                while link.as_ref().is_some_and(|entry| entry.key.borrow() != key) {
+                    link = &mut link.as_mut().unwrap().next;
+                }
+                let mut entry = link.take()?;
+                *link = entry.next.take();
+                let Entry { value, .. } = *entry;
+                Some(value)
+            })?;
+        self.len -= 1;
+        // Shrink once the table is less than an eighth full.
+        let size = self.tables[0].len();
+        if self.rehash_index.is_none() && size > MIN_BUCKETS && self.len < size / 8 {
+            self.start_rehash(size / 2);
+        }
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.tables
+            .iter()
+            .flatten()
+            .flat_map(chain)
+            .map(|entry| (&entry.key, &entry.value))
+    }
+
+    /// Returns a random entry: a random non-empty bucket of either table,
+    /// then a random entry within it.
+    pub fn random_entry(&self) -> Option<(&K, &V)> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut rng = rand::thread_rng();
+        // Buckets of the old table before the rehash index are all empty.
+        let [old, new] = &self.tables;
+        let first = self.rehash_index.unwrap_or(0);
+        loop {
+            let index = rng.gen_range(first..old.len() + new.len());
+            let bucket = match index.checked_sub(old.len()) {
+                Some(index) => &new[index],
+                None => &old[index],
+            };
+            let mut entries = chain(bucket);
+            let len = entries.clone().count();
+            if len > 0 {
+                let entry = entries.nth(rng.gen_range(0..len)).unwrap();
+                return Some((&entry.key, &entry.value));
+            }
+        }
+    }
+
+    /// Visits every entry of the bucket at `cursor` and returns the cursor to
+    /// continue from, which is 0 once the whole table has been covered. While
+    /// resizing, that is the bucket of the smaller table and every bucket of
+    /// the larger one sharing its low bits.
+    pub fn scan<F: FnMut(&K, &V)>(&self, cursor: u64, mut visit: F) -> u64 {
+        let [first, second] = &self.tables;
+        let (small, large) = if second.is_empty() || first.len() < second.len() {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        let mut visit_bucket = |table: &[Bucket<K, V>], cursor: u64| {
+            let bucket = &table[(cursor & (table.len() - 1) as u64) as usize];
+            chain(bucket).for_each(|entry| visit(&entry.key, &entry.value));
+        };
+
+        let small_mask = (small.len() - 1) as u64;
+        visit_bucket(small, cursor);
+        if large.is_empty() {
+            return next_cursor(cursor, small_mask);
+        }
+        let large_mask = (large.len() - 1) as u64;
+        let mut cursor = cursor;
+        loop {
+            visit_bucket(large, cursor);
+            cursor = next_cursor(cursor, large_mask);
+            // Once the bits only the larger table has wrap around, the
+            // increment has carried into the bits of the smaller one.
+            if cursor & (small_mask ^ large_mask) == 0 {
+                return cursor;
+            }
+        }
+    }
+
+    /// Moves up to `buckets` non-empty buckets of the old table to the new
+    /// one, and switches over to the new table once the old one is empty.
+    /// Returns whether a resize is still under way.
+    pub fn rehash(&mut self, buckets: usize) -> bool {
+        let Some(mut index) = self.rehash_index else {
+            return false;
+        };
+        let [old, new] = &mut self.tables;
+        let mask = new.len() - 1;
+        let (mut moved, mut empty_visits) = (0, buckets * REHASH_EMPTY_VISITS);
+        while moved < buckets && index < old.len() {
+            let mut entries = old[index].take();
+            index += 1;
+            if entries.is_none() {
+                empty_visits -= 1;
+                if empty_visits == 0 {
+                    break;
+                }
+                continue;
+            }
+            while let Some(mut entry) = entries {
+                entries = entry.next.take();
+                let bucket = &mut new[self.hasher.hash_one(&entry.key) as usize & mask];
+                entry.next = bucket.take();
+                *bucket = Some(entry);
+            }
+            moved += 1;
+        }
+        if index < old.len() {
+            self.rehash_index = Some(index);
+            return true;
+        }
+        self.tables[0] = std::mem::take(&mut self.tables[1]);
+        self.rehash_index = None;
+        false
+    }
+
+    fn start_rehash(&mut self, size: usize) {
+        self.tables[1] = new_table(size.max(MIN_BUCKETS));
+        self.rehash_index = Some(0);
+    }
+}
+
+fn new_table<K, V>(size: usize) -> Vec<Bucket<K, V>> {
+    iter::repeat_with(|| None).take(size).collect()
+}
+
+/// The entries linked from a bucket.
+fn chain<K, V>(bucket: &Bucket<K, V>) -> impl Iterator<Item = &Entry<K, V>> + Clone {
+    iter::successors(bucket.as_deref(), |entry| entry.next.as_deref())
+}
+
+/// Increments the bits of `cursor` under `mask`, starting from the most
+/// significant one.
+fn next_cursor(cursor: u64, mask: u64) -> u64 {
+    (cursor | !mask)
+        .reverse_bits()
+        .wrapping_add(1)
+        .reverse_bits()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn resizing_moves_a_bucket_per_write() {
+        let mut dict = Dict::default();
+        for i in 0..1024 {
+            dict.insert(i, i);
+        }
+        // Growing past 1024 buckets starts a resize that later writes finish.
+        dict.insert(1024, 1024);
+        assert_eq!(dict.bucket_count(), 1024 + 2048);
+        assert!(dict.rehash(0));
+        for i in 0..1025 {
+            assert_eq!(dict.get(&i), Some(&i));
+        }
+        let mut writes = 0;
+        while dict.bucket_count() != 2048 {
+            dict.get_mut(&0);
+            writes += 1;
+        }
+        assert!(writes > 100, "rehashed in {} writes", writes);
+        assert_eq!(dict.len(), 1025);
+        assert_eq!(dict.iter().count(), 1025);
+    }
+
+    #[test]
+    fn removes_work_across_both_tables() {
+        let mut dict = Dict::default();
+        for i in 0..2000 {
+            dict.insert(i, i);
+        }
+        for i in (0..2000).step_by(2) {
+            assert_eq!(dict.remove(&i), Some(i));
+        }
+        assert_eq!(dict.remove(&0), None);
+        for i in 0..1990 {
+            dict.remove(&i);
+        }
+        assert_eq!(dict.len(), 5);
+        assert_eq!(
+            dict.iter().map(|(k, _)| *k).collect::<HashSet<_>>(),
+            HashSet::from([1991, 1993, 1995, 1997, 1999])
+        );
+        while dict.rehash(100) {}
+        assert!(dict.bucket_count() < 2048);
+        assert_eq!(dict.random_entry().map(|(k, _)| k % 2), Some(1));
+    }
+
+    #[test]
+    fn scan_returns_every_key_while_the_table_resizes() {
+        let mut dict = Dict::default();
+        for i in 0..500 {
+            dict.insert(i, ());
+        }
+        // Keys present for the whole scan must be returned, whatever the
+        // table does in between calls.
+        let mut seen = HashSet::new();
+        let mut cursor = 0;
+        let mut next = 500;
+        loop {
+            cursor = dict.scan(cursor, |k, _| {
+                seen.insert(*k);
+            });
+            if cursor == 0 {
+                break;
+            }
+            if next < 3000 {
+                dict.insert(next, ());
+                next += 1;
+            } else {
+                dict.remove(&(next - 2500));
+                next += 1;
+            }
+        }
+        assert!((0..500).all(|i| seen.contains(&i)));
+    }
+}
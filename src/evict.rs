@@ -0,0 +1,188 @@
+// Eviction of keys once the dataset grows over `maxmemory`.
+//
+// As in Redis, victims are picked by sampling rather than by keeping keys
+// ordered: a handful of random candidates is drawn from every database into a
+// small pool that survives between evictions, and the best candidate in the
+// pool is evicted. With a few samples this gets close to true LRU/LFU at no
+// bookkeeping cost beyond the per-key access clock and counter. Evicted keys
+// are propagated to replicas as `DEL`.
+
+use std::sync::Arc;
+
+use rand::Rng;
+use tokio::sync::Mutex;
+
+use crate::memory;
+use crate::storage::{AppState, Keyspace};
+
+const EVICTION_POOL_SIZE: usize = 16;
+
+/// A key that may be evicted, with a score that is higher the better a victim
+/// it is.
+pub struct EvictionCandidate {
+    score: u64,
+    db: usize,
+    key: Vec<u8>,
+}
+
+/// The best candidates seen so far, ordered by ascending score.
+pub type EvictionPool = Mutex<Vec<EvictionCandidate>>;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    NoEviction,
+    AllKeysLru,
+    VolatileLru,
+    AllKeysLfu,
+    VolatileLfu,
+    AllKeysRandom,
+    VolatileRandom,
+    VolatileTtl,
+}
+
+impl EvictionPolicy {
+    pub fn parse(name: &str) -> Option<EvictionPolicy> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "noeviction" => EvictionPolicy::NoEviction,
+            "allkeys-lru" => EvictionPolicy::AllKeysLru,
+            "volatile-lru" => EvictionPolicy::VolatileLru,
+            "allkeys-lfu" => EvictionPolicy::AllKeysLfu,
+            "volatile-lfu" => EvictionPolicy::VolatileLfu,
+            "allkeys-random" => EvictionPolicy::AllKeysRandom,
+            "volatile-random" => EvictionPolicy::VolatileRandom,
+            "volatile-ttl" => EvictionPolicy::VolatileTtl,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EvictionPolicy::NoEviction => "noeviction",
+            EvictionPolicy::AllKeysLru => "allkeys-lru",
+            EvictionPolicy::VolatileLru => "volatile-lru",
+            EvictionPolicy::AllKeysLfu => "allkeys-lfu",
+            EvictionPolicy::VolatileLfu => "volatile-lfu",
+            EvictionPolicy::AllKeysRandom => "allkeys-random",
+            EvictionPolicy::VolatileRandom => "volatile-random",
+            EvictionPolicy::VolatileTtl => "volatile-ttl",
+        }
+    }
+
+    /// Whether only keys with a deadline may be evicted.
+    fn volatile_only(self) -> bool {
+        matches!(
+            self,
+            EvictionPolicy::VolatileLru
+                | EvictionPolicy::VolatileLfu
+                | EvictionPolicy::VolatileRandom
+                | EvictionPolicy::VolatileTtl
+        )
+    }
+}
+
+/// Evicts keys until memory use is back under `maxmemory`. Returns false when
+/// the limit is still exceeded and the policy has nothing left to evict, in
+/// which case commands that would use more memory must be refused.
+pub async fn free_memory_if_needed(state: &Arc<AppState>) -> bool {
+    if state.maxmemory == 0 {
+        return true;
+    }
+    while memory::dataset_memory() as u64 > state.maxmemory {
+        if state.maxmemory_policy == EvictionPolicy::NoEviction {
+            return false;
+        }
+
+        let evicted = match state.maxmemory_policy {
+            EvictionPolicy::AllKeysRandom | EvictionPolicy::VolatileRandom => {
+                evict_random(state).await
+            }
+            _ => evict_from_pool(state).await,
+        };
+        if !evicted {
+            return false;
+        }
+    }
+    true
+}
+
+/// Evicts a random key, starting the search at a random database.
+async fn evict_random(state: &AppState) -> bool {
+    let start = rand::thread_rng().gen_range(0..state.databases.len());
+    for offset in 0..state.databases.len() {
+        let mut map = state.databases[(start + offset) % state.databases.len()]
+            .lock()
+            .await;
+        let key = if state.maxmemory_policy.volatile_only() {
+            map.random_volatile_key()
+        } else {
+            map.random_entry().map(|(key, _)| key.clone())
+        };
+        if let Some(key) = key {
+            map.remove(&key);
+            map.push_expired(key);
+            return true;
+        }
+    }
+    false
+}
+
+/// Refills the pool from every database and evicts its best candidate that
+/// still exists.
+async fn evict_from_pool(state: &AppState) -> bool {
+    let mut pool = state.eviction_pool.lock().await;
+    for (index, db) in state.databases.iter().enumerate() {
+        let mut map = db.lock().await;
+        populate_pool(&mut pool, &mut map, index, state);
+    }
+
+    while let Some(candidate) = pool.pop() {
+        let mut map = state.databases[candidate.db].lock().await;
+        if map.remove(&candidate.key).is_some() {
+            map.push_expired(candidate.key);
+            return true;
+        }
+    }
+    false
+}
+
+/// Samples keys of one database into the pool, keeping only the best
+/// `EVICTION_POOL_SIZE` candidates.
+fn populate_pool(
+    pool: &mut Vec<EvictionCandidate>,
+    map: &mut Keyspace,
+    db: usize,
+    state: &AppState,
+) {
+    let policy = state.maxmemory_policy;
+    for _ in 0..state.maxmemory_samples {
+        let key = if policy.volatile_only() {
+            map.random_volatile_key()
+        } else {
+            map.random_entry().map(|(key, _)| key.clone())
+        };
+        let Some(key) = key else {
+            break;
+        };
+        let entry = map.get(&key).unwrap();
+        let score = match policy {
+            EvictionPolicy::AllKeysLfu | EvictionPolicy::VolatileLfu => {
+                (u8::MAX - entry.lfu_count()) as u64
+            }
+            // The key closest to its deadline goes first.
+            EvictionPolicy::VolatileTtl => u64::MAX - entry.expires_at.unwrap_or(u64::MAX),
+            _ => entry.idle_ms(),
+        };
+
+        // A key already in the pool is rescored, since it may have been
+        // accessed since it was added.
+        pool.retain(|candidate| candidate.db != db || candidate.key != key);
+        if pool.len() == EVICTION_POOL_SIZE {
+            if score <= pool[0].score {
+                continue;
+            }
+            pool.remove(0);
+        }
+        let position = pool.partition_point(|candidate| candidate.score <= score);
+        pool.insert(position, EvictionCandidate { score, db, key });
+    }
+}
@@ -4,13 +4,23 @@ use std::sync::Arc;
 use tokio::sync::{broadcast, Mutex};
 use std::env;
 
-use crate::storage::AppState;
+use crate::evict::EvictionPolicy;
+use crate::storage::{AppState, BlockedClients, Keyspace};
 
 // Declare the modules to make them available
 mod commands;
+mod dict;
+mod evict;
+mod hyperloglog;
+mod memory;
 mod protocol;
+mod rdb;
 mod server;
 mod storage;
+mod stream_entries;
+
+#[global_allocator]
+static ALLOCATOR: memory::CountingAllocator = memory::CountingAllocator;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -21,52 +31,76 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     let replica_of = if env::args().any(|arg| arg == "--replicaof") {
         let idx = env::args().position(|arg| arg == "--replicaof").unwrap();
-        if let Some(addr) = env::args().nth(idx + 1) {
-            Some(addr)
-        } else {
-            None
-        }
+        env::args().nth(idx + 1)
     } else {
         None
     };
 
-    let dir = if env::args().any(|arg| arg == "--dir") {
-        let idx = env::args().position(|arg| arg == "--dir").unwrap();
-        if let Some(dir) = env::args().nth(idx + 1) {
-            Some(dir)
-        } else {
-            None
-        }
+    let databases = if env::args().any(|arg| arg == "--databases") {
+        let idx = env::args().position(|arg| arg == "--databases").unwrap();
+        env::args()
+            .nth(idx + 1)
+            .and_then(|n| n.parse::<usize>().ok())
+            .filter(|&n| n > 0)
+            .unwrap_or(16)
     } else {
-        None
+        16
     };
 
-    let dbfilename = if env::args().any(|arg| arg == "--dbfilename") {
-        let idx = env::args().position(|arg| arg == "--dbfilename").unwrap();
-        if let Some(filename) = env::args().nth(idx + 1) {
-            Some(filename)
-        } else {
-            None
-        }
+    let maxmemory = if env::args().any(|arg| arg == "--maxmemory") {
+        let idx = env::args().position(|arg| arg == "--maxmemory").unwrap();
+        env::args()
+            .nth(idx + 1)
+            .and_then(|size| memory::parse_size(&size))
+            .unwrap_or(0)
     } else {
-        None
+        0
+    };
+
+    let maxmemory_policy = if env::args().any(|arg| arg == "--maxmemory-policy") {
+        let idx = env::args().position(|arg| arg == "--maxmemory-policy").unwrap();
+        env::args()
+            .nth(idx + 1)
+            .and_then(|name| EvictionPolicy::parse(&name))
+            .unwrap_or(EvictionPolicy::NoEviction)
+    } else {
+        EvictionPolicy::NoEviction
+    };
+
+    let maxmemory_samples = if env::args().any(|arg| arg == "--maxmemory-samples") {
+        let idx = env::args().position(|arg| arg == "--maxmemory-samples").unwrap();
+        env::args()
+            .nth(idx + 1)
+            .and_then(|n| n.parse::<usize>().ok())
+            .filter(|&n| n > 0)
+            .unwrap_or(5)
+    } else {
+        5
     };
 
     let state = Arc::new(AppState {
-        db: Mutex::new(HashMap::new()),
-        blocked_clients: Mutex::new(HashMap::new()),
+        databases: (0..databases)
+            .map(|_| Mutex::new(Keyspace::default()))
+            .collect(),
+        blocked_clients: Mutex::new(BlockedClients::default()),
         stream_notifier: stream_notifier_tx,
         replica_of,
         master_replication_id: String::from("8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb"),
         master_replication_offset: Mutex::new(0),
         replicas: Mutex::new(Vec::new()),
         slave_replication_offset: Mutex::new(0),
-        dir,
-        dbfilename,
+        replication_db: Mutex::new(None),
+        maxmemory,
+        maxmemory_policy,
+        maxmemory_samples,
+        eviction_pool: Mutex::new(Vec::new()),
+        migrate_sockets: Mutex::new(HashMap::new()),
         subscribers: Mutex::new(HashMap::new()),
         client_subscriptions: Mutex::new(HashMap::new()),
     });
 
+    memory::record_startup();
+
     // Start the server
     if let Err(e) = server::run(state).await {
         eprintln!("Server error: {}", e);
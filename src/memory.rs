@@ -0,0 +1,108 @@
+// Process-wide memory accounting. Every allocation goes through a wrapper
+// around the system allocator that keeps a running total of the bytes in use,
+// much like Redis's zmalloc, which is what INFO and MEMORY STATS report.
+// Allocator overhead and fragmentation aren't counted, so the figure is a
+// little lower than the resident set size.
+//
+// `maxmemory` is checked against the dataset instead: the estimated size of
+// every key and value, which the keyspaces keep up to date as entries are
+// added, changed and removed. Unlike the allocator total it leaves out
+// connection buffers and the like, so only data is ever evicted for.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static USED_MEMORY: AtomicUsize = AtomicUsize::new(0);
+static PEAK_MEMORY: AtomicUsize = AtomicUsize::new(0);
+static STARTUP_MEMORY: AtomicUsize = AtomicUsize::new(0);
+static DATASET_MEMORY: AtomicUsize = AtomicUsize::new(0);
+
+fn record_alloc(size: usize) {
+    let used = USED_MEMORY.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK_MEMORY.fetch_max(used, Ordering::Relaxed);
+}
+
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        USED_MEMORY.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            record_alloc(new_size);
+            USED_MEMORY.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+/// Bytes currently allocated by the process.
+pub fn used_memory() -> usize {
+    USED_MEMORY.load(Ordering::Relaxed)
+}
+
+/// The most bytes the process ever had allocated.
+pub fn peak_memory() -> usize {
+    PEAK_MEMORY.load(Ordering::Relaxed)
+}
+
+/// Bytes allocated once the server was set up, before any data was loaded.
+pub fn startup_memory() -> usize {
+    STARTUP_MEMORY.load(Ordering::Relaxed)
+}
+
+/// Records the current usage as the baseline `startup_memory` reports.
+pub fn record_startup() {
+    STARTUP_MEMORY.store(used_memory(), Ordering::Relaxed);
+}
+
+/// The estimated bytes taken by the keys and values of every database.
+pub fn dataset_memory() -> usize {
+    DATASET_MEMORY.load(Ordering::Relaxed)
+}
+
+pub fn add_dataset_memory(bytes: usize) {
+    DATASET_MEMORY.fetch_add(bytes, Ordering::Relaxed);
+}
+
+pub fn sub_dataset_memory(bytes: usize) {
+    DATASET_MEMORY.fetch_sub(bytes, Ordering::Relaxed);
+}
+
+/// Parses a memory size such as `100mb` or `1gb`, as accepted by Redis for
+/// `maxmemory`. Units are powers of 1024 and case-insensitive.
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.to_ascii_lowercase();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (digits, unit) = size.split_at(split);
+    let multiplier = match unit {
+        "" | "b" => 1,
+        "k" | "kb" => 1 << 10,
+        "m" | "mb" => 1 << 20,
+        "g" | "gb" => 1 << 30,
+        _ => return None,
+    };
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
@@ -1,88 +1,264 @@
+use std::str::FromStr;
 use std::sync::Arc;
 
 use tokio::{io::AsyncWriteExt, net::TcpStream};
 
-use crate::storage::AppState;
+use crate::storage::{selected_db, take_expired_pending, AppState};
 
-pub fn parse_resp(input: &str) -> Result<(Vec<String>, usize), &str> {
+/// The longest bulk string accepted, Redis's default proto-max-bulk-len.
+const PROTO_MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+pub fn parse_resp(input: &[u8]) -> Result<(Vec<Vec<u8>>, usize), &'static str> {
     let mut current_pos = 0;
 
     // Find the end of the first line (array header)
-    let array_header_end = match input.find("\r\n") {
+    let array_header_end = match find_crlf(input) {
         Some(pos) => pos,
         None => return Err("Incomplete array header"),
     };
     let array_header = &input[..array_header_end];
     current_pos += array_header_end + 2;
 
-    if !array_header.starts_with('*') {
+    if !array_header.starts_with(b"*") {
         return Err("Expected an array ('*')");
     }
 
-    let num_elements: usize = array_header[1..]
-        .parse()
-        .map_err(|_| "Invalid array length")?;
+    let num_elements: usize = parse_arg(&array_header[1..]).ok_or("Invalid array length")?;
 
-    let mut result = Vec::with_capacity(num_elements);
+    // Every element takes at least the six bytes of `$0\r\n\r\n`, so a bogus
+    // count can't reserve more than the input could ever hold.
+    let mut result = Vec::with_capacity(num_elements.min(input.len() / 6));
     for _ in 0..num_elements {
         // Parse bulk string header
-        let bulk_header_end = match input[current_pos..].find("\r\n") {
+        let bulk_header_end = match find_crlf(&input[current_pos..]) {
             Some(pos) => pos,
             None => return Err("Incomplete bulk string header"),
         };
         let bulk_header = &input[current_pos..current_pos + bulk_header_end];
         current_pos += bulk_header_end + 2;
 
-        if !bulk_header.starts_with('$') {
+        if !bulk_header.starts_with(b"$") {
             return Err("Expected a bulk string ('$')");
         }
-        let bulk_len: usize = bulk_header[1..]
-            .parse()
-            .map_err(|_| "Invalid bulk string length")?;
+        let bulk_len: usize = parse_arg(&bulk_header[1..])
+            .filter(|len| *len <= PROTO_MAX_BULK_LEN)
+            .ok_or("Invalid bulk string length")?;
 
-        // Check if we have enough data for the bulk string content + CRLF
-        if input.len() < current_pos + bulk_len + 2 {
+        // Check if we have enough data for the bulk string content + CRLF.
+        // The content itself is length-prefixed, so it may contain any bytes.
+        let bulk_end = current_pos
+            .checked_add(bulk_len)
+            .and_then(|end| end.checked_add(2))
+            .ok_or("Invalid bulk string length")?;
+        let content_end = bulk_end - 2;
+        if input.len() < bulk_end {
             return Err("Incomplete bulk string content");
         }
+        if &input[content_end..bulk_end] != b"\r\n" {
+            return Err("Expected CRLF after bulk string content");
+        }
 
-        let string_content = &input[current_pos..current_pos + bulk_len];
-        result.push(string_content.to_string());
-        current_pos += bulk_len + 2; // +2 for the trailing \r\n
+        result.push(input[current_pos..content_end].to_vec());
+        current_pos = bulk_end;
     }
 
     Ok((result, current_pos))
 }
 
-pub fn serialize_resp_array(items: &[String]) -> String {
-    let mut resp = format!("*{}\r\n", items.len());
+fn find_crlf(input: &[u8]) -> Option<usize> {
+    input.windows(2).position(|w| w == b"\r\n")
+}
+
+/// Parses a numeric argument, which must be valid UTF-8.
+pub fn parse_arg<T: FromStr>(arg: &[u8]) -> Option<T> {
+    std::str::from_utf8(arg).ok()?.parse().ok()
+}
+
+/// Returns an argument upper-cased, for matching command and option names.
+pub fn to_upper(arg: &[u8]) -> String {
+    String::from_utf8_lossy(arg).to_uppercase()
+}
+
+/// Appends `value` to `out` as a RESP bulk string.
+pub fn push_bulk_string(out: &mut Vec<u8>, value: &[u8]) {
+    out.extend_from_slice(format!("${}\r\n", value.len()).as_bytes());
+    out.extend_from_slice(value);
+    out.extend_from_slice(b"\r\n");
+}
+
+pub fn bulk_string(value: &[u8]) -> Vec<u8> {
+    let mut resp = Vec::with_capacity(value.len() + 16);
+    push_bulk_string(&mut resp, value);
+    resp
+}
+
+pub fn serialize_resp_array(items: &[Vec<u8>]) -> Vec<u8> {
+    let mut resp = format!("*{}\r\n", items.len()).into_bytes();
     for item in items {
-        resp.push_str(&format!("${}\r\n{}\r\n", item.len(), item));
+        push_bulk_string(&mut resp, item);
     }
     resp
 }
 
+/// Sends a write command on the selected database to every replica. Callers
+/// must not hold any database lock, since keys that expired along the way are
+/// propagated first.
 pub async fn replicate_command(
     state: &Arc<AppState>,
-    command_with_args: Vec<String>,
+    command_with_args: Vec<Vec<u8>>,
 ) -> std::io::Result<()> {
-    // Serialize the command once
-    let serialized_cmd = serialize_resp_array(&command_with_args);
-    let cmd_bytes = serialized_cmd.as_bytes();
+    let mut replication_db = state.replication_db.lock().await;
+    let mut cmd_bytes = expired_deletes(state, &mut replication_db).await;
+    select_db(&mut cmd_bytes, &mut replication_db, selected_db());
+    cmd_bytes.extend_from_slice(&serialize_resp_array(&command_with_args));
+    drop(replication_db);
+    write_to_replicas(state, &cmd_bytes).await
+}
+
+/// Propagates a `DEL` for every key that expired since the last write.
+pub async fn propagate_expired(state: &Arc<AppState>) -> std::io::Result<()> {
+    let mut replication_db = state.replication_db.lock().await;
+    let cmd_bytes = expired_deletes(state, &mut replication_db).await;
+    drop(replication_db);
+    if cmd_bytes.is_empty() {
+        return Ok(());
+    }
+    write_to_replicas(state, &cmd_bytes).await
+}
+
+async fn expired_deletes(state: &Arc<AppState>, replication_db: &mut Option<usize>) -> Vec<u8> {
+    let mut cmd_bytes = Vec::new();
+    if !take_expired_pending() {
+        return cmd_bytes;
+    }
+    for (index, db) in state.databases.iter().enumerate() {
+        let expired = db.lock().await.take_expired();
+        if expired.is_empty() {
+            continue;
+        }
+        select_db(&mut cmd_bytes, replication_db, index);
+        for key in expired {
+            cmd_bytes.extend_from_slice(&serialize_resp_array(&[b"DEL".to_vec(), key]));
+        }
+    }
+    cmd_bytes
+}
+
+// Emits a SELECT if replicas are on another database than `index`.
+fn select_db(cmd_bytes: &mut Vec<u8>, replication_db: &mut Option<usize>, index: usize) {
+    if *replication_db != Some(index) {
+        let select = [b"SELECT".to_vec(), index.to_string().into_bytes()];
+        cmd_bytes.extend_from_slice(&serialize_resp_array(&select));
+        *replication_db = Some(index);
+    }
+}
+
+async fn write_to_replicas(state: &Arc<AppState>, cmd_bytes: &[u8]) -> std::io::Result<()> {
     let cmd_len = cmd_bytes.len() as u64;
-    
+
     // Send to all replicas
     let mut replicas = state.replicas.lock().await;
     for replica in replicas.iter_mut() {
         let mut stream = TcpStream::from_std(replica.stream.try_clone().unwrap()).unwrap();
         stream.write_all(cmd_bytes).await?;
     }
-    
+
     // Update master replication offset
     if !replicas.is_empty() {
         // Only increment if we actually have replicas
         let mut offset = state.master_replication_offset.lock().await;
         *offset += cmd_len;
     }
-    
+
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(items: &[&[u8]]) -> Vec<u8> {
+        serialize_resp_array(&items.iter().map(|item| item.to_vec()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn bulk_strings_may_contain_crlf() {
+        let input = b"*2\r\n$3\r\nSET\r\n$8\r\na\r\nb\r\n\r\n\r\n";
+        let (parsed, consumed) = parse_resp(input).unwrap();
+        assert_eq!(parsed, vec![b"SET".to_vec(), b"a\r\nb\r\n\r\n".to_vec()]);
+        assert_eq!(consumed, input.len());
+    }
+
+    #[test]
+    fn bulk_strings_may_hold_any_bytes() {
+        let payload = [0x00, 0xff, 0xfe, 0x80, b'\r', 0xc3, 0x28, b'\n', 0x00];
+        let mut input = b"*1\r\n$9\r\n".to_vec();
+        input.extend_from_slice(&payload);
+        input.extend_from_slice(b"\r\n");
+        let (parsed, consumed) = parse_resp(&input).unwrap();
+        assert_eq!(parsed, vec![payload.to_vec()]);
+        assert_eq!(consumed, input.len());
+    }
+
+    #[test]
+    fn partial_frames_are_incomplete() {
+        let input = frame(&[b"SET", b"key\r\n", &[0xff, b'\r', b'\n', 0x00]]);
+        for end in 0..input.len() {
+            assert!(
+                parse_resp(&input[..end]).is_err(),
+                "parsed the first {} of {} bytes",
+                end,
+                input.len()
+            );
+        }
+        assert!(parse_resp(&input).is_ok());
+    }
+
+    #[test]
+    fn pipelined_frames_are_parsed_one_at_a_time() {
+        let first = frame(&[b"SET", b"k", b"\r\n"]);
+        let second = frame(&[b"GET", b"k"]);
+        let mut input = [first.clone(), second.clone()].concat();
+        // Only part of the second frame has arrived.
+        input.truncate(first.len() + second.len() - 3);
+
+        let (parsed, consumed) = parse_resp(&input).unwrap();
+        assert_eq!(parsed, vec![b"SET".to_vec(), b"k".to_vec(), b"\r\n".to_vec()]);
+        assert_eq!(consumed, first.len());
+        assert!(parse_resp(&input[consumed..]).is_err());
+    }
+
+    #[test]
+    fn serialized_arrays_round_trip() {
+        let every_byte: Vec<u8> = (0..=255).collect();
+        let large: Vec<u8> = (0..100_000).map(|i| (i * 7 % 256) as u8).collect();
+        let items = vec![
+            b"RESTORE".to_vec(),
+            Vec::new(),
+            b"\r\n".to_vec(),
+            b"*1\r\n$3\r\nfoo\r\n".to_vec(),
+            every_byte,
+            large,
+        ];
+        let input = serialize_resp_array(&items);
+        let (parsed, consumed) = parse_resp(&input).unwrap();
+        assert_eq!(parsed, items);
+        assert_eq!(consumed, input.len());
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        assert!(parse_resp(b"$3\r\nfoo\r\n").is_err());
+        assert!(parse_resp(b"*x\r\n").is_err());
+        assert!(parse_resp(b"*-1\r\n").is_err());
+        assert!(parse_resp(b"*1\r\n:3\r\n").is_err());
+        assert!(parse_resp(b"*1\r\n$-3\r\n").is_err());
+        // A huge count doesn't get space reserved for it up front.
+        assert!(parse_resp(b"*18446744073709551615\r\n$1\r\na\r\n").is_err());
+        // Nor does a huge bulk length overflow the position of its end.
+        assert!(parse_resp(b"*1\r\n$18446744073709551615\r\nfoo\r\n").is_err());
+        assert!(parse_resp(b"*1\r\n$536870913\r\nfoo\r\n").is_err());
+        // The content must be followed by CRLF, not just any two bytes.
+        assert!(parse_resp(b"*1\r\n$3\r\nfooXY").is_err());
+        assert!(parse_resp(b"*1\r\n$3\r\nfoo\r").is_err());
+    }
+}
@@ -0,0 +1,335 @@
+// Serializing values in the RDB format, for DUMP and RESTORE.
+//
+// A DUMP payload is the value type and the value, followed by the RDB version
+// and a CRC64 of everything before it. Strings and lists use Redis's plain
+// encodings, so payloads dumped by Redis can be restored as long as they only
+// hold those (compressed and integer-encoded strings are decoded). Streams are
+// stored in a layout of their own, under a type id Redis doesn't use.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::storage::{Consumer, ConsumerGroup, DataStoreValue, PendingEntry, Stream, StreamId};
+use crate::stream_entries::StreamEntries;
+
+const RDB_VERSION: u16 = 11;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_STREAM: u8 = 0x7f;
+
+const ENC_INT8: u8 = 0;
+const ENC_INT16: u8 = 1;
+const ENC_INT32: u8 = 2;
+const ENC_LZF: u8 = 3;
+
+/// Serializes a single value the way DUMP returns it.
+pub fn dump(value: &DataStoreValue) -> Vec<u8> {
+    let mut out = vec![value_type(value)];
+    write_value(&mut out, value);
+    out.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let checksum = crc64(0, &out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+/// Parses a DUMP payload, checking its version and checksum first.
+pub fn undump(payload: &[u8]) -> Result<DataStoreValue, &'static str> {
+    const BAD_PAYLOAD: &str = "DUMP payload version or checksum are wrong";
+    if payload.len() < 10 {
+        return Err(BAD_PAYLOAD);
+    }
+    let (body, checksum) = payload.split_at(payload.len() - 8);
+    let version = u16::from_le_bytes(body[body.len() - 2..].try_into().unwrap());
+    let checksum = u64::from_le_bytes(checksum.try_into().unwrap());
+    if version > RDB_VERSION || checksum != crc64(0, body) {
+        return Err(BAD_PAYLOAD);
+    }
+
+    let mut reader = Reader {
+        bytes: &body[..body.len() - 2],
+        pos: 0,
+    };
+    let value_type = reader.byte().map_err(|_| "Bad data format")?;
+    let value = reader.object(value_type).map_err(|_| "Bad data format")?;
+    if reader.pos != reader.bytes.len() {
+        return Err("Bad data format");
+    }
+    Ok(value)
+}
+
+fn value_type(value: &DataStoreValue) -> u8 {
+    match value {
+        DataStoreValue::String(_) => TYPE_STRING,
+        DataStoreValue::List(_) => TYPE_LIST,
+        DataStoreValue::Stream(_) => TYPE_STREAM,
+    }
+}
+
+fn write_value(out: &mut Vec<u8>, value: &DataStoreValue) {
+    match value {
+        DataStoreValue::String(val) => write_string(out, val),
+        DataStoreValue::List(list) => {
+            write_length(out, list.len() as u64);
+            for element in list {
+                write_string(out, element);
+            }
+        }
+        // The last ID, the greatest deleted ID and the count of entries ever
+        // added, then every entry as its ID and its field-value pairs.
+        DataStoreValue::Stream(stream) => {
+            write_string(out, stream.last_id.to_string().as_bytes());
+            write_string(out, stream.max_deleted_entry_id.to_string().as_bytes());
+            write_length(out, stream.entries_added);
+            write_length(out, stream.entries.len() as u64);
+            for (id, fields) in stream.entries.iter() {
+                write_string(out, id.to_string().as_bytes());
+                write_length(out, fields.len() as u64);
+                for (field, value) in &fields {
+                    write_string(out, field);
+                    write_string(out, value);
+                }
+            }
+            // Then every consumer group with its pending entries and
+            // consumers.
+            write_length(out, stream.groups.len() as u64);
+            for (name, group) in &stream.groups {
+                write_string(out, name);
+                write_string(out, group.last_delivered_id.to_string().as_bytes());
+                // The count of entries read, after a flag for whether it's known.
+                write_length(out, group.entries_read.is_some() as u64);
+                write_length(out, group.entries_read.unwrap_or(0));
+                write_length(out, group.pending.len() as u64);
+                for (id, pending) in &group.pending {
+                    write_string(out, id.to_string().as_bytes());
+                    write_string(out, &pending.consumer);
+                    write_length(out, pending.delivery_time);
+                    write_length(out, pending.delivery_count);
+                }
+                write_length(out, group.consumers.len() as u64);
+                for (name, consumer) in &group.consumers {
+                    write_string(out, name);
+                    write_length(out, consumer.seen_time);
+                    write_length(out, consumer.active_time.is_some() as u64);
+                    write_length(out, consumer.active_time.unwrap_or(0));
+                }
+            }
+        }
+    }
+}
+
+fn write_length(out: &mut Vec<u8>, len: u64) {
+    if len < 1 << 6 {
+        out.push(len as u8);
+    } else if len < 1 << 14 {
+        out.push(0x40 | (len >> 8) as u8);
+        out.push(len as u8);
+    } else if len <= u32::MAX as u64 {
+        out.push(0x80);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        out.push(0x81);
+        out.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, value: &[u8]) {
+    write_length(out, value.len() as u64);
+    out.extend_from_slice(value);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], &'static str> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|&end| end <= self.bytes.len());
+        let end = end.ok_or("Unexpected end of RDB file")?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn byte(&mut self) -> Result<u8, &'static str> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Reads a length, or the special string encoding it announces as
+    /// `Err(encoding)` in the inner result.
+    fn length_or_encoding(&mut self) -> Result<Result<u64, u8>, &'static str> {
+        let first = self.byte()?;
+        Ok(match first >> 6 {
+            0 => Ok((first & 0x3f) as u64),
+            1 => Ok((((first & 0x3f) as u64) << 8) | self.byte()? as u64),
+            2 if first == 0x80 => Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as u64),
+            2 if first == 0x81 => Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap())),
+            2 => return Err("Unknown length encoding in RDB file"),
+            _ => Err(first & 0x3f),
+        })
+    }
+
+    fn length(&mut self) -> Result<u64, &'static str> {
+        self.length_or_encoding()?
+            .map_err(|_| "Unexpected string encoding in RDB file")
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>, &'static str> {
+        match self.length_or_encoding()? {
+            Ok(len) => Ok(self.take(len as usize)?.to_vec()),
+            Err(ENC_INT8) => Ok((self.byte()? as i8).to_string().into_bytes()),
+            Err(ENC_INT16) => {
+                let value = i16::from_le_bytes(self.take(2)?.try_into().unwrap());
+                Ok(value.to_string().into_bytes())
+            }
+            Err(ENC_INT32) => {
+                let value = i32::from_le_bytes(self.take(4)?.try_into().unwrap());
+                Ok(value.to_string().into_bytes())
+            }
+            Err(ENC_LZF) => {
+                let compressed_len = self.length()? as usize;
+                let len = self.length()? as usize;
+                lzf_decompress(self.take(compressed_len)?, len)
+            }
+            Err(_) => Err("Unknown string encoding in RDB file"),
+        }
+    }
+
+    fn id(&mut self) -> Result<StreamId, &'static str> {
+        StreamId::parse(&self.string()?, 0).ok_or("Invalid stream ID in RDB file")
+    }
+
+    fn object(&mut self, value_type: u8) -> Result<DataStoreValue, &'static str> {
+        match value_type {
+            TYPE_STRING => Ok(DataStoreValue::String(self.string()?)),
+            TYPE_LIST => {
+                let len = self.length()?;
+                let mut list = VecDeque::new();
+                for _ in 0..len {
+                    list.push_back(self.string()?);
+                }
+                Ok(DataStoreValue::List(list))
+            }
+            TYPE_STREAM => {
+                let last_id = self.id()?;
+                let max_deleted_entry_id = self.id()?;
+                let entries_added = self.length()?;
+                let mut entries = StreamEntries::default();
+                let mut previous_id = None;
+                for _ in 0..self.length()? {
+                    let id = self.id()?;
+                    // Entries are only ever appended, so they must come in order.
+                    if previous_id.is_some_and(|previous_id| id <= previous_id) {
+                        return Err("Invalid stream ID in RDB file");
+                    }
+                    previous_id = Some(id);
+                    let mut fields = Vec::new();
+                    for _ in 0..self.length()? {
+                        fields.push((self.string()?, self.string()?));
+                    }
+                    entries.push(id, &fields);
+                }
+                // New IDs must come after both, so the last ID can't be behind
+                // the last entry.
+                if previous_id.is_some_and(|previous_id| last_id < previous_id) {
+                    return Err("Invalid stream ID in RDB file");
+                }
+                let mut groups = BTreeMap::new();
+                for _ in 0..self.length()? {
+                    let name = self.string()?;
+                    let mut group = ConsumerGroup::new(self.id()?);
+                    let known = self.length()? != 0;
+                    let entries_read = self.length()?;
+                    group.entries_read = known.then_some(entries_read);
+                    for _ in 0..self.length()? {
+                        let id = self.id()?;
+                        let pending = PendingEntry {
+                            consumer: self.string()?,
+                            delivery_time: self.length()?,
+                            delivery_count: self.length()?,
+                        };
+                        group.pending.insert(id, pending);
+                    }
+                    for _ in 0..self.length()? {
+                        let name = self.string()?;
+                        let seen_time = self.length()?;
+                        let active = self.length()? != 0;
+                        let active_time = self.length()?;
+                        let consumer = Consumer {
+                            seen_time,
+                            active_time: active.then_some(active_time),
+                        };
+                        group.consumers.insert(name, consumer);
+                    }
+                    groups.insert(name, group);
+                }
+                Ok(DataStoreValue::Stream(Stream {
+                    entries,
+                    last_id,
+                    max_deleted_entry_id,
+                    entries_added,
+                    groups,
+                }))
+            }
+            _ => Err("Unsupported value type in RDB file"),
+        }
+    }
+}
+
+fn lzf_decompress(input: &[u8], len: usize) -> Result<Vec<u8>, &'static str> {
+    const ERR: &str = "Invalid LZF compressed string in RDB file";
+    // The best LZF does is a three-byte back reference to 264 bytes, so a
+    // longer claimed length is corrupt, and must not be allocated up front.
+    if len > input.len().saturating_mul(88) {
+        return Err(ERR);
+    }
+    let mut out = Vec::with_capacity(len);
+    let mut i = 0;
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+        if ctrl < 32 {
+            // A literal run of ctrl + 1 bytes.
+            let run = input.get(i..i + ctrl + 1).ok_or(ERR)?;
+            out.extend_from_slice(run);
+            i += ctrl + 1;
+        } else {
+            // A back reference.
+            let mut ref_len = ctrl >> 5;
+            if ref_len == 7 {
+                ref_len += *input.get(i).ok_or(ERR)? as usize;
+                i += 1;
+            }
+            let offset = ((ctrl & 0x1f) << 8) + *input.get(i).ok_or(ERR)? as usize + 1;
+            i += 1;
+            let start = out.len().checked_sub(offset).ok_or(ERR)?;
+            for j in 0..ref_len + 2 {
+                out.push(out[start + j]);
+            }
+        }
+    }
+    if out.len() == len {
+        Ok(out)
+    } else {
+        Err(ERR)
+    }
+}
+
+/// CRC-64 with the Jones polynomial, as used for RDB checksums.
+pub fn crc64(mut crc: u64, data: &[u8]) -> u64 {
+    const POLY: u64 = 0x95ac_9329_ac4b_c9b5;
+    for &byte in data {
+        crc ^= byte as u64;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
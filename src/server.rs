@@ -31,7 +31,7 @@ pub async fn run(state: Arc<AppState>) -> std::io::Result<()> {
         master_stream.write_all(b"*1\r\n$4\r\nPING\r\n").await?;
 
         let mut buf = [0; 1024];
-        master_stream.read(&mut buf).await?;
+        let _ = master_stream.read(&mut buf).await?;
 
         master_stream
             .write_all(
@@ -42,12 +42,12 @@ pub async fn run(state: Arc<AppState>) -> std::io::Result<()> {
                 .as_bytes(),
             )
             .await?;
-        master_stream.read(&mut buf).await?;
+        let _ = master_stream.read(&mut buf).await?;
 
         master_stream
             .write_all(b"*3\r\n$8\r\nREPLCONF\r\n$4\r\ncapa\r\n$6\r\npsync2\r\n")
             .await?;
-        master_stream.read(&mut buf).await?;
+        let _ = master_stream.read(&mut buf).await?;
 
         master_stream
             .write_all(b"*3\r\n$5\r\nPSYNC\r\n$1\r\n?\r\n$2\r\n-1\r\n")
@@ -88,15 +88,24 @@ pub async fn run(state: Arc<AppState>) -> std::io::Result<()> {
         tokio::spawn(async move {
             handle_master_stream(master_stream, state_clone, Vec::new()).await;
         });
+    } else {
+        // Replicas leave expiry to the master, which propagates a DEL for
+        // every key it expires.
+        tokio::spawn(commands::expire::active_expire_cycle(state.clone()));
     }
 
     loop {
         let (socket, addr) = listener.accept().await?;
         println!("Accepted new connection from: {}", addr);
+        // Replies are written one by one, so with Nagle's algorithm a
+        // pipelining client would wait on delayed ACKs between them.
+        let _ = socket.set_nodelay(true);
         let state_clone = state.clone();
         let transation_state = TransactionState {
             in_transaction: false,
             queued_commands: Vec::new(),
+            selected_db: 0,
+            in_exec: false,
         };
         tokio::spawn(async move {
             handle_stream(socket, state_clone, transation_state).await;
@@ -113,66 +122,59 @@ async fn handle_stream(
     let mut temp_buf = [0; 1024];
 
     loop {
-        // Attempt to parse commands from the buffer before reading more data
-        loop {
-            let received_str = match std::str::from_utf8(&buffer) {
-                Ok(s) => s,
-                Err(_) => break, // Incomplete UTF-8, need more data
-            };
-
-            match protocol::parse_resp(received_str) {
-                Ok((parsed, consumed_bytes)) => {
-                    if parsed[0].to_uppercase() == "REPLCONF" && parsed[1].to_uppercase() == "ACK" {
-                        // Special handling for REPLCONF ACK to update replica offset
-                        if let Ok(peer_addr) = stream.peer_addr() {
-                            let mut replicas = state.replicas.lock().await;
-                            if let Some(replica) = replicas
-                                .iter_mut()
-                                .find(|r| r.stream.peer_addr().unwrap() == peer_addr)
-                            {
-                                if let Ok(offset) = parsed[2].parse::<u64>() {
-                                    replica.offset = offset;
-                                }
-                            }
-                        }
-                    } else {
-                        let stream_id = format!("{:?}", stream.peer_addr().unwrap());
-                        match commands::handle_command(
-                            parsed.clone(),
-                            &mut stream,
-                            &state,
-                            &mut transation_state,
-                            stream_id
-                        )
-                        .await
-                        {
-                            Ok(_) => {
-                                if parsed[0].to_uppercase() == "PSYNC" {
-                                    let mut replicas = state.replicas.lock().await;
-                                    let stream_std = stream.into_std().unwrap();
-                                    let replica_info = storage::ReplicaInfo {
-                                        stream: stream_std.try_clone().unwrap(),
-                                        offset: 0,
-                                    };
-                                    replicas.push(replica_info);
-                                    stream = TcpStream::from_std(stream_std).unwrap();
-                                }
-                            }
-                            Err(e) => {
-                                eprintln!("Error handling command: {}", e);
-                                let _ = stream.write_all(b"-ERR server error\r\n").await;
-                                return;
-                            }
+        // Attempt to parse commands from the buffer before reading more data.
+        // Parsing stops at the first incomplete command.
+        while let Ok((parsed, consumed_bytes)) = protocol::parse_resp(&buffer) {
+            if parsed[0].eq_ignore_ascii_case(b"REPLCONF")
+                && parsed.get(1).is_some_and(|arg| arg.eq_ignore_ascii_case(b"ACK"))
+            {
+                // Special handling for REPLCONF ACK to update replica offset
+                if let Ok(peer_addr) = stream.peer_addr() {
+                    let mut replicas = state.replicas.lock().await;
+                    if let Some(replica) = replicas
+                        .iter_mut()
+                        .find(|r| r.stream.peer_addr().unwrap() == peer_addr)
+                    {
+                        let offset = parsed.get(2).and_then(|arg| protocol::parse_arg::<u64>(arg));
+                        if let Some(offset) = offset {
+                            replica.offset = offset;
                         }
                     }
-                    // Remove the processed command from the buffer
-                    buffer.drain(..consumed_bytes);
                 }
-                Err(_) => {
-                    // Not enough data to parse a full command, break to read more
-                    break;
+            } else {
+                let stream_id = format!("{:?}", stream.peer_addr().unwrap());
+                match commands::handle_command(
+                    parsed.clone(),
+                    &mut stream,
+                    &state,
+                    &mut transation_state,
+                    stream_id
+                )
+                .await
+                {
+                    Ok(_) => {
+                        if parsed[0].eq_ignore_ascii_case(b"PSYNC") {
+                            let mut replicas = state.replicas.lock().await;
+                            let stream_std = stream.into_std().unwrap();
+                            let replica_info = storage::ReplicaInfo {
+                                stream: stream_std.try_clone().unwrap(),
+                                offset: 0,
+                            };
+                            replicas.push(replica_info);
+                            // The new replica starts without a selected database.
+                            *state.replication_db.lock().await = None;
+                            stream = TcpStream::from_std(stream_std).unwrap();
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Error handling command: {}", e);
+                        let _ = stream.write_all(b"-ERR server error\r\n").await;
+                        return;
+                    }
                 }
             }
+            // Remove the processed command from the buffer
+            buffer.drain(..consumed_bytes);
         }
 
         // Read more data from the client
@@ -191,66 +193,56 @@ async fn handle_stream(
 async fn handle_master_stream(mut stream: TcpStream, state: Arc<AppState>, initial_data: Vec<u8>) {
     let mut buffer = initial_data;
     let mut temp_buf = [0; 1024];
+    // Kept across commands so a propagated SELECT sticks.
+    let mut dummy_transaction_state = TransactionState {
+        in_transaction: false,
+        queued_commands: Vec::new(),
+        selected_db: 0,
+        in_exec: false,
+    };
 
     loop {
-        loop {
-            let received_str = match std::str::from_utf8(&buffer) {
-                Ok(s) => s,
-                Err(_) => break, // Incomplete UTF-8 sequence, need more data
-            };
-
-            match protocol::parse_resp(received_str) {
-                Ok((parsed_command, consumed_bytes)) => {
-                    println!("parsed command: {:?}", parsed_command);
-                    let mut dummy_transaction_state = TransactionState {
-                        in_transaction: false,
-                        queued_commands: Vec::new(),
-                    };
-
-                    let command_result = if parsed_command[0].to_uppercase() == "REPLCONF" {
-                        // For REPLCONF, use the real stream to send the ACK back.
-                        let stream_id = format!("{:?}", stream.peer_addr().unwrap());
-                        commands::handle_command(
-                            parsed_command,
-                            &mut stream,
-                            &state,
-                            &mut dummy_transaction_state,
-                            stream_id
-                        )
-                        .await
-                    } else {
-                        // For other commands (SET, etc.), use a dummy sink.
-                        let mut sink = tokio::io::sink();
-                        let stream_id = String::from("");
-                        commands::handle_command(
-                            parsed_command,
-                            &mut sink,
-                            &state,
-                            &mut dummy_transaction_state,
-                            stream_id
-                        )
-                        .await
-                    };
+        while let Ok((parsed_command, consumed_bytes)) = protocol::parse_resp(&buffer) {
+            println!("parsed command: {:?}", parsed_command);
 
-                    match command_result {
-                        Ok(_) => {
-                            println!("Processed propagated command.");
-                        }
-                        Err(e) => {
-                            eprintln!("Error processing propagated command: {}", e);
-                        }
-                    }
+            let command_result = if parsed_command[0].eq_ignore_ascii_case(b"REPLCONF") {
+                // For REPLCONF, use the real stream to send the ACK back.
+                let stream_id = format!("{:?}", stream.peer_addr().unwrap());
+                commands::handle_command(
+                    parsed_command,
+                    &mut stream,
+                    &state,
+                    &mut dummy_transaction_state,
+                    stream_id
+                )
+                .await
+            } else {
+                // For other commands (SET, etc.), use a dummy sink.
+                let mut sink = tokio::io::sink();
+                let stream_id = String::from("");
+                commands::handle_command(
+                    parsed_command,
+                    &mut sink,
+                    &state,
+                    &mut dummy_transaction_state,
+                    stream_id
+                )
+                .await
+            };
 
-                    // Remove the processed command from the buffer
-                    let mut offset = state.slave_replication_offset.lock().await;
-                    *offset += consumed_bytes as u64;
-                    buffer.drain(..consumed_bytes);
+            match command_result {
+                Ok(_) => {
+                    println!("Processed propagated command.");
                 }
-                Err(_) => {
-                    // Not enough data to parse a full command, break to read more
-                    break;
+                Err(e) => {
+                    eprintln!("Error processing propagated command: {}", e);
                 }
             }
+
+            // Remove the processed command from the buffer
+            let mut offset = state.slave_replication_offset.lock().await;
+            *offset += consumed_bytes as u64;
+            buffer.drain(..consumed_bytes);
         }
 
         // Read more data from the master
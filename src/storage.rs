@@ -1,27 +1,381 @@
-use std::collections::{BTreeMap, HashMap, VecDeque};
+use rand::Rng;
+use crate::commands::list::ListPop;
+use crate::dict::Dict;
+use crate::evict::{EvictionPool, EvictionPolicy};
+use crate::memory;
+use crate::stream_entries::StreamEntries;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::ops::Deref;
 use std::net::TcpStream;
-use std::time::Instant;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::BufStream;
 use tokio::sync::{Mutex, oneshot, broadcast};
 
+#[derive(Clone)]
 pub enum DataStoreValue {
-    String(String),
-    List(Vec<String>),
+    String(Vec<u8>),
+    List(VecDeque<Vec<u8>>),
     Stream(Stream)
 }
 
+impl DataStoreValue {
+    /// The name `TYPE` reports for the value.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            DataStoreValue::String(_) => "string",
+            DataStoreValue::List(_) => "list",
+            DataStoreValue::Stream(_) => "stream",
+        }
+    }
+
+    /// The name `OBJECT ENCODING` reports, following the thresholds at which
+    /// Redis switches a value to its larger representation.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            DataStoreValue::String(val) => {
+                let is_int = val.len() <= 20
+                    && std::str::from_utf8(val).is_ok_and(|s| s.parse::<i64>().is_ok());
+                if is_int {
+                    "int"
+                } else if val.len() <= 44 {
+                    "embstr"
+                } else {
+                    "raw"
+                }
+            }
+            DataStoreValue::List(list) => {
+                if list.len() <= 128 && list.iter().all(|element| element.len() <= 64) {
+                    "listpack"
+                } else {
+                    "quicklist"
+                }
+            }
+            DataStoreValue::Stream(_) => "stream",
+        }
+    }
+
+    /// Estimates the bytes the value takes on the heap. Large collections are
+    /// measured from `samples` of their elements, or from all of them when
+    /// `samples` is 0.
+    pub fn memory_usage(&self, samples: usize) -> usize {
+        fn sampled<I: ExactSizeIterator>(
+            items: I,
+            samples: usize,
+            size: impl Fn(I::Item) -> usize,
+        ) -> usize {
+            let len = items.len();
+            let take = if samples == 0 { len } else { samples.min(len) };
+            if take == 0 {
+                return 0;
+            }
+            let total: usize = items.take(take).map(size).sum();
+            total * len / take
+        }
+
+        match self {
+            DataStoreValue::String(val) => val.capacity(),
+            DataStoreValue::List(list) => {
+                let elements = sampled(list.iter(), samples, |element| element.capacity());
+                list.capacity() * std::mem::size_of::<Vec<u8>>() + elements
+            }
+            DataStoreValue::Stream(stream) => {
+                sampled(stream.entries.node_sizes(), samples, |size| size)
+            }
+        }
+    }
+}
+
+/// A client blocked on one or more lists.
 pub struct BlockedSender {
-    pub id: String,
-    pub sender: oneshot::Sender<()>
+    pub db: usize,
+    /// Every key the client waits on, so serving it takes it off all their
+    /// queues.
+    pub keys: Vec<Vec<u8>>,
+    /// The pop or move to run for the client once one of its keys holds a list.
+    pub op: ListPop,
+    /// Receives the reply once the client is served.
+    pub sender: oneshot::Sender<Vec<u8>>,
+}
+
+/// The clients blocked on lists. A client is queued on all its keys at once,
+/// and each key's queue is kept in the order its clients blocked.
+#[derive(Default)]
+pub struct BlockedClients {
+    waiters: HashMap<u64, BlockedSender>,
+    // Keyed by database index and key.
+    queues: HashMap<(usize, Vec<u8>), VecDeque<u64>>,
+    next_id: u64,
+}
+
+impl BlockedClients {
+    /// Queues a client on all its keys, returning the id to unblock it with.
+    pub fn block(&mut self, waiter: BlockedSender) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        for key in &waiter.keys {
+            let queue = self.queues.entry((waiter.db, key.clone())).or_default();
+            // A key given twice is only queued on once.
+            if queue.back() != Some(&id) {
+                queue.push_back(id);
+            }
+        }
+        self.waiters.insert(id, waiter);
+        id
+    }
+
+    /// Takes a client off every queue it is on. Returns `None` when it was
+    /// already taken off to be served.
+    pub fn unblock(&mut self, id: u64) -> Option<BlockedSender> {
+        let waiter = self.waiters.remove(&id)?;
+        for key in &waiter.keys {
+            let queue_key = (waiter.db, key.clone());
+            if let Some(queue) = self.queues.get_mut(&queue_key) {
+                queue.retain(|queued| *queued != id);
+                if queue.is_empty() {
+                    self.queues.remove(&queue_key);
+                }
+            }
+        }
+        Some(waiter)
+    }
+
+    /// Takes the client that has waited longest on `key` off every queue.
+    pub fn pop_waiter(&mut self, db: usize, key: &[u8]) -> Option<BlockedSender> {
+        let id = *self.queues.get(&(db, key.to_vec()))?.front()?;
+        self.unblock(id)
+    }
 }
 
+// New keys start with a small LFU count so they aren't evicted before they
+// had a chance to be read. The count is bumped with a probability that falls
+// as it grows, and drops by one for every minute the key goes untouched.
+const LFU_INIT_VAL: u8 = 5;
+const LFU_LOG_FACTOR: f64 = 10.0;
+const LFU_DECAY_MS: u64 = 60_000;
+
+/// Elements sampled when estimating the size of a collection.
+pub const MEMORY_USAGE_SAMPLES: usize = 5;
+
+#[derive(Clone)]
 pub struct ValueEntry {
     pub value: DataStoreValue,
-    pub expires_at: Option<Instant>,
+    /// Absolute deadline as a Unix time in milliseconds.
+    pub expires_at: Option<u64>,
+    /// Unix time in milliseconds the key was last read or written.
+    pub last_access: u64,
+    /// Logarithmic access counter for the LFU policies, as of `last_access`.
+    lfu_counter: u8,
+    /// The estimated bytes taken by the key and the value, as last counted
+    /// by the keyspace.
+    size: usize,
 }
 
+impl ValueEntry {
+    pub fn new(value: DataStoreValue, expires_at: Option<u64>) -> Self {
+        ValueEntry {
+            value,
+            expires_at,
+            last_access: unix_time_ms(),
+            lfu_counter: LFU_INIT_VAL,
+            size: 0,
+        }
+    }
+
+    /// Estimates the bytes taken by the entry stored under `key`: the key,
+    /// its slot in the hash table and the value.
+    pub fn memory_usage(&self, key: &[u8], samples: usize) -> usize {
+        key.len() + std::mem::size_of::<(Vec<u8>, ValueEntry)>() + self.value.memory_usage(samples)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|deadline| unix_time_ms() > deadline)
+    }
+
+    /// Milliseconds since the key was last accessed.
+    pub fn idle_ms(&self) -> u64 {
+        unix_time_ms().saturating_sub(self.last_access)
+    }
+
+    /// The LFU counter after decaying it for the time the key sat idle.
+    pub fn lfu_count(&self) -> u8 {
+        let periods = self.idle_ms() / LFU_DECAY_MS;
+        self.lfu_counter.saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+
+    /// Overrides the access history, as RESTORE does with IDLETIME and FREQ.
+    pub fn set_lfu_count(&mut self, count: u8) {
+        self.lfu_counter = count;
+    }
+
+    /// Records an access for the LRU and LFU policies.
+    pub fn touch(&mut self) {
+        let mut counter = self.lfu_count();
+        if counter < u8::MAX {
+            let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+            if rand::random::<f64>() < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+                counter += 1;
+            }
+        }
+        self.lfu_counter = counter;
+        self.last_access = unix_time_ms();
+    }
+}
+
+/// The field-value pairs of a stream entry, in the order they were given.
+pub type StreamFields = Vec<(Vec<u8>, Vec<u8>)>;
+
+#[derive(Clone)]
 pub struct Stream {
-    pub entries: BTreeMap<String, HashMap<String, String>>,
-    pub last_id: String,
+    pub entries: StreamEntries,
+    pub last_id: StreamId,
+    /// The greatest ID removed with XDEL, and how many entries were ever
+    /// added, trimmed or deleted ones included.
+    pub max_deleted_entry_id: StreamId,
+    pub entries_added: u64,
+    /// Consumer groups by name.
+    pub groups: BTreeMap<Vec<u8>, ConsumerGroup>,
+}
+
+impl Default for Stream {
+    fn default() -> Stream {
+        Stream {
+            entries: StreamEntries::default(),
+            last_id: StreamId::MIN,
+            max_deleted_entry_id: StreamId::MIN,
+            entries_added: 0,
+            groups: BTreeMap::new(),
+        }
+    }
+}
+
+impl Stream {
+    /// Whether an entry from `start` on may have been deleted, going by the
+    /// greatest deleted ID. Counting the entries a group has read one by one
+    /// only works when none was.
+    pub fn has_tombstones_from(&self, start: StreamId) -> bool {
+        self.entries.len() > 0
+            && self.max_deleted_entry_id != StreamId::MIN
+            && start <= self.max_deleted_entry_id
+            && self.max_deleted_entry_id <= self.last_id
+    }
+
+    /// How many entries were added up to and including `id`, when that can
+    /// be told without counting: at the end of the stream, or at its start
+    /// when nothing has been deleted from the entries left.
+    pub fn estimate_entries_read(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        if id >= self.last_id || self.entries.len() == 0 {
+            return (id <= self.last_id).then_some(self.entries_added);
+        }
+        let first_id = self.entries.first_id()?;
+        let trimmed = self.entries_added - self.entries.len() as u64;
+        if self.max_deleted_entry_id == StreamId::MIN || self.max_deleted_entry_id < first_id {
+            if id < first_id {
+                return Some(trimmed);
+            } else if id == first_id {
+                return Some(trimmed + 1);
+            }
+        }
+        None
+    }
+
+    /// How many entries a group has read, and how many it has left to read,
+    /// when those can be told.
+    pub fn group_lag(&self, group: &ConsumerGroup) -> (Option<u64>, Option<u64>) {
+        if self.entries_added == 0 {
+            return (group.entries_read, Some(0));
+        }
+        let entries_read = match group.entries_read {
+            Some(read) if !self.has_tombstones_from(group.last_delivered_id) => Some(read),
+            _ => self.estimate_entries_read(group.last_delivered_id),
+        };
+        let lag = entries_read.map(|read| self.entries_added.saturating_sub(read));
+        (group.entries_read.or(entries_read), lag)
+    }
+}
+
+/// A stream entry ID: a Unix time in milliseconds, and a sequence number
+/// telling apart the entries added within it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId { ms: u64::MAX, seq: u64::MAX };
+
+    /// Parses `<ms>-<seq>`, or a bare `<ms>` with `default_seq` for its
+    /// sequence number.
+    pub fn parse(arg: &[u8], default_seq: u64) -> Option<StreamId> {
+        let id = std::str::from_utf8(arg).ok()?;
+        let (ms, seq) = match id.split_once('-') {
+            Some((ms, seq)) => (ms, seq.parse().ok()?),
+            None => (id, default_seq),
+        };
+        Some(StreamId { ms: ms.parse().ok()?, seq })
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// A consumer group: how far it has read the stream, and the entries handed
+/// to its consumers that they haven't acknowledged yet.
+#[derive(Clone)]
+pub struct ConsumerGroup {
+    pub last_delivered_id: StreamId,
+    /// How many entries of the stream the group has read, `None` when that
+    /// isn't known, as after XGROUP SETID.
+    pub entries_read: Option<u64>,
+    /// The pending entries list, by entry ID.
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<Vec<u8>, Consumer>,
+}
+
+impl ConsumerGroup {
+    pub fn new(last_delivered_id: StreamId) -> ConsumerGroup {
+        ConsumerGroup {
+            last_delivered_id,
+            entries_read: None,
+            pending: BTreeMap::new(),
+            consumers: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PendingEntry {
+    pub consumer: Vec<u8>,
+    /// When the entry was last delivered, in Unix milliseconds.
+    pub delivery_time: u64,
+    pub delivery_count: u64,
+}
+
+#[derive(Clone)]
+pub struct Consumer {
+    /// When the consumer last read from the group, in Unix milliseconds.
+    pub seen_time: u64,
+    /// When a read or claim last actually gave it entries, `None` if none
+    /// ever has.
+    pub active_time: Option<u64>,
+}
+
+impl Consumer {
+    pub fn new(seen_time: u64) -> Consumer {
+        Consumer {
+            seen_time,
+            active_time: None,
+        }
+    }
 }
 
 pub struct ReplicaInfo {
@@ -29,25 +383,293 @@ pub struct ReplicaInfo {
     pub offset: u64,
 }
 
+/// A connection MIGRATE keeps open to a target instance between calls.
+pub struct MigrateSocket {
+    pub stream: BufStream<tokio::net::TcpStream>,
+    /// The database last selected on the target.
+    pub db: Option<i64>,
+    pub last_use: Instant,
+}
+
 pub struct AppState {
-    pub db: Db,
-    pub blocked_clients: BlockedClients,
+    /// The logical databases; connections pick one with SELECT.
+    pub databases: Vec<Db>,
+    pub blocked_clients: Mutex<BlockedClients>,
     pub stream_notifier: broadcast::Sender<()>,
     pub replica_of: Option<String>,
     pub master_replication_id: String,
     pub master_replication_offset: Mutex<u64>,
     pub replicas: Mutex<Vec<ReplicaInfo>>,
     pub slave_replication_offset: Mutex<u64>,
-    pub dir: Option<String>,
-    pub dbfilename: Option<String>,
-    pub subscribers: Mutex<HashMap<String, Vec<oneshot::Sender<String>>>>,
-    pub client_subscriptions: Mutex<HashMap<String, Vec<String>>>,
+    /// The database replicas currently have selected, so a SELECT is only
+    /// propagated when writes move to another one.
+    pub replication_db: Mutex<Option<usize>>,
+    /// The memory limit in bytes; 0 means no limit.
+    pub maxmemory: u64,
+    pub maxmemory_policy: EvictionPolicy,
+    /// How many keys per database eviction samples to pick a victim.
+    pub maxmemory_samples: usize,
+    pub eviction_pool: EvictionPool,
+    /// MIGRATE connections by `host:port`.
+    pub migrate_sockets: Mutex<HashMap<String, MigrateSocket>>,
+    pub subscribers: Subscribers,
+    pub client_subscriptions: Mutex<HashMap<String, Vec<Vec<u8>>>>,
 }
 
 pub struct TransactionState {
     pub in_transaction: bool,
-    pub queued_commands: Vec<Vec<String>>,
+    pub queued_commands: Vec<Vec<Vec<u8>>>,
+    /// The database this connection has selected.
+    pub selected_db: usize,
+    /// Set while EXEC runs the queued commands, during which blocking
+    /// commands return at once instead of blocking.
+    pub in_exec: bool,
+}
+
+tokio::task_local! {
+    /// The database selected by the connection whose command is running. It
+    /// is scoped around each command by `handle_command`.
+    pub static SELECTED_DB: usize;
+}
+
+/// Returns the database index the running command operates on.
+pub fn selected_db() -> usize {
+    SELECTED_DB.try_with(|db| *db).unwrap_or(0)
+}
+
+impl AppState {
+    /// The database selected by the connection running the current command.
+    pub fn db(&self) -> &Db {
+        &self.databases[selected_db()]
+    }
+}
+
+pub type Db = Mutex<Keyspace>;
+pub type Subscribers = Mutex<HashMap<Vec<u8>, Vec<oneshot::Sender<Vec<u8>>>>>;
+
+/// The keys and values of the database. It derefs to the underlying dict for
+/// lookups, while changes go through its own methods so it can remember which
+/// keys were given a deadline, for the active expiry cycle to sample them, and
+/// how much memory every entry takes, for `maxmemory`.
+#[derive(Default)]
+pub struct Keyspace {
+    entries: Dict<Vec<u8>, ValueEntry>,
+    // Keys that were given a deadline. This may still hold keys that were
+    // since deleted or persisted; sampling drops them as it finds them.
+    volatile: Vec<Vec<u8>>,
+    volatile_set: HashSet<Vec<u8>>,
+    /// Keys removed because their deadline passed or to free memory, waiting
+    /// to be propagated to replicas as `DEL`.
+    expired: Vec<Vec<u8>>,
+    /// The estimated size of every entry added up, and the keys handed out
+    /// for writing since their size was last estimated.
+    used_memory: usize,
+    written: Vec<Vec<u8>>,
+}
+
+// Set whenever a database queues a removed key, so propagating them only has
+// to go through the databases when there is something to send.
+static EXPIRED_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Whether any database has queued removed keys since the last call.
+pub fn take_expired_pending() -> bool {
+    EXPIRED_PENDING.swap(false, Ordering::AcqRel)
+}
+
+impl Keyspace {
+    pub fn insert(&mut self, key: Vec<u8>, mut entry: ValueEntry) -> Option<ValueEntry> {
+        if entry.expires_at.is_some() {
+            self.track_volatile(&key);
+        }
+        entry.size = entry.memory_usage(&key, MEMORY_USAGE_SAMPLES);
+        self.add_used_memory(entry.size);
+        let old = self.entries.insert(key, entry)?;
+        self.sub_used_memory(old.size);
+        Some(old)
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<ValueEntry> {
+        let entry = self.entries.remove(key)?;
+        self.sub_used_memory(entry.size);
+        Some(entry)
+    }
+
+    /// Looks up a value to change in place. Its size is estimated again by
+    /// the next `update_memory_usage`.
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut ValueEntry> {
+        let entry = self.entries.get_mut(key)?;
+        self.written.push(key.to_vec());
+        Some(entry)
+    }
+
+    /// Like `get_mut`, inserting the value from `default` if the key is
+    /// missing.
+    pub fn get_or_insert_with<F: FnOnce() -> ValueEntry>(
+        &mut self,
+        key: Vec<u8>,
+        default: F,
+    ) -> &mut ValueEntry {
+        self.written.push(key.clone());
+        self.entries.get_or_insert_with(key, default)
+    }
+
+    /// Estimates again the size of the values handed out for writing since
+    /// the last call, now that the command is done with them.
+    pub fn update_memory_usage(&mut self) {
+        for key in std::mem::take(&mut self.written) {
+            let Some(entry) = self.entries.get_mut(&key) else {
+                continue;
+            };
+            let (old, new) = (entry.size, entry.memory_usage(&key, MEMORY_USAGE_SAMPLES));
+            entry.size = new;
+            self.add_used_memory(new);
+            self.sub_used_memory(old);
+        }
+    }
+
+    fn add_used_memory(&mut self, bytes: usize) {
+        self.used_memory += bytes;
+        memory::add_dataset_memory(bytes);
+    }
+
+    fn sub_used_memory(&mut self, bytes: usize) {
+        self.used_memory -= bytes;
+        memory::sub_dataset_memory(bytes);
+    }
+
+    /// Sets or clears the deadline of an existing key.
+    pub fn set_expiry(&mut self, key: &[u8], expires_at: Option<u64>) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.expires_at = expires_at;
+            if expires_at.is_some() {
+                self.track_volatile(key);
+            }
+        }
+    }
+
+    fn track_volatile(&mut self, key: &[u8]) {
+        if self.volatile_set.insert(key.to_vec()) {
+            self.volatile.push(key.to_vec());
+        }
+    }
+
+    /// Queues a removed key to be propagated to replicas as `DEL`.
+    pub fn push_expired(&mut self, key: Vec<u8>) {
+        self.expired.push(key);
+        EXPIRED_PENDING.store(true, Ordering::Release);
+    }
+
+    pub fn take_expired(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.expired)
+    }
+
+    pub fn volatile_len(&self) -> usize {
+        self.volatile.len()
+    }
+
+    /// Bytes taken by the hash table itself: its buckets and the slot of
+    /// every entry, not counting the keys' and values' own allocations.
+    pub fn table_overhead(&self) -> usize {
+        self.entries.overhead()
+    }
+
+    /// Bytes taken by the bookkeeping of keys with a deadline.
+    pub fn expires_overhead(&self) -> usize {
+        let key = std::mem::size_of::<Vec<u8>>();
+        self.volatile.capacity() * key + self.volatile_set.capacity() * (key + 8)
+    }
+
+    /// Returns a random key that has a deadline, dropping keys from the
+    /// volatile set that were since deleted or persisted as it runs into them.
+    pub fn random_volatile_key(&mut self) -> Option<Vec<u8>> {
+        let mut rng = rand::thread_rng();
+        while !self.volatile.is_empty() {
+            let index = rng.gen_range(0..self.volatile.len());
+            let key = &self.volatile[index];
+            if self.entries.get(key).is_some_and(|entry| entry.expires_at.is_some()) {
+                return Some(key.clone());
+            }
+            let key = self.volatile.swap_remove(index);
+            self.volatile_set.remove(&key);
+        }
+        None
+    }
+
+    /// Moves up to `buckets` buckets of a table being resized, so a database
+    /// nobody writes to still finishes resizing.
+    pub fn rehash(&mut self, buckets: usize) {
+        self.entries.rehash(buckets);
+    }
+
+    /// Checks up to `samples` random keys with a deadline and removes the ones
+    /// that have expired. Returns how many keys were removed.
+    pub fn expire_sample(&mut self, samples: usize) -> usize {
+        let mut rng = rand::thread_rng();
+        let mut removed = 0;
+        for _ in 0..samples {
+            if self.volatile.is_empty() {
+                break;
+            }
+            let index = rng.gen_range(0..self.volatile.len());
+            let (volatile, expired) = match self.entries.get(&self.volatile[index]) {
+                Some(entry) => (entry.expires_at.is_some(), entry.is_expired()),
+                None => (false, false),
+            };
+            if volatile && !expired {
+                continue;
+            }
+
+            let key = self.volatile.swap_remove(index);
+            self.volatile_set.remove(&key);
+            if expired {
+                self.remove(&key);
+                self.push_expired(key);
+                removed += 1;
+            }
+        }
+        removed
+    }
+}
+
+impl Deref for Keyspace {
+    type Target = Dict<Vec<u8>, ValueEntry>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
+
+// A database being dropped, on FLUSHDB or FLUSHALL, no longer counts towards
+// the dataset.
+impl Drop for Keyspace {
+    fn drop(&mut self) {
+        memory::sub_dataset_memory(self.used_memory);
+    }
 }
 
-pub type Db = Mutex<HashMap<String, ValueEntry>>;
-pub type BlockedClients = Mutex<HashMap<String, VecDeque<BlockedSender>>>;
+/// Drops `key` from the keyspace if its TTL has passed, so callers can treat
+/// it as missing.
+pub fn remove_if_expired(map: &mut Keyspace, key: &[u8]) {
+    if map.get(key).is_some_and(ValueEntry::is_expired) {
+        map.remove(key);
+        map.push_expired(key.to_vec());
+    }
+}
+
+/// Prepares `key` for a command that reads or writes it: drops the key if its
+/// TTL has passed and records the access for the eviction policies.
+pub fn lookup_key(map: &mut Keyspace, key: &[u8]) {
+    remove_if_expired(map, key);
+    // Not `get_mut`, as a read leaves the size of the value as it was.
+    if let Some(entry) = map.entries.get_mut(key) {
+        entry.touch();
+    }
+}
+
+/// Returns the current wall-clock time as Unix milliseconds.
+pub fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
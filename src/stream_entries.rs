@@ -0,0 +1,495 @@
+// Stream entries packed into nodes, the way Redis keeps a stream as a radix
+// tree of listpacks.
+//
+// Consecutive entries share a node of at most NODE_MAX_ENTRIES entries and
+// about NODE_MAX_BYTES bytes, indexed by the ID of its first entry, the master
+// ID. The field names of that first entry are stored once per node as its
+// master fields, and entries with the same field names only store their
+// values, which is what makes streams of same-shaped entries small. IDs are
+// stored as their difference to the master ID, so most take a byte or two.
+//
+// A node is a single byte buffer, every count, length and ID part being an
+// LEB128 varint:
+//
+//   master fields:  count | (length | name)...
+//   each entry:     flags | ms - master ms | seq | values
+//
+// The seq is the difference to the master seq when the ms are the same. The
+// values are `length | value` for each master field with the SAME_FIELDS flag,
+// and `count | (length | field | length | value)...` without it. Deleting an
+// entry only sets its DELETED flag; a node is dropped once all its entries
+// are deleted.
+
+use std::collections::{btree_map, BTreeMap, HashSet, VecDeque};
+use std::ops::Bound::{self, Excluded, Included, Unbounded};
+use std::ops::RangeBounds;
+
+use crate::storage::{StreamFields, StreamId};
+
+pub const NODE_MAX_ENTRIES: usize = 100;
+const NODE_MAX_BYTES: usize = 4096;
+
+const DELETED: u8 = 1;
+const SAME_FIELDS: u8 = 2;
+
+#[derive(Clone, Default)]
+pub struct StreamEntries {
+    nodes: BTreeMap<StreamId, Node>,
+    len: usize,
+}
+
+#[derive(Clone)]
+struct Node {
+    data: Vec<u8>,
+    /// How many master fields there are, and where the entries start.
+    master_len: usize,
+    entries_start: usize,
+    /// Entries in the node, deleted ones included.
+    count: usize,
+    live: usize,
+}
+
+/// Where an entry sits in its node.
+struct EntryRef {
+    id: StreamId,
+    flags_at: usize,
+    flags: u8,
+    values_at: usize,
+}
+
+impl StreamEntries {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Appends an entry. Its ID must be greater than every ID in the stream.
+    pub fn push(&mut self, id: StreamId, fields: &[(Vec<u8>, Vec<u8>)]) {
+        self.len += 1;
+        if let Some(mut last) = self.nodes.last_entry() {
+            let master_id = *last.key();
+            let node = last.get_mut();
+            if node.count < NODE_MAX_ENTRIES && node.data.len() < NODE_MAX_BYTES {
+                node.append(master_id, id, fields);
+                return;
+            }
+            // The node is full for good, so it can give back its spare room.
+            node.data.shrink_to_fit();
+        }
+        self.nodes.insert(id, Node::new(id, fields));
+    }
+
+    pub fn get(&self, id: &StreamId) -> Option<StreamFields> {
+        let (master_id, node) = self.nodes.range(..=id).next_back()?;
+        node.find(*master_id, id).map(|entry| node.fields(&entry))
+    }
+
+    pub fn contains_key(&self, id: &StreamId) -> bool {
+        self.nodes
+            .range(..=id)
+            .next_back()
+            .is_some_and(|(master_id, node)| node.find(*master_id, id).is_some())
+    }
+
+    /// Deletes an entry, returning whether it was there.
+    pub fn remove(&mut self, id: &StreamId) -> bool {
+        let Some((&master_id, node)) = self.nodes.range_mut(..=id).next_back() else {
+            return false;
+        };
+        let Some(entry) = node.find(master_id, id) else {
+            return false;
+        };
+        node.data[entry.flags_at] |= DELETED;
+        node.live -= 1;
+        self.len -= 1;
+        if node.live == 0 {
+            self.nodes.remove(&master_id);
+        }
+        true
+    }
+
+    /// The entries with IDs within `bounds`, in either order.
+    pub fn range(&self, bounds: (Bound<StreamId>, Bound<StreamId>)) -> Range<'_> {
+        // The first node to look at is the one holding the start of the range.
+        let first_node = match bounds.0 {
+            Included(start) | Excluded(start) => self.nodes.range(..=start).next_back(),
+            Unbounded => None,
+        };
+        let nodes_start = first_node.map_or(Unbounded, |(master_id, _)| Included(*master_id));
+        let nodes = match (nodes_start, bounds.1) {
+            (Included(first), Included(end) | Excluded(end)) if first > end => None,
+            nodes_bounds => Some(self.nodes.range(nodes_bounds)),
+        };
+        Range {
+            nodes,
+            bounds,
+            front: VecDeque::new(),
+            back: VecDeque::new(),
+        }
+    }
+
+    pub fn iter(&self) -> Range<'_> {
+        self.range((Unbounded, Unbounded))
+    }
+
+    pub fn first_id(&self) -> Option<StreamId> {
+        let (master_id, node) = self.nodes.first_key_value()?;
+        node.entries(*master_id)
+            .find(|entry| entry.flags & DELETED == 0)
+            .map(|entry| entry.id)
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// How many nodes a radix tree keyed by the 16-byte big-endian master IDs
+    /// would have, as Redis reports for its own: the root, one inner node for
+    /// each prefix shared by IDs next to each other, and one leaf per key. It
+    /// ignores how rax splits and compresses nodes, so it is close to Redis's
+    /// figure rather than equal to it.
+    pub fn radix_tree_nodes(&self) -> usize {
+        let keys: Vec<[u8; 16]> = self
+            .nodes
+            .keys()
+            .map(|id| {
+                let mut key = [0; 16];
+                key[..8].copy_from_slice(&id.ms.to_be_bytes());
+                key[8..].copy_from_slice(&id.seq.to_be_bytes());
+                key
+            })
+            .collect();
+        let prefixes: HashSet<&[u8]> = keys
+            .windows(2)
+            .map(|pair| {
+                let shared = pair[0].iter().zip(&pair[1]).take_while(|(a, b)| a == b);
+                &pair[0][..shared.count()]
+            })
+            .filter(|prefix| !prefix.is_empty())
+            .collect();
+        1 + prefixes.len() + keys.len()
+    }
+
+    /// How many entries the first node holds, and the greatest ID in it.
+    pub fn first_node(&self) -> Option<(usize, StreamId)> {
+        let (master_id, node) = self.nodes.first_key_value()?;
+        let last_id = node.entries(*master_id).last()?.id;
+        Some((node.live, last_id))
+    }
+
+    /// Drops the first node, returning how many entries went with it.
+    pub fn pop_first_node(&mut self) -> usize {
+        let removed = self.nodes.pop_first().map_or(0, |(_, node)| node.live);
+        self.len -= removed;
+        removed
+    }
+
+    /// The heap bytes taken by each node.
+    pub fn node_sizes(&self) -> impl ExactSizeIterator<Item = usize> + '_ {
+        self.nodes
+            .values()
+            .map(|node| std::mem::size_of::<(StreamId, Node)>() + node.data.capacity())
+    }
+}
+
+impl Node {
+    fn new(master_id: StreamId, fields: &[(Vec<u8>, Vec<u8>)]) -> Node {
+        let mut data = Vec::new();
+        write_varint(&mut data, fields.len() as u64);
+        for (field, _) in fields {
+            write_bytes(&mut data, field);
+        }
+        let mut node = Node {
+            entries_start: data.len(),
+            data,
+            master_len: fields.len(),
+            count: 0,
+            live: 0,
+        };
+        node.append(master_id, master_id, fields);
+        node
+    }
+
+    fn append(&mut self, master_id: StreamId, id: StreamId, fields: &[(Vec<u8>, Vec<u8>)]) {
+        let same_fields = fields.len() == self.master_len
+            && self
+                .master_fields()
+                .zip(fields)
+                .all(|(master, (field, _))| master == field.as_slice());
+
+        let data = &mut self.data;
+        data.push(if same_fields { SAME_FIELDS } else { 0 });
+        write_varint(data, id.ms - master_id.ms);
+        if id.ms == master_id.ms {
+            write_varint(data, id.seq - master_id.seq);
+        } else {
+            write_varint(data, id.seq);
+        }
+        if same_fields {
+            for (_, value) in fields {
+                write_bytes(data, value);
+            }
+        } else {
+            write_varint(data, fields.len() as u64);
+            for (field, value) in fields {
+                write_bytes(data, field);
+                write_bytes(data, value);
+            }
+        }
+        self.count += 1;
+        self.live += 1;
+    }
+
+    fn master_fields(&self) -> impl Iterator<Item = &[u8]> {
+        let mut reader = Reader {
+            data: &self.data,
+            pos: 0,
+        };
+        let count = reader.varint();
+        (0..count).map(move |_| reader.bytes())
+    }
+
+    /// Every entry of the node, deleted ones included.
+    fn entries(&self, master_id: StreamId) -> impl Iterator<Item = EntryRef> + '_ {
+        let mut reader = Reader {
+            data: &self.data,
+            pos: self.entries_start,
+        };
+        std::iter::from_fn(move || {
+            if reader.pos == reader.data.len() {
+                return None;
+            }
+            let flags_at = reader.pos;
+            let flags = reader.data[flags_at];
+            reader.pos += 1;
+            let ms = master_id.ms + reader.varint();
+            let seq = match reader.varint() {
+                delta if ms == master_id.ms => master_id.seq + delta,
+                seq => seq,
+            };
+            let values_at = reader.pos;
+            let values = if flags & SAME_FIELDS != 0 {
+                self.master_len as u64
+            } else {
+                reader.varint() * 2
+            };
+            for _ in 0..values {
+                reader.bytes();
+            }
+            Some(EntryRef {
+                id: StreamId { ms, seq },
+                flags_at,
+                flags,
+                values_at,
+            })
+        })
+    }
+
+    /// The entry with the given ID, unless it's been deleted.
+    fn find(&self, master_id: StreamId, id: &StreamId) -> Option<EntryRef> {
+        self.entries(master_id)
+            .take_while(|entry| entry.id <= *id)
+            .find(|entry| entry.id == *id && entry.flags & DELETED == 0)
+    }
+
+    fn fields(&self, entry: &EntryRef) -> StreamFields {
+        let mut reader = Reader {
+            data: &self.data,
+            pos: entry.values_at,
+        };
+        if entry.flags & SAME_FIELDS != 0 {
+            return self
+                .master_fields()
+                .map(|field| (field.to_vec(), reader.bytes().to_vec()))
+                .collect();
+        }
+        let count = reader.varint();
+        (0..count)
+            .map(|_| (reader.bytes().to_vec(), reader.bytes().to_vec()))
+            .collect()
+    }
+}
+
+pub struct Range<'a> {
+    /// The nodes not decoded yet, or `None` when the range is empty.
+    nodes: Option<btree_map::Range<'a, StreamId, Node>>,
+    bounds: (Bound<StreamId>, Bound<StreamId>),
+    /// Entries decoded from the nodes taken from either end.
+    front: VecDeque<(StreamId, StreamFields)>,
+    back: VecDeque<(StreamId, StreamFields)>,
+}
+
+impl Range<'_> {
+    fn decode(&self, master_id: StreamId, node: &Node) -> VecDeque<(StreamId, StreamFields)> {
+        node.entries(master_id)
+            .filter(|entry| entry.flags & DELETED == 0 && self.bounds.contains(&entry.id))
+            .map(|entry| (entry.id, node.fields(&entry)))
+            .collect()
+    }
+}
+
+impl Iterator for Range<'_> {
+    type Item = (StreamId, StreamFields);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.front.pop_front() {
+                return Some(entry);
+            }
+            match self.nodes.as_mut().and_then(Iterator::next) {
+                Some((master_id, node)) => self.front = self.decode(*master_id, node),
+                None => return self.back.pop_front(),
+            }
+        }
+    }
+}
+
+impl DoubleEndedIterator for Range<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.back.pop_back() {
+                return Some(entry);
+            }
+            match self.nodes.as_mut().and_then(DoubleEndedIterator::next_back) {
+                Some((master_id, node)) => self.back = self.decode(*master_id, node),
+                None => return self.front.pop_back(),
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn varint(&mut self) -> u64 {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.data[self.pos];
+            self.pos += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return value;
+            }
+            shift += 7;
+        }
+    }
+
+    fn bytes(&mut self) -> &'a [u8] {
+        let len = self.varint() as usize;
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        bytes
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(ms: u64) -> StreamId {
+        StreamId { ms, seq: 0 }
+    }
+
+    /// A stream of entries 1-0 to `len`-0, every tenth with other fields.
+    fn entries(len: u64) -> StreamEntries {
+        let mut entries = StreamEntries::default();
+        for ms in 1..=len {
+            let field = if ms % 10 == 0 { "other" } else { "field" };
+            entries.push(id(ms), &[(field.into(), ms.to_string().into())]);
+        }
+        entries
+    }
+
+    fn ids(range: impl Iterator<Item = (StreamId, StreamFields)>) -> Vec<u64> {
+        range.map(|(id, _)| id.ms).collect()
+    }
+
+    #[test]
+    fn entries_are_found_across_nodes() {
+        let entries = entries(250);
+        assert_eq!(entries.len(), 250);
+        assert_eq!(entries.node_count(), 3);
+        assert_eq!(
+            entries.get(&id(100)),
+            Some(vec![(b"other".to_vec(), b"100".to_vec())])
+        );
+        assert_eq!(
+            entries.get(&id(101)),
+            Some(vec![(b"field".to_vec(), b"101".to_vec())])
+        );
+        assert_eq!(entries.get(&StreamId { ms: 100, seq: 1 }), None);
+        assert_eq!(entries.get(&id(251)), None);
+
+        let range = entries.range((Included(id(98)), Excluded(id(103))));
+        assert_eq!(ids(range), [98, 99, 100, 101, 102]);
+        let range = entries.range((Excluded(id(198)), Included(id(202))));
+        assert_eq!(ids(range.rev()), [202, 201, 200, 199]);
+        let range = entries.range((Included(id(300)), Unbounded));
+        assert_eq!(ids(range), []);
+        let range = entries.range((Included(id(5)), Included(id(4))));
+        assert_eq!(ids(range), []);
+    }
+
+    #[test]
+    fn removing_every_entry_of_a_node_drops_it() {
+        let mut entries = entries(250);
+        for ms in 101..=200 {
+            assert!(entries.remove(&id(ms)));
+        }
+        assert!(!entries.remove(&id(150)));
+        assert!(!entries.contains_key(&id(150)));
+        assert_eq!(entries.len(), 150);
+        assert_eq!(entries.node_count(), 2);
+        let range = entries.range((Included(id(99)), Included(id(201))));
+        assert_eq!(ids(range), [99, 100, 201]);
+        let range = entries.range((Included(id(120)), Included(id(180))));
+        assert_eq!(ids(range), []);
+
+        // Deleted entries at the start of a node are skipped over.
+        entries.remove(&id(1));
+        entries.remove(&id(2));
+        assert_eq!(entries.first_id(), Some(id(3)));
+        assert_eq!(entries.first_node(), Some((98, id(100))));
+        assert_eq!(entries.pop_first_node(), 98);
+        assert_eq!(entries.first_id(), Some(id(201)));
+        assert_eq!(entries.len(), 50);
+    }
+
+    #[test]
+    fn both_ends_of_a_range_meet_without_repeats() {
+        let mut entries = entries(250);
+        entries.remove(&id(125));
+        let mut range = entries.range((Included(id(50)), Included(id(210))));
+        let (mut front, mut back) = (Vec::new(), Vec::new());
+        while let Some((id, _)) = range.next() {
+            front.push(id.ms);
+            // Two from the back for every one from the front, so the ends
+            // meet in the middle of the second node, both decoded at once.
+            for _ in 0..2 {
+                if let Some((id, _)) = range.next_back() {
+                    back.push(id.ms);
+                }
+            }
+        }
+        back.reverse();
+        front.extend(back);
+        let expected: Vec<u64> = (50..=210).filter(|ms| *ms != 125).collect();
+        assert_eq!(front, expected);
+    }
+}
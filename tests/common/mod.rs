@@ -0,0 +1,186 @@
+// A server started from the built binary on a free loopback port, and a
+// client speaking RESP to it. Shared by the integration tests and, through
+// `#[path]`, the benchmarks, none of which use all of it.
+#![allow(dead_code)]
+
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+pub struct Server {
+    process: Child,
+    pub port: u16,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+pub fn start_server() -> Server {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("no free port")
+        .port();
+    let process = Command::new(env!("CARGO_BIN_EXE_codecrafters-redis"))
+        .args(["--port", &port.to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("cannot start server");
+    Server { process, port }
+}
+
+pub struct Client {
+    pub stream: TcpStream,
+    pub reader: BufReader<TcpStream>,
+}
+
+impl Client {
+    /// Connects to `server`, waiting for it to start listening.
+    pub fn connect(server: &Server) -> Client {
+        for _ in 0..100 {
+            if let Ok(stream) = TcpStream::connect(("127.0.0.1", server.port)) {
+                stream.set_nodelay(true).unwrap();
+                let reader = BufReader::new(stream.try_clone().unwrap());
+                return Client { stream, reader };
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("server did not start on port {}", server.port);
+    }
+
+    /// Sends a command without waiting for its reply.
+    pub fn send(&mut self, args: &[&[u8]]) {
+        let mut command = Vec::new();
+        encode(&mut command, args);
+        self.stream.write_all(&command).unwrap();
+    }
+
+    /// Reads one reply: the line of a simple string, error or integer, the
+    /// contents of a bulk string, or the header of an array once its elements
+    /// have been read past.
+    pub fn read_reply(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).expect("connection closed");
+        let line = line.trim_end().to_string();
+        if let Some(len) = line.strip_prefix('$') {
+            let len: i64 = len.parse().unwrap();
+            if len >= 0 {
+                let mut bulk = vec![0; len as usize + 2];
+                self.reader.read_exact(&mut bulk).unwrap();
+                return String::from_utf8_lossy(&bulk[..len as usize]).into_owned();
+            }
+        }
+        if let Some(len) = line.strip_prefix('*') {
+            for _ in 0..len.parse::<i64>().unwrap().max(0) {
+                self.read_reply();
+            }
+        }
+        line
+    }
+
+    /// Sends a command and returns its reply.
+    pub fn command(&mut self, args: &[&str]) -> String {
+        let args: Vec<&[u8]> = args.iter().map(|arg| arg.as_bytes()).collect();
+        self.send(&args);
+        self.read_reply()
+    }
+
+    /// Sends a command and returns its whole reply, nested arrays included.
+    pub fn query(&mut self, args: &[&str]) -> Reply {
+        let args: Vec<&[u8]> = args.iter().map(|arg| arg.as_bytes()).collect();
+        self.send(&args);
+        self.read_value()
+    }
+
+    /// Reads one reply, nested arrays included.
+    pub fn read_value(&mut self) -> Reply {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).expect("connection closed");
+        let (kind, rest) = line.trim_end().split_at(1);
+        match kind {
+            "+" => Reply::Status(rest.to_string()),
+            "-" => Reply::Error(rest.to_string()),
+            ":" => Reply::Integer(rest.parse().unwrap()),
+            "$" | "*" if rest.starts_with('-') => Reply::Nil,
+            "$" => {
+                let len: usize = rest.parse().unwrap();
+                let mut bulk = vec![0; len + 2];
+                self.reader.read_exact(&mut bulk).unwrap();
+                Reply::Bulk(String::from_utf8_lossy(&bulk[..len]).into_owned())
+            }
+            "*" => Reply::Array(
+                (0..rest.parse().unwrap())
+                    .map(|_| self.read_value())
+                    .collect(),
+            ),
+            _ => panic!("unexpected reply {:?}", line),
+        }
+    }
+}
+
+/// A reply, which prints compactly for comparisons: bulk strings quoted,
+/// arrays in brackets.
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    Status(String),
+    Error(String),
+    Integer(i64),
+    Bulk(String),
+    Nil,
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    pub fn integer(&self) -> i64 {
+        match self {
+            Reply::Integer(value) => *value,
+            _ => panic!("expected an integer, got {}", self),
+        }
+    }
+
+    pub fn items(&self) -> &[Reply] {
+        match self {
+            Reply::Array(items) => items,
+            _ => panic!("expected an array, got {}", self),
+        }
+    }
+}
+
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reply::Status(status) => write!(f, "{}", status),
+            Reply::Error(err) => write!(f, "-{}", err),
+            Reply::Integer(value) => write!(f, "{}", value),
+            Reply::Bulk(bulk) => write!(f, "{:?}", bulk),
+            Reply::Nil => write!(f, "nil"),
+            Reply::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+/// Appends a command to `out` as a RESP array of bulk strings.
+pub fn encode(out: &mut Vec<u8>, args: &[&[u8]]) {
+    out.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+    for arg in args {
+        out.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        out.extend_from_slice(arg);
+        out.extend_from_slice(b"\r\n");
+    }
+}
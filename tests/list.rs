@@ -0,0 +1,90 @@
+// Blocking list commands against a server started from the built binary.
+
+mod common;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{start_server, Client, Server};
+
+/// Sends a blocking command from a new client, and gives the server time to
+/// block it before returning the client.
+fn block(server: &Server, args: &[&str]) -> Client {
+    let mut client = Client::connect(server);
+    let args: Vec<&[u8]> = args.iter().map(|arg| arg.as_bytes()).collect();
+    client.send(&args);
+    thread::sleep(Duration::from_millis(100));
+    client
+}
+
+#[test]
+fn pushed_elements_go_to_blocked_clients_in_order() {
+    let server = start_server();
+    let mut client = Client::connect(&server);
+    let mut first = block(&server, &["BLPOP", "list", "0"]);
+    let mut second = block(&server, &["BRPOP", "other", "list", "0"]);
+
+    assert_eq!(client.command(&["RPUSH", "list", "a", "b", "c"]), ":3");
+    assert_eq!(first.read_value().to_string(), r#"["list", "a"]"#);
+    assert_eq!(second.read_value().to_string(), r#"["list", "c"]"#);
+    assert_eq!(
+        client.query(&["LRANGE", "list", "0", "-1"]).to_string(),
+        r#"["b"]"#
+    );
+}
+
+#[test]
+fn blocked_moves_take_the_pushed_element() {
+    let server = start_server();
+    let mut client = Client::connect(&server);
+    let mut mover = block(
+        &server,
+        &["BLMOVE", "source", "target", "LEFT", "RIGHT", "0"],
+    );
+    let mut popper = block(&server, &["BLMPOP", "0", "2", "source", "other", "RIGHT"]);
+
+    assert_eq!(client.command(&["LPUSH", "source", "x", "y"]), ":2");
+    assert_eq!(mover.read_value().to_string(), r#""y""#);
+    assert_eq!(popper.read_value().to_string(), r#"["source", ["x"]]"#);
+    assert_eq!(client.command(&["EXISTS", "source"]), ":0");
+    assert_eq!(
+        client.query(&["LRANGE", "target", "0", "-1"]).to_string(),
+        r#"["y"]"#
+    );
+}
+
+#[test]
+fn timed_out_clients_leave_later_pushes_alone() {
+    let server = start_server();
+    let mut client = Client::connect(&server);
+    let mut blocked = Client::connect(&server);
+
+    let started = Instant::now();
+    assert_eq!(blocked.query(&["BLPOP", "list", "0.1"]).to_string(), "nil");
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    client.command(&["RPUSH", "list", "a"]);
+    assert_eq!(client.command(&["LLEN", "list"]), ":1");
+}
+
+#[test]
+fn a_push_racing_a_timeout_is_never_lost() {
+    let server = start_server();
+    let mut client = Client::connect(&server);
+    let mut blocked = Client::connect(&server);
+
+    // Push around the moment the timeout fires: the element either reaches
+    // the blocked client or stays in the list, never both or neither.
+    for attempt in 0..40 {
+        blocked.send(&[b"BLPOP", b"list", b"0.05"]);
+        thread::sleep(Duration::from_millis(40 + attempt / 2));
+        client.command(&["RPUSH", "list", "a"]);
+        let reply = blocked.read_value().to_string();
+        let len = client.command(&["LLEN", "list"]);
+        match reply.as_str() {
+            r#"["list", "a"]"# => assert_eq!(len, ":0"),
+            "nil" => assert_eq!(len, ":1"),
+            _ => panic!("unexpected reply {}", reply),
+        }
+        client.command(&["DEL", "list"]);
+    }
+}
@@ -0,0 +1,116 @@
+// MIGRATE between two servers started from the built binary, both on
+// loopback.
+
+mod common;
+
+use common::{start_server, Client, Server};
+
+/// MIGRATE from `client` to `target`, with the remaining arguments after the
+/// key as given.
+fn migrate(client: &mut Client, target: &Server, key: &str, rest: &[&str]) -> String {
+    let port = target.port.to_string();
+    let mut args = vec!["MIGRATE", "127.0.0.1", &port, key];
+    args.extend_from_slice(rest);
+    client.command(&args)
+}
+
+#[test]
+fn migrate_moves_the_key_with_its_ttl() {
+    let (source, target) = (start_server(), start_server());
+    let (mut a, mut b) = (Client::connect(&source), Client::connect(&target));
+
+    assert_eq!(a.command(&["SET", "key", "value", "EX", "100"]), "+OK");
+    assert_eq!(migrate(&mut a, &target, "key", &["0", "1000"]), "+OK");
+    assert_eq!(a.command(&["EXISTS", "key"]), ":0");
+    assert_eq!(b.command(&["GET", "key"]), "value");
+    let ttl: i64 = b.command(&["TTL", "key"])[1..].parse().unwrap();
+    assert!(ttl > 90 && ttl <= 100, "ttl {}", ttl);
+
+    // The cached connection is reused for the next call, into another
+    // database this time.
+    a.command(&["RPUSH", "list", "x", "y"]);
+    assert_eq!(migrate(&mut a, &target, "list", &["3", "1000"]), "+OK");
+    assert_eq!(b.command(&["EXISTS", "list"]), ":0");
+    b.command(&["SELECT", "3"]);
+    assert_eq!(b.command(&["LLEN", "list"]), ":2");
+}
+
+#[test]
+fn migrate_copy_keeps_the_source_key() {
+    let (source, target) = (start_server(), start_server());
+    let (mut a, mut b) = (Client::connect(&source), Client::connect(&target));
+
+    a.command(&["SET", "key", "value"]);
+    assert_eq!(
+        migrate(&mut a, &target, "key", &["0", "1000", "COPY"]),
+        "+OK"
+    );
+    assert_eq!(a.command(&["GET", "key"]), "value");
+    assert_eq!(b.command(&["GET", "key"]), "value");
+}
+
+#[test]
+fn migrate_only_overwrites_with_replace() {
+    let (source, target) = (start_server(), start_server());
+    let (mut a, mut b) = (Client::connect(&source), Client::connect(&target));
+
+    a.command(&["SET", "key", "new"]);
+    b.command(&["SET", "key", "old"]);
+    let reply = migrate(&mut a, &target, "key", &["0", "1000"]);
+    assert!(
+        reply.starts_with("-ERR Target instance replied with error: BUSYKEY"),
+        "{}",
+        reply
+    );
+    // Nothing was moved, so the key stays on the source.
+    assert_eq!(a.command(&["GET", "key"]), "new");
+    assert_eq!(b.command(&["GET", "key"]), "old");
+
+    assert_eq!(
+        migrate(&mut a, &target, "key", &["0", "1000", "REPLACE"]),
+        "+OK"
+    );
+    assert_eq!(a.command(&["EXISTS", "key"]), ":0");
+    assert_eq!(b.command(&["GET", "key"]), "new");
+}
+
+#[test]
+fn migrate_keys_moves_every_existing_key() {
+    let (source, target) = (start_server(), start_server());
+    let (mut a, mut b) = (Client::connect(&source), Client::connect(&target));
+
+    a.command(&["SET", "a", "1"]);
+    a.command(&["SET", "b", "2"]);
+    let reply = migrate(
+        &mut a,
+        &target,
+        "",
+        &["0", "1000", "KEYS", "a", "missing", "b"],
+    );
+    assert_eq!(reply, "+OK");
+    assert_eq!(a.command(&["EXISTS", "a", "b"]), ":0");
+    assert_eq!(b.command(&["GET", "a"]), "1");
+    assert_eq!(b.command(&["GET", "b"]), "2");
+    assert_eq!(b.command(&["EXISTS", "missing"]), ":0");
+
+    // The key argument must be empty with KEYS.
+    let reply = migrate(&mut a, &target, "a", &["0", "1000", "KEYS", "b"]);
+    assert!(
+        reply.starts_with("-ERR When using MIGRATE KEYS option"),
+        "{}",
+        reply
+    );
+}
+
+#[test]
+fn migrate_without_keys_replies_nokey() {
+    let (source, target) = (start_server(), start_server());
+    let mut a = Client::connect(&source);
+
+    assert_eq!(
+        migrate(&mut a, &target, "missing", &["0", "1000"]),
+        "+NOKEY"
+    );
+    let reply = migrate(&mut a, &target, "", &["0", "1000", "KEYS", "x", "y"]);
+    assert_eq!(reply, "+NOKEY");
+}
@@ -0,0 +1,126 @@
+// Consumer groups read and claimed against a server started from the built
+// binary.
+
+mod common;
+
+use common::{start_server, Client};
+
+/// A stream `s` with entries 1-0 to 3-0 and a group `g` whose consumer `c`
+/// has read all of them.
+fn delivered_stream(client: &mut Client) {
+    for id in ["1-0", "2-0", "3-0"] {
+        client.command(&["XADD", "s", id, "f", id]);
+    }
+    assert_eq!(client.command(&["XGROUP", "CREATE", "s", "g", "0"]), "+OK");
+    client.query(&["XREADGROUP", "GROUP", "g", "c", "STREAMS", "s", ">"]);
+}
+
+/// The pending entries of group `g` as `id consumer delivery-count`.
+fn pending(client: &mut Client) -> Vec<String> {
+    let reply = client.query(&["XPENDING", "s", "g", "-", "+", "10"]);
+    reply
+        .items()
+        .iter()
+        .map(|entry| {
+            let fields = entry.items();
+            format!("{} {} {}", fields[0], fields[1], fields[3])
+        })
+        .collect()
+}
+
+#[test]
+fn history_reads_return_deleted_entries_as_nil() {
+    let server = start_server();
+    let mut client = Client::connect(&server);
+    delivered_stream(&mut client);
+
+    assert_eq!(client.command(&["XDEL", "s", "2-0"]), ":1");
+    let reply = client.query(&["XREADGROUP", "GROUP", "g", "c", "STREAMS", "s", "0"]);
+    assert_eq!(
+        reply.to_string(),
+        r#"[["s", [["1-0", ["f", "1-0"]], ["2-0", nil], ["3-0", ["f", "3-0"]]]]]"#
+    );
+    // The deleted entry stays pending until acknowledged, and reading the
+    // history counts as another delivery.
+    assert_eq!(
+        pending(&mut client),
+        [r#""1-0" "c" 2"#, r#""2-0" "c" 2"#, r#""3-0" "c" 2"#]
+    );
+    assert_eq!(client.command(&["XACK", "s", "g", "2-0"]), ":1");
+    let reply = client.query(&["XREADGROUP", "GROUP", "g", "c", "STREAMS", "s", "1-0"]);
+    assert_eq!(reply.to_string(), r#"[["s", [["3-0", ["f", "3-0"]]]]]"#);
+
+    // Another consumer has no history, and nothing new is left.
+    let reply = client.query(&["XREADGROUP", "GROUP", "g", "d", "STREAMS", "s", "0"]);
+    assert_eq!(reply.to_string(), r#"[["s", []]]"#);
+    let reply = client.query(&["XREADGROUP", "GROUP", "g", "c", "STREAMS", "s", ">"]);
+    assert_eq!(reply.to_string(), "nil");
+}
+
+#[test]
+fn xclaim_counts_deliveries_unless_justid() {
+    let server = start_server();
+    let mut client = Client::connect(&server);
+    delivered_stream(&mut client);
+
+    let reply = client.query(&["XCLAIM", "s", "g", "d", "0", "1-0"]);
+    assert_eq!(reply.to_string(), r#"[["1-0", ["f", "1-0"]]]"#);
+    let reply = client.query(&["XCLAIM", "s", "g", "d", "0", "2-0", "JUSTID"]);
+    assert_eq!(reply.to_string(), r#"["2-0"]"#);
+    let reply = client.query(&["XCLAIM", "s", "g", "d", "0", "3-0", "RETRYCOUNT", "7"]);
+    assert_eq!(reply.to_string(), r#"[["3-0", ["f", "3-0"]]]"#);
+    assert_eq!(
+        pending(&mut client),
+        [r#""1-0" "d" 2"#, r#""2-0" "d" 1"#, r#""3-0" "d" 7"#]
+    );
+
+    // Entries idle for less than the minimum aren't claimed.
+    let reply = client.query(&["XCLAIM", "s", "g", "c", "60000", "1-0"]);
+    assert_eq!(reply.to_string(), "[]");
+
+    // FORCE claims an entry nobody was given yet as its first delivery.
+    client.command(&["XADD", "s", "4-0", "f", "4-0"]);
+    let reply = client.query(&["XCLAIM", "s", "g", "c", "0", "4-0", "FORCE", "JUSTID"]);
+    assert_eq!(reply.to_string(), r#"["4-0"]"#);
+    client.command(&["XADD", "s", "5-0", "f", "5-0"]);
+    client.query(&["XCLAIM", "s", "g", "c", "0", "5-0", "FORCE"]);
+    let pending = pending(&mut client);
+    assert_eq!(pending[3..], [r#""4-0" "c" 0"#, r#""5-0" "c" 1"#]);
+
+    // A deleted entry is dropped from the pending list instead.
+    client.command(&["XDEL", "s", "1-0"]);
+    let reply = client.query(&["XCLAIM", "s", "g", "c", "0", "1-0"]);
+    assert_eq!(reply.to_string(), "[]");
+    assert_eq!(
+        client.query(&["XPENDING", "s", "g"]).items()[0].integer(),
+        4
+    );
+}
+
+#[test]
+fn xautoclaim_counts_deliveries_unless_justid() {
+    let server = start_server();
+    let mut client = Client::connect(&server);
+    delivered_stream(&mut client);
+
+    let reply = client.query(&["XAUTOCLAIM", "s", "g", "d", "0", "0", "COUNT", "1"]);
+    assert_eq!(reply.to_string(), r#"["2-0", [["1-0", ["f", "1-0"]]], []]"#);
+    let reply = client.query(&["XAUTOCLAIM", "s", "g", "d", "0", "2-0", "JUSTID"]);
+    assert_eq!(reply.to_string(), r#"["0-0", ["2-0", "3-0"], []]"#);
+    assert_eq!(
+        pending(&mut client),
+        [r#""1-0" "d" 2"#, r#""2-0" "d" 1"#, r#""3-0" "d" 1"#]
+    );
+
+    // Deleted entries are reported and dropped, and a start past the last
+    // pending entry ends the scan.
+    client.command(&["XDEL", "s", "2-0"]);
+    let reply = client.query(&["XAUTOCLAIM", "s", "g", "c", "0", "0"]);
+    assert_eq!(
+        reply.to_string(),
+        r#"["0-0", [["1-0", ["f", "1-0"]], ["3-0", ["f", "3-0"]]], ["2-0"]]"#
+    );
+    assert_eq!(pending(&mut client), [r#""1-0" "c" 3"#, r#""3-0" "c" 2"#]);
+    let reply = client.query(&["XAUTOCLAIM", "s", "g", "c", "0", "4-0"]);
+    assert_eq!(reply.to_string(), r#"["0-0", [], []]"#);
+}